@@ -0,0 +1,151 @@
+use crate::config::SharedConfig;
+use crate::connection::Connection;
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::StreamExt;
+use lru::LruCache;
+use once_cell::sync::Lazy;
+use showdown::{RoomId, SendMessage};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+/// Shortest delay between two notices about the same problem.
+const NOTICE_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Number of notice keys remembered for rate limiting, as keys can be
+/// chosen by whoever sends deliveries.
+const NOTICE_KEYS: usize = 200;
+
+/// Notices for `PSDEVBOT_ADMIN_ROOM`, shared so that any part of the bot
+/// can report problems.
+pub static NOTICES: Lazy<Notices> = Lazy::new(Notices::default);
+
+/// Plain text notices about problems the bot runs into. Notices with
+/// the same key are sent at most once per `NOTICE_INTERVAL`, so that a
+/// misbehaving webhook can't flood the admin room.
+pub struct Notices {
+    last_sent: Mutex<LruCache<String, Instant>>,
+    queue: Mutex<Option<UnboundedSender<String>>>,
+}
+
+impl Default for Notices {
+    fn default() -> Self {
+        Self {
+            last_sent: Mutex::new(LruCache::new(NOTICE_KEYS)),
+            queue: Mutex::new(None),
+        }
+    }
+}
+
+impl Notices {
+    /// Queues a notice, unless one with the same key was queued recently
+    /// or nothing receives notices. Notices must not contain secrets or
+    /// payloads.
+    pub fn notify(&self, key: &str, notice: impl Into<String>) {
+        let now = Instant::now();
+        {
+            let mut last_sent = self.last_sent.lock().unwrap();
+            match last_sent.get(&key.to_owned()) {
+                Some(sent) if now.duration_since(*sent) < NOTICE_INTERVAL => return,
+                _ => {
+                    last_sent.put(key.into(), now);
+                }
+            }
+        }
+        if let Some(queue) = &*self.queue.lock().unwrap() {
+            queue.unbounded_send(notice.into()).ok();
+        }
+    }
+
+    /// Starts receiving queued notices, replacing the earlier receiver.
+    pub fn receive(&self) -> UnboundedReceiver<String> {
+        let (tx, rx) = mpsc::unbounded();
+        *self.queue.lock().unwrap() = Some(tx);
+        rx
+    }
+}
+
+/// Sends notices to the admin room while it's configured. Notices which
+/// can't be sent are only logged, so that problems with the admin room
+/// don't cause more notices.
+pub async fn send_notices(
+    config: &SharedConfig,
+    connection: &Connection,
+    mut notices: UnboundedReceiver<String>,
+) {
+    while let Some(notice) = notices.next().await {
+        let config = config.get();
+        let room = match &config.admin_room {
+            Some(room) => room,
+            None => continue,
+        };
+        if config.dry_run {
+            info!("Would send admin notice to {}: {}", room, notice);
+            continue;
+        }
+        let sender = match connection.sender() {
+            Some(sender) => sender,
+            None => {
+                warn!("Not connected, dropping admin notice: {}", notice);
+                continue;
+            }
+        };
+        let message = SendMessage::chat_message(RoomId(room), &notice);
+        if sender.send_to_room(room, message).await.is_err() {
+            warn!("Cannot send admin notice: {}", notice);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Notices, NOTICE_INTERVAL, NOTICE_KEYS};
+    use std::time::Duration;
+    use tokio::time;
+
+    #[tokio::test]
+    async fn test_repeated_notices_are_rate_limited() {
+        time::pause();
+        let notices = Notices::default();
+        let mut rx = notices.receive();
+        notices.notify("signature smogon/pokemon-showdown", "first");
+        notices.notify("signature smogon/pokemon-showdown", "repeated");
+        notices.notify("signature smogon/sprites", "other repository");
+        assert_eq!(rx.try_next().unwrap().as_deref(), Some("first"));
+        assert_eq!(rx.try_next().unwrap().as_deref(), Some("other repository"));
+        assert!(rx.try_next().is_err());
+        time::advance(NOTICE_INTERVAL - Duration::from_secs(1)).await;
+        notices.notify("signature smogon/pokemon-showdown", "still repeated");
+        assert!(rx.try_next().is_err());
+        time::advance(Duration::from_secs(1)).await;
+        notices.notify("signature smogon/pokemon-showdown", "again");
+        assert_eq!(rx.try_next().unwrap().as_deref(), Some("again"));
+    }
+
+    #[tokio::test]
+    async fn test_notice_keys_are_capped() {
+        time::pause();
+        let notices = Notices::default();
+        let mut rx = notices.receive();
+        notices.notify("first", "first");
+        for key in 0..NOTICE_KEYS {
+            notices.notify(&key.to_string(), "other");
+        }
+        assert_eq!(notices.last_sent.lock().unwrap().len(), NOTICE_KEYS);
+        notices.notify("first", "forgotten");
+        let sent: Vec<_> = std::iter::from_fn(|| rx.try_next().ok().flatten()).collect();
+        assert_eq!(sent.len(), NOTICE_KEYS + 2);
+        assert_eq!(sent.last().map(String::as_str), Some("forgotten"));
+    }
+
+    #[test]
+    fn test_notices_without_receiver_are_dropped() {
+        let notices = Notices::default();
+        notices.notify("reconnect", "dropped");
+        let mut rx = notices.receive();
+        notices.notify("panic", "sent");
+        assert_eq!(rx.try_next().unwrap().as_deref(), Some("sent"));
+        assert!(rx.try_next().is_err());
+    }
+}
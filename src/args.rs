@@ -0,0 +1,128 @@
+use clap::Parser;
+
+const ENVIRONMENT_HELP: &str = "\
+ENVIRONMENT:
+    PSDEVBOT_CONFIG                  TOML configuration file (--config)
+    PSDEVBOT_SERVER                  Showdown websocket URL (--server)
+    PSDEVBOT_USER                    Showdown user name (--user)
+    PSDEVBOT_PASSWORD                Showdown password
+    PSDEVBOT_SECRET                  Default GitHub webhook secrets, separated by commas or as a JSON list
+    PSDEVBOT_PORT                    Webhook server port, 3030 by default (--port)
+    PSDEVBOT_BIND                    Address the webhook server listens on, 0.0.0.0 by default
+    PSDEVBOT_WEBHOOK_PATH            Path receiving GitHub and GitLab deliveries, /github/callback by default
+    PSDEVBOT_ALLOWED_CIDRS           Comma separated address blocks webhooks are accepted from
+    PSDEVBOT_TLS_CERT                PEM certificate chain, to serve webhooks over HTTPS
+    PSDEVBOT_TLS_KEY                 PEM private key for PSDEVBOT_TLS_CERT
+    PSDEVBOT_RECONNECT_MAX_SECS      Longest delay between reconnection attempts, 300 by default
+    PSDEVBOT_SEND_INTERVAL_MS        Delay between messages sent to Showdown, 700 by default
+    PSDEVBOT_DRAIN_TIMEOUT_SECS      Longest wait for queued messages on SIGTERM, 10 by default
+    PSDEVBOT_BATCH_WINDOW_SECONDS    Seconds pushes to a branch are collected into one announcement
+    PSDEVBOT_ROOM                    Room used for projects without configuration (--room)
+    PSDEVBOT_ADMIN_ROOM              Room receiving notices about errors, such as failed signatures
+    PSDEVBOT_IGNORED_BRANCHES        Comma separated branch patterns never announced
+    PSDEVBOT_DISABLED_EVENTS         Comma separated GitHub events never announced
+    PSDEVBOT_SHA_LENGTH              Length of commit ids in announcements, 7 by default
+    PSDEVBOT_ANNOUNCED_LABELS        Comma separated pull request labels announced when changed
+    PSDEVBOT_DRY_RUN                 Set to 1 to log announcements instead of sending them (--dry-run)
+    PSDEVBOT_STATE_PATH              JSON file keeping deliveries, ~alias changes and star milestones
+    PSDEVBOT_PROJECT_CONFIGURATION   JSON object mapping repositories to their rooms
+    PSDEVBOT_PROJECT_CONFIGURATION_FILE
+                                     File containing PSDEVBOT_PROJECT_CONFIGURATION
+    PSDEVBOT_USERNAME_ALIASES        JSON object mapping GitHub logins to display names
+    PSDEVBOT_USERNAME_ALIASES_FILE   File containing PSDEVBOT_USERNAME_ALIASES, may use TOML
+    PSDEVBOT_MESSAGE_TEMPLATES       JSON object mapping event types to message templates
+    PSDEVBOT_MESSAGE_TEMPLATES_FILE  File containing PSDEVBOT_MESSAGE_TEMPLATES, may use TOML
+    PSDEVBOT_GITHUB_API_USER         GitHub API user
+    PSDEVBOT_GITHUB_API_PASSWORD     GitHub API password
+    PSDEVBOT_GITHUB_API_TOKEN        GitHub API token, used instead of the user and password
+    PSDEVBOT_GITHUB_API_CACHE_SIZE   Number of cached GitHub API responses, at least 1, 100 by default
+
+PSDEVBOT_PASSWORD, PSDEVBOT_SECRET, PSDEVBOT_GITHUB_API_PASSWORD and
+PSDEVBOT_GITHUB_API_TOKEN can instead be read from the file named by the
+same variable with a _FILE suffix.";
+
+/// Bot used to report GitHub changes in Pokémon Showdown chatrooms.
+///
+/// Options override the environment variable shown next to them.
+#[derive(Debug, Default, Parser)]
+#[command(version, after_help = ENVIRONMENT_HELP)]
+pub struct Args {
+    /// Validate the configuration and exit without connecting anywhere
+    #[arg(long)]
+    pub check: bool,
+    /// Print the configuration with secrets redacted and exit
+    #[arg(long, conflicts_with = "check")]
+    pub print_config: bool,
+    /// TOML configuration file [env: PSDEVBOT_CONFIG]
+    #[arg(long, value_name = "PATH")]
+    config: Option<String>,
+    /// Showdown websocket URL [env: PSDEVBOT_SERVER]
+    #[arg(long, value_name = "URL")]
+    server: Option<String>,
+    /// Showdown user name [env: PSDEVBOT_USER]
+    #[arg(long)]
+    user: Option<String>,
+    /// Webhook server port [env: PSDEVBOT_PORT] [default: 3030]
+    #[arg(long)]
+    port: Option<u16>,
+    /// Room used for projects without configuration [env: PSDEVBOT_ROOM]
+    #[arg(long)]
+    room: Option<String>,
+    /// Log announcements instead of sending them [env: PSDEVBOT_DRY_RUN]
+    #[arg(long)]
+    dry_run: bool,
+}
+
+impl Args {
+    /// Returns the value given on the command line for a setting
+    /// otherwise read from the given environment variable.
+    pub fn get(&self, variable: &str) -> Option<String> {
+        match variable {
+            "PSDEVBOT_CONFIG" => self.config.clone(),
+            "PSDEVBOT_SERVER" => self.server.clone(),
+            "PSDEVBOT_USER" => self.user.clone(),
+            "PSDEVBOT_PORT" => self.port.map(|port| port.to_string()),
+            "PSDEVBOT_ROOM" => self.room.clone(),
+            "PSDEVBOT_DRY_RUN" if self.dry_run => Some("1".into()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Args;
+    use clap::{CommandFactory, Parser};
+
+    #[test]
+    fn test_args_definition() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn test_get() {
+        let args = Args::parse_from(["psdevbot", "--port", "8080", "--room", "dev"]);
+        assert_eq!(args.get("PSDEVBOT_PORT").as_deref(), Some("8080"));
+        assert_eq!(args.get("PSDEVBOT_ROOM").as_deref(), Some("dev"));
+        assert_eq!(args.get("PSDEVBOT_USER"), None);
+        assert_eq!(args.get("PSDEVBOT_PASSWORD"), None);
+        assert_eq!(args.get("PSDEVBOT_DRY_RUN"), None);
+        let args = Args::parse_from(["psdevbot", "--dry-run"]);
+        assert_eq!(args.get("PSDEVBOT_DRY_RUN").as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn test_help_mentions_environment_variables() {
+        let help = Args::command().render_help().to_string();
+        for variable in [
+            "PSDEVBOT_CONFIG",
+            "PSDEVBOT_SERVER",
+            "PSDEVBOT_USER",
+            "PSDEVBOT_PORT",
+            "PSDEVBOT_ROOM",
+            "PSDEVBOT_DRY_RUN",
+        ] {
+            assert!(help.contains(&format!("[env: {}]", variable)), "{}", help);
+        }
+    }
+}
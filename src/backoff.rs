@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+const INITIAL_DELAY: Duration = Duration::from_secs(1);
+
+/// Delays between reconnection attempts, doubling after every failed
+/// attempt up to a limit.
+#[derive(Debug)]
+pub struct Backoff {
+    next: Duration,
+}
+
+impl Backoff {
+    pub fn new() -> Self {
+        Self {
+            next: INITIAL_DELAY,
+        }
+    }
+
+    /// Returns how long to wait before the next attempt, never more
+    /// than `max`.
+    pub fn next_delay(&mut self, max: Duration) -> Duration {
+        let delay = self.next.min(max);
+        self.next = delay.saturating_mul(2).max(INITIAL_DELAY);
+        delay
+    }
+
+    /// Starts over with a short delay, used after connecting successfully.
+    pub fn reset(&mut self) {
+        self.next = INITIAL_DELAY;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Backoff;
+    use std::time::Duration;
+
+    #[test]
+    fn test_backoff() {
+        let max = Duration::from_secs(5);
+        let mut backoff = Backoff::new();
+        let delays: Vec<_> = (0..5).map(|_| backoff.next_delay(max).as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 5, 5]);
+        backoff.reset();
+        assert_eq!(backoff.next_delay(max), Duration::from_secs(1));
+    }
+}
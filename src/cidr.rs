@@ -0,0 +1,119 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// A block of IP addresses written like `192.30.252.0/22`. An address
+/// without a prefix length only matches itself.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+#[derive(Debug)]
+pub struct InvalidCidr;
+
+impl FromStr for Cidr {
+    type Err = InvalidCidr;
+
+    fn from_str(cidr: &str) -> Result<Self, InvalidCidr> {
+        let (address, prefix) = match cidr.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (cidr, None),
+        };
+        let address: IpAddr = address.parse().map_err(|_| InvalidCidr)?;
+        let max = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().map_err(|_| InvalidCidr)?,
+            None => max,
+        };
+        if prefix > max {
+            return Err(InvalidCidr);
+        }
+        Ok(Self {
+            network: mask(address, prefix),
+            prefix,
+        })
+    }
+}
+
+impl Cidr {
+    /// Checks whether an address is in the block. IPv4 addresses mapped
+    /// to IPv6, as seen when listening on `::`, are treated as IPv4.
+    pub fn contains(&self, address: IpAddr) -> bool {
+        let address = match address {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(address, IpAddr::V4),
+            address => address,
+        };
+        address.is_ipv4() == self.network.is_ipv4() && mask(address, self.prefix) == self.network
+    }
+}
+
+/// Clears the bits of an address past the prefix length.
+fn mask(address: IpAddr, prefix: u8) -> IpAddr {
+    match address {
+        IpAddr::V4(v4) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+            IpAddr::V4((u32::from(v4) & mask).into())
+        }
+        IpAddr::V6(v6) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+            IpAddr::V6((u128::from(v6) & mask).into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Cidr;
+    use std::net::IpAddr;
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn test_ipv4() {
+        let cidr: Cidr = "192.30.252.0/22".parse().unwrap();
+        assert!(cidr.contains(ip("192.30.252.1")));
+        assert!(cidr.contains(ip("192.30.255.255")));
+        assert!(!cidr.contains(ip("192.30.251.255")));
+        assert!(!cidr.contains(ip("192.31.0.1")));
+        assert!(cidr.contains(ip("::ffff:192.30.253.4")));
+        assert!(!cidr.contains(ip("2a0a:a440::1")));
+    }
+
+    #[test]
+    fn test_ipv6() {
+        let cidr: Cidr = "2a0a:a440::/29".parse().unwrap();
+        assert!(cidr.contains(ip("2a0a:a440::1")));
+        assert!(cidr.contains(ip("2a0a:a447:ffff::1")));
+        assert!(!cidr.contains(ip("2a0a:a448::1")));
+        assert!(!cidr.contains(ip("192.30.252.1")));
+    }
+
+    #[test]
+    fn test_single_address() {
+        let cidr: Cidr = "127.0.0.1".parse().unwrap();
+        assert!(cidr.contains(ip("127.0.0.1")));
+        assert!(!cidr.contains(ip("127.0.0.2")));
+        let everything: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(everything.contains(ip("8.8.8.8")));
+    }
+
+    #[test]
+    fn test_invalid() {
+        for cidr in [
+            "",
+            "github",
+            "192.30.252.0/33",
+            "192.30.252.0/",
+            "::/129",
+            "1.2.3/8",
+        ] {
+            assert!(cidr.parse::<Cidr>().is_err(), "{}", cidr);
+        }
+    }
+}
@@ -0,0 +1,440 @@
+use crate::config::SharedConfig;
+use crate::connection::Connection;
+use crate::webhook::announce_latest_commit;
+
+/// Ranks allowed to use commands, from room moderator up.
+const AUTHORIZED_RANKS: &[char] = &['@', '#', '&', '~'];
+
+const ALIAS_USAGE: &str =
+    "Usage: ~alias add login=Name, ~alias remove login, ~alias who Name, ~alias list";
+
+const ANNOUNCE_USAGE: &str = "Usage: ~announce owner/repo";
+
+/// Longest reply sent as a single chat message, which is the most
+/// Showdown allows.
+const MAX_REPLY_LENGTH: usize = 300;
+
+/// Finds the arguments of a command, if the message is that command and
+/// the user is allowed to use it.
+fn arguments<'a>(user: &str, message: &'a str, command: &str) -> Option<&'a str> {
+    let arguments = message.strip_prefix(command)?;
+    if !(arguments.is_empty() || arguments.starts_with(' ')) {
+        return None;
+    }
+    if !user.starts_with(AUTHORIZED_RANKS) {
+        return None;
+    }
+    Some(arguments.trim())
+}
+
+/// Handles a chat message, returning the reply if it was a command the
+/// user is allowed to use. `user` is prefixed with the user's room rank,
+/// as sent by Showdown. Replies start with fixed text, so that arguments
+/// can't make them run as a chat command.
+///
+/// Alias changes last until the bot stops, or across restarts when
+/// `PSDEVBOT_STATE_PATH` is set.
+pub fn handle_command(config: &SharedConfig, user: &str, message: &str) -> Option<String> {
+    let arguments = arguments(user, message, "~alias")?;
+    let (subcommand, argument) = arguments.split_once(' ').unwrap_or((arguments, ""));
+    let argument = argument.trim();
+    Some(match subcommand {
+        "add" => match argument.split_once('=') {
+            Some((login, alias)) if !login.trim().is_empty() && !alias.trim().is_empty() => {
+                let (login, alias) = (login.trim(), alias.trim());
+                config
+                    .update_username_aliases(|aliases| aliases.insert(login.into(), alias.into()));
+                format!("Now showing {} as {}", login, alias)
+            }
+            _ => ALIAS_USAGE.into(),
+        },
+        "remove" if !argument.is_empty() => {
+            if config.update_username_aliases(|aliases| aliases.remove(argument)) {
+                format!("Removed the alias for {}", argument)
+            } else {
+                format!("There is no alias for {}", argument)
+            }
+        }
+        "who" if !argument.is_empty() => {
+            let config = config.get();
+            let mut logins: Vec<_> = config.username_aliases.reverse_get_all(argument).collect();
+            if logins.is_empty() {
+                format!("No GitHub account is shown as {}", argument)
+            } else {
+                logins.sort_unstable_by_key(|login| login.to_lowercase());
+                format!(
+                    "GitHub accounts shown as {}: {}",
+                    argument,
+                    logins.join(", ")
+                )
+            }
+        }
+        "list" => {
+            let config = config.get();
+            let mut aliases: Vec<_> = config
+                .username_aliases
+                .iter()
+                .map(|(login, alias)| format!("{}: {}", login, alias))
+                .collect();
+            if aliases.is_empty() {
+                "There are no aliases".into()
+            } else {
+                aliases.sort_unstable_by_key(|alias| alias.to_lowercase());
+                format!("Aliases: {}", aliases.join(", "))
+            }
+        }
+        _ => ALIAS_USAGE.into(),
+    })
+}
+
+/// Handles `~config`, listing the projects announcing in `room` and
+/// where their announcements go, or every project in the admin room.
+/// Secrets are never shown, only whether they are set. Long lists are
+/// split into several replies.
+pub fn handle_config(
+    config: &SharedConfig,
+    room: &str,
+    user: &str,
+    message: &str,
+) -> Option<Vec<String>> {
+    arguments(user, message, "~config")?;
+    let config = config.get();
+    let everything = config.admin_room.as_deref() == Some(room);
+    let projects: Vec<_> = config
+        .projects()
+        .filter(|(_, project)| {
+            everything
+                || project
+                    .rooms
+                    .iter()
+                    .chain(project.simple_rooms)
+                    .any(|project_room| project_room == room)
+        })
+        .map(|(name, project)| format!("{} ({})", name, project.summary()))
+        .collect();
+    if projects.is_empty() {
+        return Some(vec!["There are no projects announcing in this room".into()]);
+    }
+    let mut replies = vec![String::from("Projects: ")];
+    for project in projects {
+        let reply = replies.last_mut().unwrap();
+        if !reply.ends_with(": ") {
+            if reply.len() + project.len() + 2 <= MAX_REPLY_LENGTH {
+                *reply += ", ";
+                *reply += &project;
+                continue;
+            }
+            replies.push("More projects: ".into());
+        }
+        // Projects with many rooms may not fit in a reply on their own.
+        let mut rest = project.as_str();
+        loop {
+            let reply = replies.last_mut().unwrap();
+            let (part, remaining) = split_reply(rest, MAX_REPLY_LENGTH - reply.len());
+            *reply += part;
+            if remaining.is_empty() {
+                break;
+            }
+            rest = remaining;
+            replies.push("More projects: ".into());
+        }
+    }
+    Some(replies)
+}
+
+/// Splits `text` after at most `max_length` bytes, at the last space
+/// when there is one.
+fn split_reply(text: &str, max_length: usize) -> (&str, &str) {
+    if text.len() <= max_length {
+        return (text, "");
+    }
+    let mut end = max_length;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    match text[..end].rfind(' ') {
+        Some(space) if space > 0 => (&text[..space], &text[space + 1..]),
+        _ => text.split_at(end),
+    }
+}
+
+/// Checks that a repository is written as `owner/repo`, as it's used in
+/// GitHub API URLs.
+fn is_repository_name(repository: &str) -> bool {
+    let is_valid_part = |part: &str| {
+        !part.is_empty()
+            && !part.chars().all(|c| c == '.')
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+    };
+    match repository.split_once('/') {
+        Some((owner, name)) => is_valid_part(owner) && is_valid_part(name),
+        None => false,
+    }
+}
+
+/// Handles `~announce owner/repo`, announcing the latest commit of the
+/// repository's default branch to the rooms of its project. Returns the
+/// reply, including when the announcement failed.
+///
+/// `user` is prefixed with their rank in `room`, so the command is only
+/// accepted in the rooms the project announces to, or the admin room.
+pub async fn handle_announce(
+    config: &SharedConfig,
+    connection: &'static Connection,
+    room: &str,
+    user: &str,
+    message: &str,
+) -> Option<String> {
+    let repository = arguments(user, message, "~announce")?;
+    if !is_repository_name(repository) {
+        return Some(ANNOUNCE_USAGE.into());
+    }
+    let config = config.get();
+    let room_configuration = config.rooms_for(repository);
+    let allowed = config.admin_room.as_deref() == Some(room)
+        || room_configuration
+            .rooms
+            .iter()
+            .chain(room_configuration.simple_rooms)
+            .any(|project_room| project_room == room);
+    if !allowed {
+        return Some(format!(
+            "Announcing {} is only possible in its rooms",
+            repository
+        ));
+    }
+    Some(
+        match announce_latest_commit(&config, connection, repository).await {
+            Ok(()) => format!("Announced the latest commit of {}", repository),
+            Err(e) => e,
+        },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        handle_announce, handle_command, handle_config, ALIAS_USAGE, ANNOUNCE_USAGE,
+        MAX_REPLY_LENGTH,
+    };
+    use crate::args::Args;
+    use crate::config::{Config, ConfigFile, SharedConfig};
+    use crate::connection::Connection;
+
+    fn shared_config() -> SharedConfig {
+        let config = Config::from_sources(ConfigFile::default(), |name| {
+            match name {
+                "PSDEVBOT_SERVER" => Some("wss://localhost/showdown/websocket"),
+                "PSDEVBOT_USER" => Some("user"),
+                "PSDEVBOT_PASSWORD" => Some("password"),
+                "PSDEVBOT_SECRET" => Some("secret"),
+                "PSDEVBOT_ROOM" => Some("room"),
+                "PSDEVBOT_PROJECT_CONFIGURATION" => Some(r#"{"smogon/*": {"rooms": ["dev"]}}"#),
+                _ => None,
+            }
+            .map(String::from)
+        })
+        .unwrap();
+        SharedConfig::new(config, Args::default())
+    }
+
+    #[test]
+    fn test_alias_commands() {
+        let config = shared_config();
+        assert_eq!(
+            handle_command(&config, "@Mod", "~alias list").as_deref(),
+            Some("There are no aliases"),
+        );
+        assert_eq!(
+            handle_command(&config, "#Owner", "~alias add Zarel = Guangcong Chen").as_deref(),
+            Some("Now showing Zarel as Guangcong Chen"),
+        );
+        handle_command(&config, "@Mod", "~alias add a=Awesome");
+        handle_command(&config, "@Mod", "~alias add b=awesome");
+        assert_eq!(config.get().username_aliases.get("zarel"), "Guangcong Chen");
+        assert_eq!(
+            handle_command(&config, "@Mod", "~alias list").as_deref(),
+            Some("Aliases: a: Awesome, b: awesome, Zarel: Guangcong Chen"),
+        );
+        assert_eq!(
+            handle_command(&config, "@Mod", "~alias who AWESOME").as_deref(),
+            Some("GitHub accounts shown as AWESOME: a, b"),
+        );
+        assert_eq!(
+            handle_command(&config, "@Mod", "~alias who guangcong chen").as_deref(),
+            Some("GitHub accounts shown as guangcong chen: Zarel"),
+        );
+        assert_eq!(
+            handle_command(&config, "@Mod", "~alias who Nobody").as_deref(),
+            Some("No GitHub account is shown as Nobody"),
+        );
+        assert_eq!(
+            handle_command(&config, "@Mod", "~alias remove zarel").as_deref(),
+            Some("Removed the alias for zarel"),
+        );
+        assert_eq!(
+            handle_command(&config, "@Mod", "~alias remove zarel").as_deref(),
+            Some("There is no alias for zarel"),
+        );
+        assert_eq!(config.get().username_aliases.get("zarel"), "zarel");
+    }
+
+    #[test]
+    fn test_alias_replies_are_not_commands() {
+        let config = shared_config();
+        for command in [
+            "~alias add /ban a=b",
+            "~alias remove !dt pikachu",
+            "~alias who /ban a",
+        ] {
+            let reply = handle_command(&config, "@Mod", command).unwrap();
+            assert!(!reply.starts_with(&['/', '!'][..]), "{}", reply);
+        }
+    }
+
+    #[test]
+    fn test_alias_command_needs_rank() {
+        let config = shared_config();
+        assert_eq!(handle_command(&config, " Regular", "~alias add a=b"), None);
+        assert_eq!(handle_command(&config, "+Voice", "~alias list"), None);
+        assert_eq!(config.get().username_aliases.get("a"), "a");
+    }
+
+    #[test]
+    fn test_alias_command_usage() {
+        let config = shared_config();
+        for message in [
+            "~alias",
+            "~alias add a",
+            "~alias add =b",
+            "~alias remove",
+            "~alias who",
+            "~alias x",
+        ] {
+            assert_eq!(
+                handle_command(&config, "@Mod", message).as_deref(),
+                Some(ALIAS_USAGE),
+                "{}",
+                message,
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_announce_command() {
+        let config = shared_config();
+        let connection = Box::leak(Box::new(Connection::new()));
+        for (room, user, message, reply) in [
+            ("dev", " Regular", "~announce a/b", None),
+            ("dev", "@Mod", "~announcement", None),
+            ("dev", "@Mod", "~announce", Some(ANNOUNCE_USAGE)),
+            ("dev", "@Mod", "~announce smogon", Some(ANNOUNCE_USAGE)),
+            ("dev", "@Mod", "~announce smogon/", Some(ANNOUNCE_USAGE)),
+            ("dev", "@Mod", "~announce a/b c", Some(ANNOUNCE_USAGE)),
+            ("dev", "@Mod", "~announce a/b/c", Some(ANNOUNCE_USAGE)),
+            ("dev", "@Mod", "~announce ../b", Some(ANNOUNCE_USAGE)),
+            ("dev", "@Mod", "~announce a/b?c", Some(ANNOUNCE_USAGE)),
+            (
+                "lobby",
+                "@Mod",
+                "~announce smogon/pokemon-showdown",
+                Some("Announcing smogon/pokemon-showdown is only possible in its rooms"),
+            ),
+            (
+                "room",
+                "@Mod",
+                "~announce a/b",
+                Some("There is no project for a/b"),
+            ),
+            (
+                "dev",
+                "#Owner",
+                "~announce smogon/pokemon-showdown",
+                Some("The GitHub API isn't configured"),
+            ),
+        ] {
+            assert_eq!(
+                handle_announce(&config, connection, room, user, message)
+                    .await
+                    .as_deref(),
+                reply,
+                "{}",
+                message,
+            );
+        }
+    }
+
+    #[test]
+    fn test_config_command() {
+        let config = shared_config();
+        assert_eq!(
+            handle_config(&config, "dev", "@Mod", "~config"),
+            Some(vec![
+                "Projects: smogon/* (rooms: dev; simple rooms: none; secret: set)".into()
+            ]),
+        );
+        assert_eq!(
+            handle_config(&config, "lobby", "@Mod", "~config"),
+            Some(vec!["There are no projects announcing in this room".into()]),
+        );
+        assert_eq!(handle_config(&config, "dev", " Regular", "~config"), None);
+        assert_eq!(handle_config(&config, "dev", "@Mod", "~alias list"), None);
+    }
+
+    #[test]
+    fn test_config_command_in_admin_room() {
+        let mut projects: Vec<_> = (0..20)
+            .map(|n| format!(r#""smogon/project-{}": {{"rooms": ["room{}"]}}"#, n, n))
+            .collect();
+        let rooms: Vec<_> = (0..60).map(|n| format!(r#""busyroom{}""#, n)).collect();
+        projects.push(format!(
+            r#""smogon/zbusy": {{"rooms": [{}]}}"#,
+            rooms.join(","),
+        ));
+        let projects = projects.join(",");
+        let config = Config::from_sources(ConfigFile::default(), |name| match name {
+            "PSDEVBOT_SERVER" => Some("wss://localhost/showdown/websocket".into()),
+            "PSDEVBOT_USER" => Some("user".into()),
+            "PSDEVBOT_PASSWORD" => Some("password".into()),
+            "PSDEVBOT_SECRET" => Some("secret".into()),
+            "PSDEVBOT_ADMIN_ROOM" => Some("staff".into()),
+            "PSDEVBOT_PROJECT_CONFIGURATION" => Some(format!("{{{}}}", projects)),
+            _ => None,
+        })
+        .unwrap();
+        let config = SharedConfig::new(config, Args::default());
+        let replies = handle_config(&config, "staff", "@Mod", "~config").unwrap();
+        assert!(replies.len() > 1);
+        assert!(replies[0].starts_with("Projects: smogon/project-0 ("));
+        for reply in &replies[1..] {
+            assert!(reply.starts_with("More projects: "), "{}", reply);
+        }
+        for reply in &replies {
+            assert!(reply.len() <= MAX_REPLY_LENGTH, "{}", reply);
+        }
+        let all = replies.join(", ");
+        assert_eq!(all.matches("smogon/project-").count(), 20);
+        assert_eq!(all.matches("smogon/zbusy").count(), 1);
+        // Rooms aren't cut in the middle of their names.
+        let rooms = all.matches("busyroom").count();
+        let whole = (0..60)
+            .filter(|n| {
+                all.contains(&format!("busyroom{},", n)) || all.contains(&format!("busyroom{};", n))
+            })
+            .count();
+        assert_eq!((rooms, whole), (60, 60));
+        assert_eq!(
+            handle_config(&config, "room3", "@Mod", "~config").unwrap(),
+            ["Projects: smogon/project-3 (rooms: room3; simple rooms: none; secret: set)"],
+        );
+    }
+
+    #[test]
+    fn test_other_messages_are_ignored() {
+        let config = shared_config();
+        assert_eq!(handle_command(&config, "@Mod", "hello"), None);
+        assert_eq!(handle_command(&config, "@Mod", "~aliases"), None);
+    }
+}
@@ -0,0 +1,173 @@
+use crate::unbounded::DelayedSender;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+/// The current connection to Showdown, outliving reconnections so that
+/// the webhook server can keep running while the bot reconnects.
+pub struct Connection {
+    state: RwLock<State>,
+    started: Instant,
+    shutting_down: AtomicBool,
+    /// Number of webhooks being handled, see [`Connection::start_webhook`].
+    webhooks: AtomicUsize,
+    /// Notified when the last webhook being handled is done.
+    webhooks_done: Notify,
+}
+
+/// Webhook being handled, which is done once this is dropped.
+pub struct Webhook<'a> {
+    connection: &'a Connection,
+}
+
+impl Drop for Webhook<'_> {
+    fn drop(&mut self) {
+        if self.connection.webhooks.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.connection.webhooks_done.notify_one();
+        }
+    }
+}
+
+enum State {
+    Connected(Arc<DelayedSender>),
+    Disconnected(Instant),
+}
+
+/// Status reported by the health endpoint.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct Health {
+    pub showdown: &'static str,
+    pub uptime_secs: u64,
+    /// How long the bot has been trying to reconnect.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disconnected_secs: Option<u64>,
+}
+
+impl Connection {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            state: RwLock::new(State::Disconnected(now)),
+            started: now,
+            shutting_down: AtomicBool::new(false),
+            webhooks: AtomicUsize::new(0),
+            webhooks_done: Notify::new(),
+        }
+    }
+
+    /// Marks the bot as shutting down, after which new webhooks are
+    /// refused.
+    pub fn shut_down(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+    }
+
+    /// Records a webhook being handled, unless the bot is shutting down.
+    pub fn start_webhook(&self) -> Option<Webhook<'_>> {
+        // Counted before checking, so that a webhook is either refused or
+        // waited for by `webhooks_finished`.
+        self.webhooks.fetch_add(1, Ordering::SeqCst);
+        let webhook = Webhook { connection: self };
+        if self.shutting_down.load(Ordering::SeqCst) {
+            None
+        } else {
+            Some(webhook)
+        }
+    }
+
+    /// Waits until no webhook is being handled.
+    pub async fn webhooks_finished(&self) {
+        while self.webhooks.load(Ordering::SeqCst) != 0 {
+            self.webhooks_done.notified().await;
+        }
+    }
+
+    pub fn connect(&self, sender: Arc<DelayedSender>) {
+        *self.state.write().unwrap() = State::Connected(sender);
+    }
+
+    pub fn disconnect(&self) {
+        let mut state = self.state.write().unwrap();
+        if let State::Connected(_) = *state {
+            *state = State::Disconnected(Instant::now());
+        }
+    }
+
+    /// Returns the sender for the current connection, if connected.
+    pub fn sender(&self) -> Option<Arc<DelayedSender>> {
+        match &*self.state.read().unwrap() {
+            State::Connected(sender) => Some(Arc::clone(sender)),
+            State::Disconnected(_) => None,
+        }
+    }
+
+    pub fn health(&self) -> Health {
+        let (showdown, disconnected_secs) = match &*self.state.read().unwrap() {
+            State::Connected(_) => ("connected", None),
+            State::Disconnected(since) => ("reconnecting", Some(since.elapsed().as_secs())),
+        };
+        Health {
+            showdown,
+            uptime_secs: self.started.elapsed().as_secs(),
+            disconnected_secs,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Connection, Health};
+    use crate::unbounded::DelayedSender;
+    use futures::channel::mpsc;
+    use futures::FutureExt;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::time;
+
+    #[tokio::test]
+    async fn test_health() {
+        time::pause();
+        let connection = Connection::new();
+        time::advance(Duration::from_secs(5)).await;
+        assert_eq!(
+            connection.health(),
+            Health {
+                showdown: "reconnecting",
+                uptime_secs: 5,
+                disconnected_secs: Some(5),
+            },
+        );
+        let (tx, _rx) = mpsc::unbounded();
+        connection.connect(Arc::new(DelayedSender::new(tx, Duration::from_millis(700))));
+        assert!(connection.sender().is_some());
+        time::advance(Duration::from_secs(5)).await;
+        assert_eq!(
+            connection.health(),
+            Health {
+                showdown: "connected",
+                uptime_secs: 10,
+                disconnected_secs: None,
+            },
+        );
+        connection.disconnect();
+        time::advance(Duration::from_secs(2)).await;
+        assert!(connection.sender().is_none());
+        assert_eq!(connection.health().disconnected_secs, Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_webhooks_finish_before_shutting_down() {
+        let connection = Connection::new();
+        let webhook = connection.start_webhook();
+        assert!(webhook.is_some());
+        connection.shut_down();
+        assert!(connection.start_webhook().is_none());
+        let mut finished = Box::pin(connection.webhooks_finished());
+        assert!((&mut finished).now_or_never().is_none());
+        drop(webhook);
+        time::timeout(Duration::from_secs(1), finished)
+            .await
+            .unwrap();
+    }
+}
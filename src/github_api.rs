@@ -1,22 +1,70 @@
-use log::info;
+use crate::admin::NOTICES;
+use crate::metrics::METRICS;
+use chrono::{DateTime, FixedOffset};
 use lru::LruCache;
-use reqwest::{header, Client};
+use reqwest::header::{self, HeaderMap};
+use reqwest::{Client, RequestBuilder, StatusCode, Url};
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
-use std::time::Duration;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+/// How long responses are reused before asking GitHub again.
+const CACHE_DURATION: Duration = Duration::from_secs(10 * 60);
 
 pub struct GitHubApi {
-    user: String,
-    password: String,
-    cache: LruCache<String, User>,
+    credentials: Credentials,
+    cache: LruCache<String, CacheEntry>,
+    rate_limit: Option<RateLimit>,
     client: Client,
 }
 
+/// Request quota reported by GitHub in the last response.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+struct RateLimit {
+    remaining: u32,
+    reset: SystemTime,
+}
+
+impl RateLimit {
+    fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let reset: u64 = parse_header(headers, "X-RateLimit-Reset")?;
+        Some(Self {
+            remaining: parse_header(headers, "X-RateLimit-Remaining")?,
+            reset: UNIX_EPOCH + Duration::from_secs(reset),
+        })
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.remaining == 0 && SystemTime::now() < self.reset
+    }
+}
+
+fn parse_header<T: FromStr>(headers: &HeaderMap, name: &str) -> Option<T> {
+    headers.get(name)?.to_str().ok()?.parse().ok()
+}
+
+enum Credentials {
+    Basic { user: String, password: String },
+    Token(String),
+}
+
 impl GitHubApi {
     pub fn new(user: String, password: String) -> Self {
+        Self::with_credentials(Credentials::Basic { user, password })
+    }
+
+    pub fn with_token(token: String) -> Self {
+        Self::with_credentials(Credentials::Token(token))
+    }
+
+    fn with_credentials(credentials: Credentials) -> Self {
         Self {
-            user,
-            password,
+            credentials,
             cache: LruCache::new(100),
+            rate_limit: None,
             client: Client::builder()
                 .timeout(Duration::from_secs(5))
                 .user_agent("psdevbot-rust")
@@ -25,31 +73,488 @@ impl GitHubApi {
         }
     }
 
-    pub async fn fetch_user(
+    /// Changes how many responses are kept in memory, 100 by default.
+    /// Responses are returned from the cache, so without room for any
+    /// every request would seem to fail.
+    pub fn with_cache_size(mut self, size: usize) -> Self {
+        self.cache.resize(size);
+        self
+    }
+
+    pub async fn fetch_user(&mut self, user_name: &str) -> Option<&User> {
+        self.fetch(format!("https://api.github.com/users/{}", user_name))
+            .await
+    }
+
+    /// Lists files changed by a pull request. Only the first 100 files
+    /// are returned.
+    pub async fn fetch_pull_request_files(
+        &mut self,
+        repository: &str,
+        number: u32,
+    ) -> Option<Vec<String>> {
+        let files: &Vec<PullRequestFile> = self
+            .fetch(format!(
+                "https://api.github.com/repos/{}/pulls/{}/files?per_page=100",
+                repository, number,
+            ))
+            .await?;
+        Some(files.iter().map(|file| file.filename.clone()).collect())
+    }
+
+    /// Finds the default branch of a repository, for payloads that don't
+    /// include it.
+    pub async fn fetch_default_branch(&mut self, repository: &str) -> Option<&str> {
+        let repository: &RepositoryInfo = self
+            .fetch(format!("https://api.github.com/repos/{}", repository))
+            .await?;
+        Some(&repository.default_branch)
+    }
+
+    /// Fetches the latest commit of a branch. Branches move, so cached
+    /// responses are only used while the quota is exhausted.
+    pub async fn fetch_latest_commit(
         &mut self,
-        #[allow(clippy::ptr_arg)] // due to LruCache limitations accepting &String is necessary.
-        user_name: &String,
-    ) -> Option<&User> {
-        if !self.cache.contains(user_name) {
-            info!("Fetching user `{}` from GitHub", user_name);
-            let user = self
-                .client
-                .get(&format!("https://api.github.com/users/{}", user_name))
+        repository: &str,
+        branch: &str,
+    ) -> Option<&CommitInfo> {
+        let url = format!(
+            "https://api.github.com/repos/{}/commits/{}",
+            repository, branch,
+        );
+        self.fetch_within(url, Duration::ZERO).await
+    }
+
+    /// Fetches the message of an annotated tag, given the hash of the tag
+    /// object. Lightweight tags point to a commit instead, so there is no
+    /// message to find for them.
+    pub async fn fetch_tag_message(&mut self, repository: &str, sha: &str) -> Option<&str> {
+        let tag: &TagInfo = self
+            .fetch(format!(
+                "https://api.github.com/repos/{}/git/tags/{}",
+                repository, sha,
+            ))
+            .await?;
+        Some(&tag.message)
+    }
+
+    /// Finds the open pull request made from a branch of the repository.
+    pub async fn fetch_pull_request_for_branch(
+        &mut self,
+        repository: &str,
+        branch: &str,
+    ) -> Option<&PullRequestSummary> {
+        let owner = repository.split('/').next().unwrap_or_default();
+        let url = Url::parse_with_params(
+            &format!("https://api.github.com/repos/{}/pulls", repository),
+            &[
+                ("state", "open"),
+                ("head", format!("{}:{}", owner, branch).as_str()),
+            ],
+        )
+        .ok()?;
+        let pull_requests: &Vec<PullRequestSummary> = self.fetch(url.into()).await?;
+        pull_requests.first()
+    }
+
+    /// Fetches a response, reusing a cached one when it's recent enough.
+    ///
+    /// Once the quota is exhausted no requests are made until it resets,
+    /// and outdated cached responses are returned instead. This doesn't
+    /// wait for the reset, as callers hold a lock on the API.
+    async fn fetch<T: Cached>(&mut self, url: String) -> Option<&T> {
+        self.fetch_within(url, CACHE_DURATION).await
+    }
+
+    /// Fetches a response, reusing a cached one younger than `max_age`,
+    /// see [`GitHubApi::fetch`].
+    async fn fetch_within<T: Cached>(&mut self, url: String, max_age: Duration) -> Option<&T> {
+        let fresh = self
+            .cache
+            .peek(&url)
+            .is_some_and(|entry| entry.fetched.elapsed() < max_age);
+        let exhausted = self.rate_limit.is_some_and(|limit| limit.is_exhausted());
+        if exhausted && !fresh {
+            warn!("Not fetching {}, GitHub API rate limit exceeded", url);
+        } else if !fresh {
+            info!("Fetching {} from GitHub", url);
+            METRICS.github_api_request();
+            let response = self
+                .authenticate(self.client.get(&url))
                 .header(header::ACCEPT, "application/vnd.github.v3+json")
-                .basic_auth(&self.user, Some(&self.password))
                 .send()
                 .await
-                .ok()?
-                .json()
-                .await
                 .ok()?;
-            self.cache.put(user_name.clone(), user);
+            if let Some(rate_limit) = RateLimit::from_headers(response.headers()) {
+                METRICS.set_github_rate_limit_remaining(rate_limit.remaining);
+                self.rate_limit = Some(rate_limit);
+            }
+            if response.status() == StatusCode::UNAUTHORIZED {
+                warn!("GitHub API rejected the credentials when fetching {}", url);
+                NOTICES.notify(
+                    "github unauthorized",
+                    "GitHub API rejected the configured credentials",
+                );
+                return None;
+            }
+            let response: T = response.json().await.ok()?;
+            self.cache.put(
+                url.clone(),
+                CacheEntry {
+                    fetched: Instant::now(),
+                    response: response.into_response(),
+                },
+            );
         }
-        self.cache.get(user_name)
+        self.cache
+            .get(&url)
+            .and_then(|entry| T::from_response(&entry.response))
     }
+
+    fn authenticate(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.credentials {
+            Credentials::Basic { user, password } => request.basic_auth(user, Some(password)),
+            Credentials::Token(token) => request.bearer_auth(token),
+        }
+    }
+}
+
+struct CacheEntry {
+    fetched: Instant,
+    response: Response,
+}
+
+/// Parsed responses stored in the cache.
+enum Response {
+    User(User),
+    PullRequestFiles(Vec<PullRequestFile>),
+    Repository(RepositoryInfo),
+    PullRequests(Vec<PullRequestSummary>),
+    Commit(CommitInfo),
+    Tag(TagInfo),
 }
 
+trait Cached: DeserializeOwned + 'static {
+    fn into_response(self) -> Response;
+
+    fn from_response(response: &Response) -> Option<&Self>;
+}
+
+macro_rules! cached {
+    ($type:ty, $variant:ident) => {
+        impl Cached for $type {
+            fn into_response(self) -> Response {
+                Response::$variant(self)
+            }
+
+            fn from_response(response: &Response) -> Option<&Self> {
+                match response {
+                    Response::$variant(value) => Some(value),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+cached!(User, User);
+cached!(Vec<PullRequestFile>, PullRequestFiles);
+cached!(RepositoryInfo, Repository);
+cached!(Vec<PullRequestSummary>, PullRequests);
+cached!(CommitInfo, Commit);
+cached!(TagInfo, Tag);
+
 #[derive(Deserialize)]
 pub struct User {
     pub html_url: String,
 }
+
+#[derive(Deserialize)]
+struct PullRequestFile {
+    filename: String,
+}
+
+#[derive(Deserialize)]
+struct RepositoryInfo {
+    default_branch: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PullRequestSummary {
+    pub number: u32,
+    pub title: String,
+    pub html_url: String,
+}
+
+#[derive(Deserialize)]
+pub struct CommitInfo {
+    pub sha: String,
+    pub html_url: String,
+    pub commit: GitCommit,
+    /// GitHub account of the author, missing when their e-mail address
+    /// isn't linked to one.
+    pub author: Option<Account>,
+    #[serde(default)]
+    pub parents: Vec<CommitParent>,
+    #[serde(default)]
+    files: Vec<PullRequestFile>,
+}
+
+impl CommitInfo {
+    /// Lists files changed by the commit. Only the first 300 files are
+    /// returned.
+    pub fn files(&self) -> impl Iterator<Item = &str> {
+        self.files.iter().map(|file| &*file.filename)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct GitCommit {
+    pub message: String,
+    pub author: GitAuthor,
+}
+
+#[derive(Deserialize)]
+pub struct GitAuthor {
+    pub name: String,
+    #[serde(default)]
+    pub date: Option<DateTime<FixedOffset>>,
+}
+
+#[derive(Deserialize)]
+pub struct Account {
+    pub login: String,
+}
+
+#[derive(Deserialize)]
+pub struct CommitParent {
+    pub sha: String,
+}
+
+#[derive(Deserialize)]
+struct TagInfo {
+    message: String,
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        CacheEntry, CommitInfo, GitHubApi, PullRequestSummary, RateLimit, RepositoryInfo, Response,
+        TagInfo, User, CACHE_DURATION,
+    };
+    use reqwest::header::{self, HeaderMap, HeaderValue};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use tokio::time::{self, Instant};
+
+    fn authorization(github_api: &GitHubApi) -> String {
+        let request = github_api
+            .authenticate(github_api.client.get("https://api.github.com/"))
+            .build()
+            .unwrap();
+        request.headers()[header::AUTHORIZATION]
+            .to_str()
+            .unwrap()
+            .into()
+    }
+
+    #[test]
+    fn test_token_authentication() {
+        let github_api = GitHubApi::with_token("abc".into());
+        assert_eq!(authorization(&github_api), "Bearer abc");
+    }
+
+    #[test]
+    fn test_basic_authentication() {
+        let github_api = GitHubApi::new("user".into(), "password".into());
+        assert_eq!(authorization(&github_api), "Basic dXNlcjpwYXNzd29yZA==");
+    }
+
+    fn cache_user(github_api: &mut GitHubApi, name: &str) {
+        github_api.cache.put(
+            format!("https://api.github.com/users/{}", name),
+            CacheEntry {
+                fetched: Instant::now(),
+                response: Response::User(User {
+                    html_url: format!("https://github.com/{}", name),
+                }),
+            },
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cached_response_is_reused() {
+        time::pause();
+        let mut github_api = GitHubApi::with_token("token".into());
+        cache_user(&mut github_api, "xfix");
+        time::advance(CACHE_DURATION / 2).await;
+        let user = github_api.fetch_user("xfix").await.unwrap();
+        assert_eq!(user.html_url, "https://github.com/xfix");
+    }
+
+    #[tokio::test]
+    async fn test_default_branch_is_cached() {
+        time::pause();
+        let mut github_api = GitHubApi::with_token("token".into());
+        for (repository, default_branch) in [("smogon/pokemon-showdown", "master"), ("a/b", "main")]
+        {
+            github_api.cache.put(
+                format!("https://api.github.com/repos/{}", repository),
+                CacheEntry {
+                    fetched: Instant::now(),
+                    response: Response::Repository(RepositoryInfo {
+                        default_branch: default_branch.into(),
+                    }),
+                },
+            );
+        }
+        time::advance(CACHE_DURATION / 2).await;
+        assert_eq!(
+            github_api
+                .fetch_default_branch("smogon/pokemon-showdown")
+                .await,
+            Some("master"),
+        );
+        assert_eq!(github_api.fetch_default_branch("a/b").await, Some("main"));
+    }
+
+    #[tokio::test]
+    async fn test_pull_request_for_branch() {
+        let mut github_api = GitHubApi::with_token("token".into());
+        for (branch, pull_requests) in [("feature", vec![(42, "Add widget")]), ("no-pr", vec![])] {
+            github_api.cache.put(
+                format!(
+                    "https://api.github.com/repos/a/b/pulls?state=open&head=a%3A{}",
+                    branch,
+                ),
+                CacheEntry {
+                    fetched: Instant::now(),
+                    response: Response::PullRequests(
+                        pull_requests
+                            .into_iter()
+                            .map(|(number, title)| PullRequestSummary {
+                                number,
+                                title: title.into(),
+                                html_url: format!("https://github.com/a/b/pull/{}", number),
+                            })
+                            .collect(),
+                    ),
+                },
+            );
+        }
+        let pull_request = github_api
+            .fetch_pull_request_for_branch("a/b", "feature")
+            .await
+            .unwrap();
+        assert_eq!(
+            (pull_request.number, &*pull_request.title),
+            (42, "Add widget")
+        );
+        assert!(github_api
+            .fetch_pull_request_for_branch("a/b", "no-pr")
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_latest_commit() {
+        let mut github_api = GitHubApi::with_token("token".into());
+        // The latest commit is fetched again whenever possible, so the
+        // cached one is only used without quota left.
+        github_api.rate_limit = Some(RateLimit {
+            remaining: 0,
+            reset: SystemTime::now() + Duration::from_secs(60 * 60),
+        });
+        let commit: CommitInfo = serde_json::from_value(serde_json::json!({
+            "sha": "2222222222222222222222222222222222222222",
+            "html_url": "https://github.com/a/b/commit/2222222222222222222222222222222222222222",
+            "commit": {
+                "message": "Update things",
+                "author": {"name": "Zarel", "date": "2021-07-01T12:30:00Z"}
+            },
+            "author": {"login": "Zarel"},
+            "parents": [{"sha": "1111111111111111111111111111111111111111"}],
+            "files": [{"filename": "README.md"}, {"filename": "src/main.rs"}]
+        }))
+        .unwrap();
+        github_api.cache.put(
+            "https://api.github.com/repos/a/b/commits/master".into(),
+            CacheEntry {
+                fetched: Instant::now(),
+                response: Response::Commit(commit),
+            },
+        );
+        let commit = github_api
+            .fetch_latest_commit("a/b", "master")
+            .await
+            .unwrap();
+        assert_eq!(commit.commit.message, "Update things");
+        assert_eq!(
+            commit.parents[0].sha,
+            "1111111111111111111111111111111111111111"
+        );
+        let files: Vec<_> = commit.files().collect();
+        assert_eq!(files, ["README.md", "src/main.rs"]);
+    }
+
+    #[tokio::test]
+    async fn test_tag_message() {
+        let mut github_api = GitHubApi::with_token("token".into());
+        github_api.cache.put(
+            "https://api.github.com/repos/a/b/git/tags/3333333".into(),
+            CacheEntry {
+                fetched: Instant::now(),
+                response: Response::Tag(TagInfo {
+                    message: "Version 1.0\n".into(),
+                }),
+            },
+        );
+        assert_eq!(
+            github_api.fetch_tag_message("a/b", "3333333").await,
+            Some("Version 1.0\n"),
+        );
+    }
+
+    #[test]
+    fn test_cache_size() {
+        let mut github_api = GitHubApi::with_token("token".into()).with_cache_size(2);
+        cache_user(&mut github_api, "a");
+        cache_user(&mut github_api, "b");
+        cache_user(&mut github_api, "c");
+        assert_eq!(github_api.cache.len(), 2);
+        assert!(!github_api
+            .cache
+            .contains(&"https://api.github.com/users/a".to_string()));
+    }
+
+    #[test]
+    fn test_rate_limit_from_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("X-RateLimit-Remaining", HeaderValue::from_static("42"));
+        assert_eq!(RateLimit::from_headers(&headers), None);
+        headers.insert("X-RateLimit-Reset", HeaderValue::from_static("1600000000"));
+        assert_eq!(
+            RateLimit::from_headers(&headers),
+            Some(RateLimit {
+                remaining: 42,
+                reset: UNIX_EPOCH + Duration::from_secs(1_600_000_000),
+            }),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_exhausted_rate_limit_uses_outdated_cache() {
+        time::pause();
+        let mut github_api = GitHubApi::with_token("token".into());
+        cache_user(&mut github_api, "xfix");
+        time::advance(CACHE_DURATION * 2).await;
+        let rate_limit = RateLimit {
+            remaining: 0,
+            reset: SystemTime::now() + Duration::from_secs(60 * 60),
+        };
+        github_api.rate_limit = Some(rate_limit);
+        let user = github_api.fetch_user("xfix").await.unwrap();
+        assert_eq!(user.html_url, "https://github.com/xfix");
+        assert!(github_api.fetch_user("unknown").await.is_none());
+        assert_eq!(github_api.rate_limit, Some(rate_limit));
+    }
+}
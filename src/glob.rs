@@ -0,0 +1,97 @@
+use regex::Regex;
+use serde::de::{Deserializer, Error};
+use serde::Deserialize;
+use std::fmt::{self, Debug, Formatter};
+
+/// A shell-like pattern, where `*` matches any sequence of characters
+/// other than `/`, `**` matches any sequence of characters and `?`
+/// matches a single character other than `/`.
+#[derive(Clone)]
+pub struct Glob {
+    pattern: String,
+    regex: Regex,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        let mut regex = String::from("^");
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    regex.push_str(".*");
+                }
+                '*' => regex.push_str("[^/]*"),
+                '?' => regex.push_str("[^/]"),
+                c => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+            }
+        }
+        regex.push('$');
+        Ok(Self {
+            pattern: pattern.into(),
+            regex: Regex::new(&regex)?,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn is_match(&self, input: &str) -> bool {
+        self.regex.is_match(input)
+    }
+}
+
+impl Debug for Glob {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&self.pattern, f)
+    }
+}
+
+impl<'de> Deserialize<'de> for Glob {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let pattern = String::deserialize(deserializer)?;
+        Glob::new(&pattern).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Glob;
+
+    #[test]
+    fn test_literal() {
+        let glob = Glob::new("master").unwrap();
+        assert!(glob.is_match("master"));
+        assert!(!glob.is_match("master2"));
+        assert!(!glob.is_match("old-master"));
+    }
+
+    #[test]
+    fn test_star() {
+        let glob = Glob::new("release/*").unwrap();
+        assert!(glob.is_match("release/1.0"));
+        assert!(glob.is_match("release/"));
+        assert!(!glob.is_match("release/1.0/hotfix"));
+        assert!(!glob.is_match("release"));
+    }
+
+    #[test]
+    fn test_double_star() {
+        let glob = Glob::new("dependabot/**").unwrap();
+        assert!(glob.is_match("dependabot/npm_and_yarn/eslint-7.0.0"));
+        assert!(!glob.is_match("dependabot"));
+    }
+
+    #[test]
+    fn test_question_mark_and_escaping() {
+        let glob = Glob::new("v?.(x)").unwrap();
+        assert!(glob.is_match("v1.(x)"));
+        assert!(!glob.is_match("v1a(x)"));
+        assert!(!glob.is_match("v/.(x)"));
+    }
+}
@@ -1,43 +1,215 @@
+mod admin;
+mod args;
+mod backoff;
+mod cidr;
+mod commands;
 mod config;
+mod connection;
 mod github_api;
+mod glob;
+mod message_template;
+mod metrics;
+mod quiet_hours;
+mod rooms;
+mod state;
 mod unbounded;
 mod webhook;
 
-use config::Config;
+use admin::{send_notices, NOTICES};
+use args::Args;
+use backoff::Backoff;
+use clap::Parser;
+use commands::{handle_announce, handle_command, handle_config};
+use config::{Config, SharedConfig};
+use connection::Connection;
+use futures::channel::mpsc;
 use futures::stream::{SplitStream, StreamExt};
-use log::{error, info};
-use showdown::message::{Kind, UpdateUser};
-use showdown::{SendMessage, Stream};
+use rooms::{sync_rooms, Rejection, RoomJoins};
+use showdown::message::{Kind, NoInit, UpdateUser};
+use showdown::{RoomId, SendMessage, Stream};
 use std::error::Error;
+use std::io;
+use std::panic;
+use std::process;
 use std::sync::Arc;
 use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::time;
+use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
 use unbounded::DelayedSender;
-use webhook::start_server;
+use webhook::{start_server, PendingEvents};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     dotenv::dotenv().ok();
-    let config = Box::leak(Box::new(Config::new()?));
-    env_logger::init();
+    tracing_subscriber::fmt()
+        .json()
+        .with_env_filter(EnvFilter::from_default_env())
+        .init();
+    let args = Args::parse();
+    if args.check {
+        process::exit(check(&args));
+    }
+    if args.print_config {
+        process::exit(print_config(&args));
+    }
+    let config = match Config::new(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            process::exit(2);
+        }
+    };
+    if config.dry_run {
+        warn!("Dry run, announcements will be logged instead of sent");
+    }
+    for (name, project) in config.projects() {
+        info!("Project {}: {}", name, project.summary());
+    }
+    let config = Box::leak(Box::new(SharedConfig::new(config, args)));
+    state::restore(config);
+    tokio::spawn(state::save_periodically(config));
+    tokio::spawn(reload_on_hangup(config));
+    let connection = Box::leak(Box::new(Connection::new()));
+    tokio::spawn(send_notices(config, connection, NOTICES.receive()));
+    report_panics();
+    let pending = Box::leak(Box::new(PendingEvents::default()));
+    let (_, _server) = start_server(config, connection, pending);
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        _ = stay_connected(config, connection) => {}
+        _ = terminate.recv() => {}
+    }
+    shut_down(config, connection, pending).await;
+    Ok(())
+}
+
+async fn stay_connected(config: &'static SharedConfig, connection: &'static Connection) {
+    let mut backoff = Backoff::new();
+    let mut error = None;
     loop {
-        match start(config).await {
+        match start(config, connection, &mut backoff, error.take()).await {
             Ok(()) => info!("Got a regular disconnect"),
             Err(e) => {
                 error!("Disconnected due to an error: {}", e);
-                time::sleep(Duration::from_secs(10)).await;
+                error = Some(e.to_string());
             }
         }
+        connection.disconnect();
+        let delay = backoff.next_delay(config.get().reconnect_max);
+        info!("Reconnecting in {:?}", delay);
+        time::sleep(delay).await;
+    }
+}
+
+/// Reports panics to the admin room, besides printing them as usual.
+/// Only the location is reported, as the message may contain anything.
+fn report_panics() {
+    let print = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        print(info);
+        let location = info
+            .location()
+            .map_or_else(String::new, |location| format!(" at {}", location));
+        NOTICES.notify(
+            &format!("panic{}", location),
+            format!("A task panicked{}, check the logs", location),
+        );
+    }));
+}
+
+/// Refuses new webhooks and waits for the ones being handled, then
+/// announces batched pushes, waits for queued messages to be sent and
+/// logs out, giving up after the configured drain timeout. The state is
+/// saved last, once no more deliveries are accepted.
+async fn shut_down(config: &SharedConfig, connection: &Connection, pending: &PendingEvents) {
+    info!("Shutting down");
+    connection.shut_down();
+    let drain = async {
+        connection.webhooks_finished().await;
+        if let Some(sender) = connection.sender() {
+            pending.flush().await;
+            sender
+                .send(SendMessage::global_command("logout"))
+                .await
+                .ok();
+            sender.close().await;
+        }
+    };
+    if time::timeout(config.get().drain_timeout, drain)
+        .await
+        .is_err()
+    {
+        warn!("Gave up waiting for queued messages to be sent");
+    }
+    state::save(config).await;
+}
+
+/// Validates the configuration without connecting anywhere, returning
+/// the exit code for the process.
+fn check(args: &Args) -> i32 {
+    let config = match Config::new(args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        }
+    };
+    let problems = config.validate();
+    for problem in &problems {
+        eprintln!("error: {}", problem);
+    }
+    if problems.is_empty() {
+        println!("Configuration is valid");
+        0
+    } else {
+        1
+    }
+}
+
+/// Prints the configuration as the bot understands it, returning the
+/// exit code for the process.
+fn print_config(args: &Args) -> i32 {
+    match Config::new(args) {
+        Ok(config) => {
+            println!("{:#?}", config);
+            0
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            1
+        }
     }
 }
 
-async fn start(config: &'static Config) -> Result<(), Box<dyn Error + Send + Sync>> {
+async fn reload_on_hangup(config: &'static SharedConfig) -> io::Result<()> {
+    let mut hangups = signal(SignalKind::hangup())?;
+    while hangups.recv().await.is_some() {
+        match config.reload() {
+            Ok(()) => info!("Reloaded configuration"),
+            Err(e) => error!("Keeping old configuration, reload failed: {}", e),
+        }
+    }
+    Ok(())
+}
+
+async fn start(
+    config: &'static SharedConfig,
+    connection: &'static Connection,
+    backoff: &mut Backoff,
+    error: Option<String>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
     let stream = time::timeout(Duration::from_secs(30), authenticate(config)).await??;
+    backoff.reset();
     let (sender, receiver) = stream.split();
-    run_authenticated(DelayedSender::new(sender), receiver, config).await
+    let sender = Arc::new(DelayedSender::new(sender, config.get().send_interval));
+    connection.connect(Arc::clone(&sender));
+    run_authenticated(sender, receiver, config, connection, error).await
 }
 
-async fn authenticate(config: &'static Config) -> Result<Stream, Box<dyn Error + Send + Sync>> {
+async fn authenticate(config: &SharedConfig) -> Result<Stream, Box<dyn Error + Send + Sync>> {
+    let config = config.get();
     let mut stream = Stream::connect_to_url(&config.server).await?;
     while let Some(message) = stream.next().await {
         if let Kind::Challenge(ch) = message?.kind() {
@@ -50,21 +222,110 @@ async fn authenticate(config: &'static Config) -> Result<Stream, Box<dyn Error +
 }
 
 async fn run_authenticated(
-    sender: DelayedSender,
+    sender: Arc<DelayedSender>,
     mut receiver: SplitStream<Stream>,
-    config: &'static Config,
+    config: &'static SharedConfig,
+    connection: &'static Connection,
+    mut error: Option<String>,
 ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
-    let sender = Arc::new(sender);
-    let _server = start_server(config, Arc::clone(&sender));
-    while let Some(message) = receiver.next().await {
-        let message = message?;
+    let mut joins = RoomJoins::default();
+    let mut named = false;
+    let mut reloads = config.reloads();
+    let (retry_tx, mut retries) = mpsc::unbounded::<String>();
+    loop {
+        let message = tokio::select! {
+            message = receiver.next() => match message {
+                Some(message) => message?,
+                None => break,
+            },
+            Ok(()) = reloads.changed(), if named => {
+                sync_rooms(&mut joins, &sender, config.get().all_rooms()).await?;
+                continue;
+            }
+            Some(room) = retries.next() => {
+                if joins.retry(&room) {
+                    info!("Trying to join {} again", room);
+                    rooms::join(&sender, &room).await?;
+                }
+                continue;
+            }
+        };
         info!("Received message: {:?}", message);
-        if let Kind::UpdateUser(UpdateUser { named: true, .. }) = message.kind() {
-            for room in config.all_rooms() {
-                let command = SendMessage::global_command(format_args!("join {}", room));
-                sender.send(command).await?;
+        match message.kind() {
+            Kind::UpdateUser(UpdateUser { named: true, .. }) => {
+                named = true;
+                sync_rooms(&mut joins, &sender, config.get().all_rooms()).await?;
+                // Sent once the admin room is joined.
+                if let Some(error) = error.take() {
+                    NOTICES.notify(
+                        "reconnect",
+                        format!("Reconnected to Showdown after an error: {}", error),
+                    );
+                }
+            }
+            Kind::RoomInit(_) => joins.joined(message.room().0),
+            Kind::Chat(chat) => {
+                if let Some(reply) = handle_command(config, chat.user(), chat.message()) {
+                    sender
+                        .send(SendMessage::chat_message(message.room(), reply))
+                        .await?;
+                } else if let Some(replies) =
+                    handle_config(config, message.room().0, chat.user(), chat.message())
+                {
+                    for reply in replies {
+                        sender
+                            .send(SendMessage::chat_message(message.room(), reply))
+                            .await?;
+                    }
+                } else if chat.message().starts_with("~announce") {
+                    let (room, user) = (message.room().0, chat.user());
+                    spawn_announce(config, connection, &sender, room, user, chat.message());
+                }
             }
+            Kind::NoInit(NoInit { reason, .. }) => {
+                let room = message.room().0;
+                match joins.rejected(room) {
+                    Rejection::Retry(delay) => {
+                        warn!("Cannot join {}, retrying in {:?}: {}", room, delay, reason);
+                        let retry_tx = retry_tx.clone();
+                        let room = room.to_owned();
+                        tokio::spawn(async move {
+                            time::sleep(delay).await;
+                            retry_tx.unbounded_send(room).ok();
+                        });
+                    }
+                    Rejection::GiveUp => {
+                        warn!("Cannot join configured room {}: {}", room, reason)
+                    }
+                    Rejection::Unexpected => {}
+                }
+            }
+            _ => {}
         }
     }
     Ok(())
 }
+
+/// Handles `~announce` in the background, as fetching the commit from
+/// GitHub takes a while.
+fn spawn_announce(
+    config: &'static SharedConfig,
+    connection: &'static Connection,
+    sender: &Arc<DelayedSender>,
+    room: &str,
+    user: &str,
+    message: &str,
+) {
+    let sender = Arc::clone(sender);
+    let room = room.to_owned();
+    let user = user.to_owned();
+    let message = message.to_owned();
+    tokio::spawn(async move {
+        if let Some(reply) = handle_announce(config, connection, &room, &user, &message).await {
+            sender
+                .send(SendMessage::chat_message(RoomId(&room), reply))
+                .await
+                .ok();
+        }
+    });
+}
@@ -0,0 +1,236 @@
+use crate::webhook::SUPPORTED_EVENTS;
+use serde::de::{Deserializer, Error, MapAccess, Visitor};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::{self, Formatter};
+
+/// Returns the placeholders a template for the given GitHub event type
+/// can use.
+pub fn placeholders(event: &str) -> &'static [&'static str] {
+    match event {
+        "push" => &["user", "repo", "branch", "count", "url"],
+        "pull_request" => &["user", "repo", "action", "number", "title", "url", "label"],
+        "pull_request_review" | "issues" => &["user", "repo", "action", "number", "title", "url"],
+        "issue_comment" => &["user", "repo", "number", "title", "url", "excerpt"],
+        "pull_request_review_comment" => {
+            &["user", "repo", "number", "title", "url", "count", "excerpt"]
+        }
+        "release" => &["user", "repo", "tag", "name", "url"],
+        "check_suite" | "status" => &["repo", "context", "branch", "sha", "url"],
+        "create" => &["user", "repo", "kind", "name", "url"],
+        "delete" => &["user", "repo", "kind", "name"],
+        "fork" => &["user", "repo", "count", "forks", "url"],
+        "star" => &["user", "repo", "stars", "url"],
+        "gollum" => &["user", "repo", "count", "title", "url"],
+        "commit_comment" => &["user", "repo", "sha", "url", "excerpt"],
+        "deployment_status" => &["user", "repo", "environment", "state", "ref", "sha", "url"],
+        "repository" => &["user", "repo", "action", "from", "name", "url"],
+        "milestone" => &[
+            "user", "repo", "action", "title", "open", "closed", "due", "url",
+        ],
+        "workflow_run" => &[
+            "repo", "workflow", "action", "branch", "sha", "title", "url",
+        ],
+        _ => &[],
+    }
+}
+
+/// Message announcing an event, written like `{user} pushed to {branch}`.
+/// Literal braces are written as `{{` and `}}`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MessageTemplate {
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Part {
+    Text(String),
+    Placeholder(&'static str),
+}
+
+impl MessageTemplate {
+    /// Parses a template, only allowing the given placeholders so that
+    /// mistakes are found when loading the configuration.
+    pub fn parse(template: &str, placeholders: &[&'static str]) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| format!("unclosed `{{` in `{}`", template))?;
+                    let name = &rest[..end];
+                    let placeholder = placeholders
+                        .iter()
+                        .find(|placeholder| **placeholder == name)
+                        .ok_or_else(|| {
+                            format!(
+                                "unknown placeholder `{{{}}}`, expected one of {}",
+                                name,
+                                placeholders
+                                    .iter()
+                                    .map(|placeholder| format!("`{{{}}}`", placeholder))
+                                    .collect::<Vec<_>>()
+                                    .join(", "),
+                            )
+                        })?;
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Placeholder(placeholder));
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => return Err(format!("unmatched `}}` in `{}`", template)),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Self { parts })
+    }
+
+    /// Fills in the placeholders. Values are expected to already be
+    /// escaped for the message format, while the template text is used
+    /// as written.
+    pub fn render(&self, mut value: impl FnMut(&str) -> String) -> String {
+        let mut message = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => message += text,
+                Part::Placeholder(name) => message += &value(name),
+            }
+        }
+        message
+    }
+}
+
+/// Templates replacing the built-in messages, by GitHub event type.
+#[derive(Clone, Debug, Default)]
+pub struct MessageTemplates {
+    templates: HashMap<String, MessageTemplate>,
+}
+
+impl MessageTemplates {
+    pub fn get(&self, event: &str) -> Option<&MessageTemplate> {
+        self.templates.get(event)
+    }
+
+    /// Combines templates with more general ones, which are used for
+    /// events not mentioned in `self`.
+    pub fn layered_over(&self, fallback: &MessageTemplates) -> MessageTemplates {
+        let mut templates = fallback.templates.clone();
+        templates.extend(self.templates.iter().map(|(k, v)| (k.clone(), v.clone())));
+        MessageTemplates { templates }
+    }
+}
+
+impl<'de> Deserialize<'de> for MessageTemplates {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct MapVisitor;
+
+        impl<'de> Visitor<'de> for MapVisitor {
+            type Value = MessageTemplates;
+
+            fn expecting(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+                fmt.write_str("a map of event types to templates")
+            }
+
+            fn visit_map<A>(self, mut access: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let mut templates = HashMap::new();
+                while let Some((event, template)) = access.next_entry::<String, String>()? {
+                    if !SUPPORTED_EVENTS.contains(&event.as_str()) {
+                        return Err(A::Error::custom(format_args!(
+                            "unknown event `{}`, supported events are: {}",
+                            event,
+                            SUPPORTED_EVENTS.join(", "),
+                        )));
+                    }
+                    let template = MessageTemplate::parse(&template, placeholders(&event))
+                        .map_err(|e| {
+                            A::Error::custom(format_args!("`{}` template has {}", event, e))
+                        })?;
+                    templates.insert(event, template);
+                }
+                Ok(MessageTemplates { templates })
+            }
+        }
+
+        deserializer.deserialize_map(MapVisitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MessageTemplate, MessageTemplates};
+
+    #[test]
+    fn test_render() {
+        let template =
+            MessageTemplate::parse("{{{user}}} pushed to {branch}", &["user", "branch"]).unwrap();
+        assert_eq!(
+            template.render(|name| name.to_uppercase()),
+            "{USER} pushed to BRANCH",
+        );
+    }
+
+    #[test]
+    fn test_unknown_placeholder() {
+        assert_eq!(
+            MessageTemplate::parse("{usr} pushed", &["user", "branch"]),
+            Err("unknown placeholder `{usr}`, expected one of `{user}`, `{branch}`".into()),
+        );
+    }
+
+    #[test]
+    fn test_unbalanced_braces() {
+        assert!(MessageTemplate::parse("{user", &["user"]).is_err());
+        assert!(MessageTemplate::parse("user}", &["user"]).is_err());
+    }
+
+    #[test]
+    fn test_templates() {
+        let templates: MessageTemplates =
+            serde_json::from_str(r#"{"push": "{user} pushed {count} commits"}"#).unwrap();
+        assert!(templates.get("push").is_some());
+        assert!(templates.get("issues").is_none());
+        let fallback: MessageTemplates =
+            serde_json::from_str(r#"{"push": "{user}", "issues": "{title}"}"#).unwrap();
+        let layered = templates.layered_over(&fallback);
+        assert_eq!(layered.get("push"), templates.get("push"));
+        assert_eq!(layered.get("issues"), fallback.get("issues"));
+    }
+
+    #[test]
+    fn test_invalid_templates() {
+        let error = serde_json::from_str::<MessageTemplates>(r#"{"release": "{branch}"}"#)
+            .err()
+            .unwrap();
+        assert!(
+            error.to_string().starts_with(concat!(
+                "`release` template has unknown placeholder `{branch}`, expected one of ",
+                "`{user}`, `{repo}`, `{tag}`, `{name}`, `{url}`",
+            )),
+            "{}",
+            error,
+        );
+        assert!(serde_json::from_str::<MessageTemplates>(r#"{"watch": "{user}"}"#).is_err());
+    }
+}
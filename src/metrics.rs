@@ -0,0 +1,152 @@
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Metrics of the running bot, exposed at `/metrics`.
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::default);
+
+/// Counters and gauges rendered in the Prometheus text format.
+#[derive(Default)]
+pub struct Metrics {
+    webhooks_received: Mutex<BTreeMap<String, u64>>,
+    messages_sent: Mutex<BTreeMap<String, u64>>,
+    github_api_requests: AtomicU64,
+    github_rate_limit_remaining: Mutex<Option<u32>>,
+}
+
+impl Metrics {
+    /// Counts a delivery whose signature was verified, so that
+    /// unauthenticated requests can't create new labels.
+    pub fn webhook_received(&self, event: &str) {
+        increment(&self.webhooks_received, event);
+    }
+
+    pub fn message_sent(&self, room: &str) {
+        increment(&self.messages_sent, room);
+    }
+
+    pub fn github_api_request(&self) {
+        self.github_api_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_github_rate_limit_remaining(&self, remaining: u32) {
+        *self.github_rate_limit_remaining.lock().unwrap() = Some(remaining);
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        render_labeled(
+            &mut out,
+            "psdevbot_webhooks_received_total",
+            "Webhook deliveries received, by event.",
+            "event",
+            &self.webhooks_received,
+        );
+        render_labeled(
+            &mut out,
+            "psdevbot_messages_sent_total",
+            "Announcements sent to Showdown, by room.",
+            "room",
+            &self.messages_sent,
+        );
+        render_header(
+            &mut out,
+            "psdevbot_github_api_requests_total",
+            "Requests made to the GitHub API.",
+            "counter",
+        );
+        writeln!(
+            out,
+            "psdevbot_github_api_requests_total {}",
+            self.github_api_requests.load(Ordering::Relaxed),
+        )
+        .unwrap();
+        if let Some(remaining) = *self.github_rate_limit_remaining.lock().unwrap() {
+            render_header(
+                &mut out,
+                "psdevbot_github_rate_limit_remaining",
+                "Requests left before reaching the GitHub API rate limit.",
+                "gauge",
+            );
+            writeln!(out, "psdevbot_github_rate_limit_remaining {}", remaining).unwrap();
+        }
+        out
+    }
+}
+
+fn increment(counters: &Mutex<BTreeMap<String, u64>>, label: &str) {
+    let mut counters = counters.lock().unwrap();
+    match counters.get_mut(label) {
+        Some(count) => *count += 1,
+        None => {
+            counters.insert(label.into(), 1);
+        }
+    }
+}
+
+fn render_header(out: &mut String, name: &str, help: &str, kind: &str) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+}
+
+fn render_labeled(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    label: &str,
+    counters: &Mutex<BTreeMap<String, u64>>,
+) {
+    render_header(out, name, help, "counter");
+    for (value, count) in &*counters.lock().unwrap() {
+        let value = value
+            .replace('\\', r"\\")
+            .replace('"', r#"\""#)
+            .replace('\n', r"\n");
+        writeln!(out, "{}{{{}=\"{}\"}} {}", name, label, value, count).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Metrics;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.webhook_received("push");
+        metrics.webhook_received("push");
+        metrics.webhook_received("pull_request");
+        metrics.message_sent("dev");
+        metrics.message_sent("odd\"room");
+        metrics.github_api_request();
+        metrics.set_github_rate_limit_remaining(4999);
+        assert_eq!(
+            metrics.render(),
+            concat!(
+                "# HELP psdevbot_webhooks_received_total Webhook deliveries received, by event.\n",
+                "# TYPE psdevbot_webhooks_received_total counter\n",
+                "psdevbot_webhooks_received_total{event=\"pull_request\"} 1\n",
+                "psdevbot_webhooks_received_total{event=\"push\"} 2\n",
+                "# HELP psdevbot_messages_sent_total Announcements sent to Showdown, by room.\n",
+                "# TYPE psdevbot_messages_sent_total counter\n",
+                "psdevbot_messages_sent_total{room=\"dev\"} 1\n",
+                "psdevbot_messages_sent_total{room=\"odd\\\"room\"} 1\n",
+                "# HELP psdevbot_github_api_requests_total Requests made to the GitHub API.\n",
+                "# TYPE psdevbot_github_api_requests_total counter\n",
+                "psdevbot_github_api_requests_total 1\n",
+                "# HELP psdevbot_github_rate_limit_remaining ",
+                "Requests left before reaching the GitHub API rate limit.\n",
+                "# TYPE psdevbot_github_rate_limit_remaining gauge\n",
+                "psdevbot_github_rate_limit_remaining 4999\n",
+            ),
+        );
+    }
+
+    #[test]
+    fn test_rate_limit_is_omitted_until_known() {
+        let metrics = Metrics::default();
+        assert!(!metrics.render().contains("rate_limit"));
+    }
+}
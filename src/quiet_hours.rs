@@ -0,0 +1,199 @@
+use chrono::{NaiveTime, Timelike};
+use serde::de::{Deserializer, Error};
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Most announcements kept for a room during quiet hours, older ones
+/// are dropped once it's exceeded.
+const BUFFER_SIZE: usize = 20;
+
+const SECONDS_PER_DAY: u32 = 24 * 60 * 60;
+
+/// Time of day during which a room doesn't want announcements, written
+/// like `{"start": "02:00", "end": "08:00"}`. The end may be earlier
+/// than the start, in which case quiet hours span midnight.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct QuietHours {
+    #[serde(deserialize_with = "time")]
+    start: NaiveTime,
+    #[serde(deserialize_with = "time")]
+    end: NaiveTime,
+}
+
+fn time<'de, D>(deserializer: D) -> Result<NaiveTime, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let time = String::deserialize(deserializer)?;
+    NaiveTime::parse_from_str(&time, "%H:%M").map_err(D::Error::custom)
+}
+
+impl QuietHours {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+
+    /// Returns how long it is from `time` until quiet hours end.
+    pub fn remaining(&self, time: NaiveTime) -> Duration {
+        let end = self.end.num_seconds_from_midnight();
+        let time = time.num_seconds_from_midnight();
+        Duration::from_secs(((end + SECONDS_PER_DAY - time) % SECONDS_PER_DAY).into())
+    }
+}
+
+/// How an announcement is sent to a room.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Format {
+    Html,
+    PlainText,
+}
+
+/// Announcements held back during quiet hours, for every room.
+#[derive(Default)]
+pub struct Digests {
+    rooms: Mutex<HashMap<(String, Format), Digest>>,
+}
+
+impl Digests {
+    /// Holds back an announcement, returning whether it's the first one
+    /// for the room in that format, in which case the caller should
+    /// schedule sending the digest.
+    pub fn push(&self, room: &str, format: Format, message: String) -> bool {
+        let mut rooms = self.rooms.lock().unwrap();
+        let key = (room.into(), format);
+        let first = !rooms.contains_key(&key);
+        rooms.entry(key).or_default().push(message);
+        first
+    }
+
+    /// Removes the announcements held back for a room, returning the
+    /// messages to send. HTML announcements are combined into a single
+    /// HTML box, while plain text ones are sent one per line.
+    pub fn take(&self, room: &str, format: Format) -> Vec<String> {
+        match self.rooms.lock().unwrap().remove(&(room.into(), format)) {
+            Some(digest) => digest.render(format),
+            None => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Digest {
+    messages: VecDeque<String>,
+    dropped: usize,
+}
+
+impl Digest {
+    fn push(&mut self, message: String) {
+        if self.messages.len() == BUFFER_SIZE {
+            self.messages.pop_front();
+            self.dropped += 1;
+        }
+        self.messages.push_back(message);
+    }
+
+    fn render(self, format: Format) -> Vec<String> {
+        let mut lines = Vec::with_capacity(self.messages.len() + 1);
+        if self.dropped > 0 {
+            let ellipsis = match format {
+                Format::Html => "&hellip;",
+                Format::PlainText => "\u{2026}",
+            };
+            lines.push(format!(
+                "{}and {} earlier announcements",
+                ellipsis, self.dropped
+            ));
+        }
+        lines.extend(self.messages);
+        match format {
+            Format::Html => vec![lines.join("<br>")],
+            Format::PlainText => lines,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Digests, Format, QuietHours, BUFFER_SIZE};
+    use chrono::NaiveTime;
+    use std::time::Duration;
+
+    fn time(time: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(time, "%H:%M").unwrap()
+    }
+
+    #[test]
+    fn test_quiet_hours() {
+        let quiet_hours: QuietHours =
+            serde_json::from_str(r#"{"start": "02:00", "end": "08:00"}"#).unwrap();
+        assert!(!quiet_hours.contains(time("01:59")));
+        assert!(quiet_hours.contains(time("02:00")));
+        assert!(quiet_hours.contains(time("07:59")));
+        assert!(!quiet_hours.contains(time("08:00")));
+        assert_eq!(
+            quiet_hours.remaining(time("07:30")),
+            Duration::from_secs(30 * 60)
+        );
+    }
+
+    #[test]
+    fn test_quiet_hours_over_midnight() {
+        let quiet_hours: QuietHours =
+            serde_json::from_str(r#"{"start": "22:00", "end": "06:00"}"#).unwrap();
+        assert!(quiet_hours.contains(time("23:00")));
+        assert!(quiet_hours.contains(time("05:00")));
+        assert!(!quiet_hours.contains(time("12:00")));
+        assert_eq!(
+            quiet_hours.remaining(time("23:00")),
+            Duration::from_secs(7 * 60 * 60)
+        );
+    }
+
+    #[test]
+    fn test_invalid_quiet_hours() {
+        assert!(serde_json::from_str::<QuietHours>(r#"{"start": "2am", "end": "08:00"}"#).is_err());
+    }
+
+    #[test]
+    fn test_digest() {
+        let digests = Digests::default();
+        assert!(digests.push("dev", Format::Html, "a".into()));
+        assert!(!digests.push("dev", Format::Html, "b".into()));
+        assert!(digests.push("lobby", Format::Html, "c".into()));
+        assert_eq!(digests.take("dev", Format::Html), ["a<br>b"]);
+        assert!(digests.take("dev", Format::Html).is_empty());
+        assert_eq!(digests.take("lobby", Format::Html), ["c"]);
+    }
+
+    #[test]
+    fn test_plain_text_digest() {
+        let digests = Digests::default();
+        assert!(digests.push("dev", Format::PlainText, "a".into()));
+        assert!(!digests.push("dev", Format::PlainText, "b".into()));
+        assert!(digests.push("dev", Format::Html, "c".into()));
+        assert_eq!(digests.take("dev", Format::PlainText), ["a", "b"]);
+        assert_eq!(digests.take("dev", Format::Html), ["c"]);
+    }
+
+    #[test]
+    fn test_digest_drops_oldest() {
+        let digests = Digests::default();
+        for i in 0..BUFFER_SIZE + 2 {
+            digests.push("dev", Format::Html, i.to_string());
+        }
+        let digest = digests.take("dev", Format::Html).remove(0);
+        assert!(
+            digest.starts_with("&hellip;and 2 earlier announcements<br>2<br>3<br>"),
+            "{}",
+            digest,
+        );
+        assert!(digest.ends_with("<br>21"), "{}", digest);
+    }
+}
@@ -0,0 +1,218 @@
+use crate::unbounded::DelayedSender;
+use futures::channel::mpsc::SendError;
+use showdown::SendMessage;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+/// Times joining a room is attempted before giving up on it.
+const MAX_JOIN_ATTEMPTS: u32 = 3;
+
+/// Delay before retrying a rejected join, multiplied by the number of
+/// attempts made so far.
+const JOIN_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Keeps track of the rooms the bot is in and the ones it asked to
+/// join, so that room membership can follow the configuration and the
+/// rooms the server refused can be reported.
+#[derive(Debug, Default)]
+pub struct RoomJoins {
+    /// Rooms the configuration wants the bot in.
+    wanted: HashSet<String>,
+    pending: HashSet<String>,
+    joined: HashSet<String>,
+    attempts: HashMap<String, u32>,
+}
+
+/// Rooms to join and leave to match the configuration, in a stable
+/// order.
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct RoomChanges {
+    pub join: Vec<String>,
+    pub leave: Vec<String>,
+}
+
+/// What to do after the server refused to let the bot into a room.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Rejection {
+    /// Try joining the room again after the delay.
+    Retry(Duration),
+    /// Too many attempts were made, the room should be reported.
+    GiveUp,
+    /// The bot didn't ask to join the room.
+    Unexpected,
+}
+
+impl RoomJoins {
+    /// Compares the rooms the bot is in or joining with the ones it
+    /// should be in, marking rooms to join as being joined.
+    pub fn reconcile<'a>(&mut self, rooms: impl IntoIterator<Item = &'a str>) -> RoomChanges {
+        self.wanted = rooms.into_iter().map(String::from).collect();
+        let mut changes = RoomChanges::default();
+        for room in &self.wanted {
+            if !self.joined.contains(room) && self.pending.insert(room.clone()) {
+                changes.join.push(room.clone());
+            }
+        }
+        for room in self.joined.iter().chain(&self.pending) {
+            if !self.wanted.contains(room) {
+                changes.leave.push(room.clone());
+            }
+        }
+        for room in &changes.leave {
+            self.joined.remove(room);
+            self.pending.remove(room);
+            self.attempts.remove(room);
+        }
+        changes.join.sort_unstable();
+        changes.leave.sort_unstable();
+        changes
+    }
+
+    pub fn joined(&mut self, room: &str) {
+        self.pending.remove(room);
+        self.attempts.remove(room);
+        self.joined.insert(room.into());
+    }
+
+    /// Records that the server refused to let the bot into a room.
+    pub fn rejected(&mut self, room: &str) -> Rejection {
+        if !self.pending.remove(room) {
+            return Rejection::Unexpected;
+        }
+        let attempts = self.attempts.entry(room.into()).or_insert(0);
+        *attempts += 1;
+        if *attempts < MAX_JOIN_ATTEMPTS {
+            Rejection::Retry(JOIN_RETRY_DELAY * *attempts)
+        } else {
+            Rejection::GiveUp
+        }
+    }
+
+    /// Marks a room as being joined again after a rejection, returning
+    /// whether it's still wanted.
+    pub fn retry(&mut self, room: &str) -> bool {
+        self.wanted.contains(room)
+            && !self.joined.contains(room)
+            && self.pending.insert(room.into())
+    }
+}
+
+/// Joins rooms added to the configuration and leaves removed ones.
+/// Messages still queued for left rooms are dropped.
+pub async fn sync_rooms<'a>(
+    joins: &mut RoomJoins,
+    sender: &DelayedSender,
+    rooms: impl IntoIterator<Item = &'a str>,
+) -> Result<(), SendError> {
+    let changes = joins.reconcile(rooms);
+    for room in &changes.leave {
+        sender.set_left(room, true);
+        sender
+            .send(SendMessage::global_command(format_args!("leave {}", room)))
+            .await?;
+    }
+    for room in &changes.join {
+        join(sender, room).await?;
+    }
+    Ok(())
+}
+
+pub async fn join(sender: &DelayedSender, room: &str) -> Result<(), SendError> {
+    sender.set_left(room, false);
+    sender
+        .send(SendMessage::global_command(format_args!("join {}", room)))
+        .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::{sync_rooms, Rejection, RoomChanges, RoomJoins, JOIN_RETRY_DELAY};
+    use crate::unbounded::DelayedSender;
+    use futures::channel::mpsc;
+    use futures::StreamExt;
+    use showdown::SendMessage;
+    use std::time::Duration;
+
+    fn changes(join: &[&str], leave: &[&str]) -> RoomChanges {
+        RoomChanges {
+            join: join.iter().map(|room| room.to_string()).collect(),
+            leave: leave.iter().map(|room| room.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_room_joins() {
+        let mut joins = RoomJoins::default();
+        assert_eq!(
+            joins.reconcile(vec!["lobby", "dev", "typo"]),
+            changes(&["dev", "lobby", "typo"], &[]),
+        );
+        joins.joined("dev");
+        assert_eq!(joins.rejected("dev"), Rejection::Unexpected);
+        assert_eq!(joins.rejected("typo"), Rejection::Retry(JOIN_RETRY_DELAY));
+        assert_eq!(joins.rejected("typo"), Rejection::Unexpected);
+        assert_eq!(joins.rejected("unrelated"), Rejection::Unexpected);
+    }
+
+    #[test]
+    fn test_reconcile() {
+        let mut joins = RoomJoins::default();
+        joins.reconcile(vec!["dev", "lobby"]);
+        joins.joined("dev");
+        assert_eq!(
+            joins.reconcile(vec!["dev", "tours"]),
+            changes(&["tours"], &["lobby"]),
+        );
+        assert_eq!(joins.reconcile(vec!["dev", "tours"]), changes(&[], &[]));
+        assert_eq!(joins.reconcile(vec![]), changes(&[], &["dev", "tours"]));
+    }
+
+    #[test]
+    fn test_retries() {
+        let mut joins = RoomJoins::default();
+        joins.reconcile(vec!["dev", "lobby"]);
+        assert_eq!(joins.rejected("dev"), Rejection::Retry(JOIN_RETRY_DELAY));
+        assert!(joins.retry("dev"));
+        assert!(!joins.retry("dev"));
+        assert_eq!(
+            joins.rejected("dev"),
+            Rejection::Retry(JOIN_RETRY_DELAY * 2),
+        );
+        assert!(joins.retry("dev"));
+        assert_eq!(joins.rejected("dev"), Rejection::GiveUp);
+        // Rooms removed from the configuration aren't retried.
+        assert_eq!(joins.rejected("lobby"), Rejection::Retry(JOIN_RETRY_DELAY));
+        joins.reconcile(vec!["dev"]);
+        assert!(!joins.retry("lobby"));
+    }
+
+    #[tokio::test]
+    async fn test_sync_rooms() {
+        let (tx, rx) = mpsc::unbounded();
+        let sender = DelayedSender::new(tx, Duration::from_millis(1));
+        let mut joins = RoomJoins::default();
+        sync_rooms(&mut joins, &sender, vec!["dev", "lobby"])
+            .await
+            .unwrap();
+        joins.joined("dev");
+        joins.joined("lobby");
+        sender
+            .send_to_room("lobby", SendMessage::global_command("queued"))
+            .await
+            .unwrap();
+        sync_rooms(&mut joins, &sender, vec!["dev", "tours"])
+            .await
+            .unwrap();
+        sender.close().await;
+        let sent: Vec<_> = rx.collect().await;
+        assert_eq!(
+            sent,
+            [
+                SendMessage::global_command("join dev"),
+                SendMessage::global_command("join lobby"),
+                SendMessage::global_command("leave lobby"),
+                SendMessage::global_command("join tours"),
+            ],
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use crate::config::{SharedConfig, UsernameAliases};
+use crate::webhook::{DELIVERIES, MILESTONES};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use tokio::{task, time};
+use tracing::warn;
+
+/// How often the state is saved, so that little is lost when the bot
+/// doesn't get to shut down cleanly.
+const SAVE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// What the bot remembers across restarts, saved to the file pointed to
+/// by `PSDEVBOT_STATE_PATH`.
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+struct State {
+    /// Ids of the latest deliveries, oldest first.
+    deliveries: Vec<String>,
+    /// Global aliases set with `~alias`.
+    username_aliases: UsernameAliases,
+    /// Logins whose configured alias was removed with `~alias`.
+    removed_aliases: Vec<String>,
+    /// Last star milestone announced, by repository.
+    star_milestones: HashMap<String, u32>,
+}
+
+impl State {
+    /// Reads the state, starting fresh when the file is missing or
+    /// can't be parsed, as losing it only risks repeated announcements.
+    fn load(path: &Path) -> Self {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                warn!(
+                    "Starting without state, cannot read {}: {}",
+                    path.display(),
+                    e
+                );
+                return Self::default();
+            }
+        };
+        serde_json::from_str(&contents).unwrap_or_else(|e| {
+            warn!(
+                "Starting without state, {} is invalid: {}",
+                path.display(),
+                e
+            );
+            Self::default()
+        })
+    }
+
+    /// Writes the state to a temporary file first, so that a crash while
+    /// saving doesn't leave a truncated file behind.
+    fn save(&self, path: &Path) -> io::Result<()> {
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, serde_json::to_vec(self)?)?;
+        fs::rename(temporary, path)
+    }
+}
+
+/// Restores the state saved by an earlier run, if a state file is
+/// configured.
+pub fn restore(config: &SharedConfig) {
+    let path = match &config.get().state_path {
+        Some(path) => path.clone(),
+        None => return,
+    };
+    let State {
+        deliveries,
+        username_aliases,
+        removed_aliases,
+        star_milestones,
+    } = State::load(&path);
+    DELIVERIES.restore(deliveries);
+    MILESTONES.restore(star_milestones);
+    config.update_username_aliases(|aliases| {
+        for login in &removed_aliases {
+            aliases.remove(login);
+        }
+        for (login, alias) in username_aliases.iter() {
+            aliases.insert(login.into(), alias.into());
+        }
+    });
+}
+
+/// Saves the state, if a state file is configured, writing it from a
+/// blocking thread. Failures are only logged.
+pub async fn save(config: &SharedConfig) {
+    let path = match &config.get().state_path {
+        Some(path) => path.clone(),
+        None => return,
+    };
+    let (username_aliases, removed_aliases) = config.username_alias_changes();
+    let state = State {
+        deliveries: DELIVERIES.ids(),
+        username_aliases,
+        removed_aliases,
+        star_milestones: MILESTONES.all(),
+    };
+    let result = task::spawn_blocking(move || state.save(&path).map_err(|e| (path, e))).await;
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err((path, e))) => warn!("Cannot save state to {}: {}", path.display(), e),
+        Err(e) => warn!("Saving state failed: {}", e),
+    }
+}
+
+/// Saves the state every `SAVE_INTERVAL`.
+pub async fn save_periodically(config: &SharedConfig) {
+    let mut interval = time::interval(SAVE_INTERVAL);
+    // The first tick completes immediately, when there's nothing new.
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        save(config).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::State;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    fn test_round_trip() {
+        let path = env::temp_dir().join(format!("psdevbot-{}-state.json", process::id()));
+        let mut state = State {
+            deliveries: vec!["a".into(), "b".into()],
+            removed_aliases: vec!["Zarel".into()],
+            ..State::default()
+        };
+        state
+            .username_aliases
+            .insert("Marty-D".into(), "Marty".into());
+        state.save(&path).unwrap();
+        let loaded = State::load(&path);
+        assert_eq!(loaded.deliveries, ["a", "b"]);
+        assert_eq!(loaded.username_aliases.get("marty-d"), "Marty");
+        assert_eq!(loaded.removed_aliases, ["Zarel"]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_missing_or_invalid_state() {
+        let path = env::temp_dir().join(format!("psdevbot-{}-invalid.json", process::id()));
+        assert!(State::load(&path).deliveries.is_empty());
+        fs::write(&path, "{\"deliveries\": [").unwrap();
+        assert!(State::load(&path).deliveries.is_empty());
+        fs::remove_file(path).unwrap();
+    }
+}
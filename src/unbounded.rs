@@ -1,39 +1,184 @@
 use futures::channel::mpsc::{self, SendError};
-use futures::{Sink, SinkExt};
-use log::info;
+use futures::{Sink, SinkExt, StreamExt};
 use showdown::SendMessage;
-use tokio::time::Duration;
-use tokio_stream::StreamExt;
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+use tokio::time::{self, Duration, Instant};
+use tracing::{info, warn};
 
+/// Number of messages waiting for a room before more are dropped.
+const ROOM_QUEUE_SIZE: usize = 50;
+
+/// Queue of outgoing messages, sent with a delay between them, as
+/// Showdown throttles connections sending messages too quickly.
+///
+/// Every room has its own queue and rooms take turns, so that a backlog
+/// of announcements for one room doesn't hold back the others. Messages
+/// which aren't for a room, like joining rooms, go first.
 #[derive(Clone, Debug)]
 pub struct DelayedSender {
-    sender: mpsc::UnboundedSender<SendMessage>,
+    sender: mpsc::UnboundedSender<Queued>,
+    /// Closed once every queued message was sent.
+    finished: watch::Receiver<()>,
+    /// Rooms the bot left, whose queued messages are dropped.
+    left_rooms: Arc<Mutex<HashSet<String>>>,
+}
+
+#[derive(Debug)]
+struct Queued {
+    room: Option<String>,
+    message: SendMessage,
+}
+
+/// Messages waiting for their turn.
+#[derive(Default)]
+struct Queues {
+    global: VecDeque<SendMessage>,
+    /// Rooms with waiting messages, in the order they take turns.
+    rooms: VecDeque<(String, VecDeque<SendMessage>)>,
+}
+
+impl Queues {
+    fn is_empty(&self) -> bool {
+        self.global.is_empty() && self.rooms.is_empty()
+    }
+
+    fn push(&mut self, Queued { room, message }: Queued) {
+        let room = match room {
+            Some(room) => room,
+            None => return self.global.push_back(message),
+        };
+        match self.rooms.iter_mut().find(|(queued, _)| *queued == room) {
+            Some((_, queue)) if queue.len() >= ROOM_QUEUE_SIZE => {
+                warn!(
+                    "Dropped message for {}, its queue is full: {:?}",
+                    room, message
+                );
+            }
+            Some((_, queue)) => queue.push_back(message),
+            None => self.rooms.push_back((room, VecDeque::from(vec![message]))),
+        }
+    }
+
+    /// Takes the next message, moving its room to the end of the line.
+    fn pop(&mut self) -> Option<(Option<String>, SendMessage)> {
+        if let Some(message) = self.global.pop_front() {
+            return Some((None, message));
+        }
+        let (room, mut queue) = self.rooms.pop_front()?;
+        let message = queue.pop_front()?;
+        if !queue.is_empty() {
+            self.rooms.push_back((room.clone(), queue));
+        }
+        Some((Some(room), message))
+    }
 }
 
 impl DelayedSender {
-    pub fn new(mut showdown_sender: impl Sink<SendMessage> + Send + Unpin + 'static) -> Self {
-        let (tx, rx) = mpsc::unbounded::<SendMessage>();
-        let rx = rx.throttle(Duration::from_millis(700));
+    pub fn new(
+        mut showdown_sender: impl Sink<SendMessage> + Send + Unpin + 'static,
+        interval: Duration,
+    ) -> Self {
+        let (tx, mut rx) = mpsc::unbounded::<Queued>();
+        let (finished_tx, finished) = watch::channel(());
+        let left_rooms = Arc::new(Mutex::new(HashSet::<String>::new()));
+        let left = Arc::clone(&left_rooms);
         tokio::spawn(async move {
-            tokio::pin!(rx);
-            while let Some(message) = rx.next().await {
-                info!("Sent message: {:?}", message);
-                if showdown_sender.send(message).await.is_err() {
-                    return;
+            let _finished = finished_tx;
+            let mut queues = Queues::default();
+            let mut open = true;
+            let mut next_send = Instant::now();
+            loop {
+                if queues.is_empty() {
+                    match rx.next().await {
+                        Some(queued) => queues.push(queued),
+                        None => return,
+                    }
+                    continue;
+                }
+                // Timers round up to the next millisecond, so they are
+                // only used when there's something to wait for.
+                let turn = async {
+                    if Instant::now() < next_send {
+                        time::sleep_until(next_send).await;
+                    }
+                };
+                tokio::select! {
+                    biased;
+                    queued = rx.next(), if open => match queued {
+                        Some(queued) => queues.push(queued),
+                        None => open = false,
+                    },
+                    _ = turn => {
+                        let (room, message) = match queues.pop() {
+                            Some(next) => next,
+                            None => continue,
+                        };
+                        if let Some(room) = room {
+                            if left.lock().unwrap().contains(&room) {
+                                info!(
+                                    "Dropped message for {}, which was left: {:?}",
+                                    room, message
+                                );
+                                continue;
+                            }
+                        }
+                        info!("Sent message: {:?}", message);
+                        if showdown_sender.send(message).await.is_err() {
+                            return;
+                        }
+                        next_send = Instant::now() + interval;
+                    }
                 }
             }
         });
-        Self { sender: tx }
+        Self {
+            sender: tx,
+            finished,
+            left_rooms,
+        }
     }
 
     pub async fn send(&self, message: SendMessage) -> Result<(), SendError> {
-        (&self.sender).send(message).await
+        (&self.sender)
+            .send(Queued {
+                room: None,
+                message,
+            })
+            .await
+    }
+
+    /// Queues a message for a room, which is dropped instead of sent if
+    /// the bot leaves the room before the message's turn comes, or if too
+    /// many messages are already waiting for the room.
+    pub async fn send_to_room(&self, room: &str, message: SendMessage) -> Result<(), SendError> {
+        let room = Some(room.into());
+        (&self.sender).send(Queued { room, message }).await
+    }
+
+    /// Records whether the bot is in a room, see
+    /// [`DelayedSender::send_to_room`].
+    pub fn set_left(&self, room: &str, left: bool) {
+        let mut left_rooms = self.left_rooms.lock().unwrap();
+        if left {
+            left_rooms.insert(room.into());
+        } else {
+            left_rooms.remove(room);
+        }
+    }
+
+    /// Stops accepting messages and waits until the queued ones are sent.
+    pub async fn close(&self) {
+        self.sender.close_channel();
+        let mut finished = self.finished.clone();
+        while finished.changed().await.is_ok() {}
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::DelayedSender;
+    use super::{DelayedSender, ROOM_QUEUE_SIZE};
     use futures::channel::mpsc;
     use futures::StreamExt;
     use showdown::SendMessage;
@@ -46,7 +191,7 @@ mod test {
         // Spawning a task is necessary to workaround https://github.com/tokio-rs/tokio/issues/3108
         tokio::spawn(async {
             let (tx, mut rx) = mpsc::unbounded();
-            let sender = DelayedSender::new(tx);
+            let sender = DelayedSender::new(tx, Duration::from_millis(700));
             let now = Instant::now();
             let message = SendMessage::global_command("test");
             sender.send(message.clone()).await?;
@@ -63,7 +208,7 @@ mod test {
         // Spawning a task is necessary to workaround https://github.com/tokio-rs/tokio/issues/3108
         tokio::spawn(async {
             let (tx, mut rx) = mpsc::unbounded();
-            let sender = DelayedSender::new(tx);
+            let sender = DelayedSender::new(tx, Duration::from_millis(700));
             let start = Instant::now();
             let a_message = SendMessage::global_command("a");
             sender.send(a_message.clone()).await?;
@@ -77,4 +222,118 @@ mod test {
         })
         .await?
     }
+
+    #[tokio::test]
+    async fn close_waits_for_queued_messages() -> Result<(), Box<dyn Error + Send + Sync>> {
+        time::pause();
+        // Spawning a task is necessary to workaround https://github.com/tokio-rs/tokio/issues/3108
+        tokio::spawn(async {
+            let (tx, rx) = mpsc::unbounded();
+            let sender = DelayedSender::new(tx, Duration::from_millis(700));
+            let start = Instant::now();
+            for message in ["a", "b"] {
+                sender.send(SendMessage::global_command(message)).await?;
+            }
+            sender.close().await;
+            assert!(Instant::now() >= start + Duration::from_millis(700));
+            assert!(sender.send(SendMessage::global_command("c")).await.is_err());
+            let sent: Vec<_> = rx.collect().await;
+            assert_eq!(
+                sent,
+                [
+                    SendMessage::global_command("a"),
+                    SendMessage::global_command("b"),
+                ],
+            );
+            Ok(())
+        })
+        .await?
+    }
+
+    #[tokio::test]
+    async fn messages_for_left_rooms_are_dropped() -> Result<(), Box<dyn Error + Send + Sync>> {
+        time::pause();
+        // Spawning a task is necessary to workaround https://github.com/tokio-rs/tokio/issues/3108
+        tokio::spawn(async {
+            let (tx, rx) = mpsc::unbounded();
+            let sender = DelayedSender::new(tx, Duration::from_millis(700));
+            for room in ["dev", "lobby", "dev"] {
+                let message = SendMessage::global_command(room);
+                sender.send_to_room(room, message).await?;
+            }
+            sender.set_left("dev", true);
+            sender.close().await;
+            let sent: Vec<_> = rx.collect().await;
+            assert_eq!(sent, [SendMessage::global_command("lobby")]);
+            Ok(())
+        })
+        .await?
+    }
+
+    #[tokio::test]
+    async fn sender_uses_configured_interval() -> Result<(), Box<dyn Error + Send + Sync>> {
+        time::pause();
+        // Spawning a task is necessary to workaround https://github.com/tokio-rs/tokio/issues/3108
+        tokio::spawn(async {
+            let (tx, mut rx) = mpsc::unbounded();
+            let sender = DelayedSender::new(tx, Duration::from_millis(600));
+            let start = Instant::now();
+            for message in ["a", "b", "c"] {
+                sender.send(SendMessage::global_command(message)).await?;
+            }
+            for message in ["a", "b", "c"] {
+                assert_eq!(rx.next().await, Some(SendMessage::global_command(message)));
+            }
+            assert!(Instant::now() >= start + Duration::from_millis(1200));
+            Ok(())
+        })
+        .await?
+    }
+
+    #[tokio::test]
+    async fn rooms_take_turns() -> Result<(), Box<dyn Error + Send + Sync>> {
+        time::pause();
+        // Spawning a task is necessary to workaround https://github.com/tokio-rs/tokio/issues/3108
+        tokio::spawn(async {
+            let (tx, rx) = mpsc::unbounded();
+            let sender = DelayedSender::new(tx, Duration::from_millis(700));
+            for (room, message) in [("dev", "a"), ("dev", "b"), ("dev", "c"), ("lobby", "d")] {
+                sender
+                    .send_to_room(room, SendMessage::global_command(message))
+                    .await?;
+            }
+            sender.send(SendMessage::global_command("e")).await?;
+            sender.close().await;
+            let sent: Vec<_> = rx.collect().await;
+            assert_eq!(
+                sent,
+                ["e", "a", "d", "b", "c"].map(SendMessage::global_command),
+            );
+            Ok(())
+        })
+        .await?
+    }
+
+    #[tokio::test]
+    async fn full_room_queues_drop_messages() -> Result<(), Box<dyn Error + Send + Sync>> {
+        time::pause();
+        // Spawning a task is necessary to workaround https://github.com/tokio-rs/tokio/issues/3108
+        tokio::spawn(async {
+            let (tx, rx) = mpsc::unbounded();
+            let sender = DelayedSender::new(tx, Duration::from_millis(700));
+            for _ in 0..ROOM_QUEUE_SIZE + 5 {
+                let message = SendMessage::global_command("dev");
+                sender.send_to_room("dev", message).await?;
+            }
+            sender
+                .send_to_room("lobby", SendMessage::global_command("lobby"))
+                .await?;
+            sender.close().await;
+            let sent: Vec<_> = rx.collect().await;
+            assert_eq!(sent.len(), ROOM_QUEUE_SIZE + 1);
+            assert_eq!(sent[1], SendMessage::global_command("lobby"));
+            Ok(())
+        })
+        .await?
+    }
 }
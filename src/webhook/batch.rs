@@ -0,0 +1,412 @@
+use super::schema::{ForkEvent, MilestoneEvent, PullRequestReviewCommentEvent, PushEvent};
+use futures::FutureExt;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time;
+
+/// Events waiting to be announced, shared by every webhook route so
+/// that shutting down can flush them.
+#[derive(Default)]
+pub struct PendingEvents {
+    pub pushes: Batches<PushEvent<'static>>,
+    pub review_comments: Batches<PullRequestReviewCommentEvent<'static>>,
+    pub forks: Batches<ForkEvent<'static>>,
+    pub milestone_edits: Batches<MilestoneEvent<'static>>,
+}
+
+impl PendingEvents {
+    /// Sends every pending batch, returning once they were sent.
+    pub async fn flush(&self) {
+        self.pushes.flush().await;
+        self.review_comments.flush().await;
+        self.forks.flush().await;
+        self.milestone_edits.flush().await;
+    }
+}
+
+/// Events which are announced together when several arrive in a row.
+pub trait Batch {
+    /// Identifies the events merged together.
+    fn key(&self) -> String;
+
+    /// Adds a later event with the same key.
+    fn merge(&mut self, later: Self);
+}
+
+impl Batch for PushEvent<'static> {
+    /// Pushes are merged by repository and ref.
+    fn key(&self) -> String {
+        format!("{} {}", self.repository.full_name, self.git_ref())
+    }
+
+    fn merge(&mut self, later: Self) {
+        PushEvent::merge(self, later);
+    }
+}
+
+impl Batch for PullRequestReviewCommentEvent<'static> {
+    fn key(&self) -> String {
+        self.batch_key()
+    }
+
+    fn merge(&mut self, later: Self) {
+        PullRequestReviewCommentEvent::merge(self, later);
+    }
+}
+
+impl Batch for ForkEvent<'static> {
+    fn key(&self) -> String {
+        self.batch_key()
+    }
+
+    fn merge(&mut self, later: Self) {
+        ForkEvent::merge(self, later);
+    }
+}
+
+impl Batch for MilestoneEvent<'static> {
+    fn key(&self) -> String {
+        self.batch_key()
+    }
+
+    fn merge(&mut self, later: Self) {
+        MilestoneEvent::merge(self, later);
+    }
+}
+
+/// Events held back for the batch window, by key, so that consecutive
+/// events are announced together.
+pub struct Batches<T> {
+    pending: Mutex<HashMap<String, T>>,
+    flush: watch::Sender<bool>,
+    flushing: watch::Receiver<bool>,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl<T> Default for Batches<T> {
+    fn default() -> Self {
+        let (flush, flushing) = watch::channel(false);
+        Self {
+            pending: Mutex::new(HashMap::new()),
+            flush,
+            flushing,
+            tasks: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl<T: Batch + Send + 'static> Batches<T> {
+    /// Adds an event to the batch for its key. The first event of a
+    /// batch starts the window, after which `send` is called with every
+    /// event received in the meantime merged into it. Later events are
+    /// merged into the pending batch and their `send` is dropped.
+    pub fn add<F, Fut>(&'static self, event: T, window: Duration, send: F)
+    where
+        F: FnOnce(T) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let key = event.key();
+        match self.pending.lock().unwrap().entry(key.clone()) {
+            Entry::Occupied(mut batch) => {
+                batch.get_mut().merge(event);
+                return;
+            }
+            Entry::Vacant(batch) => {
+                batch.insert(event);
+            }
+        }
+        let mut flushing = self.flushing.clone();
+        let task = tokio::spawn(async move {
+            let flushed = async {
+                while !*flushing.borrow() {
+                    if flushing.changed().await.is_err() {
+                        break;
+                    }
+                }
+            };
+            tokio::select! {
+                _ = time::sleep(window) => {}
+                _ = flushed => {}
+            }
+            let batch = self.pending.lock().unwrap().remove(&key);
+            if let Some(batch) = batch {
+                send(batch).await;
+            }
+        });
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.retain_mut(|task| task.now_or_never().is_none());
+        tasks.push(task);
+    }
+
+    /// Removes the pending batch for a key, so that it can be announced
+    /// before an event which can't wait for the window to end.
+    pub fn take(&self, key: &str) -> Option<T> {
+        self.pending.lock().unwrap().remove(key)
+    }
+
+    /// Sends every pending batch without waiting for its window to end,
+    /// returning once they were sent. Events added afterwards are sent
+    /// immediately.
+    pub async fn flush(&self) {
+        self.flush.send(true).ok();
+        let tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        for task in tasks {
+            task.await.ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Batch, Batches};
+    use crate::config::UsernameAliases;
+    use crate::webhook::schema::{PullRequestReviewCommentEvent, PushEvent};
+    use futures::channel::mpsc;
+    use futures::StreamExt;
+    use serde::Deserialize;
+    use std::time::Duration;
+    use tokio::time;
+
+    fn push(before: &str, after: &str) -> PushEvent<'static> {
+        push_to("master", before, after)
+    }
+
+    /// Parses a payload built with `json!`, leaking it as events borrow
+    /// from their payload.
+    fn from_json<T: Deserialize<'static>>(value: serde_json::Value) -> T {
+        serde_json::from_str(Box::leak(value.to_string().into_boxed_str())).unwrap()
+    }
+
+    fn push_to(branch: &str, before: &str, after: &str) -> PushEvent<'static> {
+        from_json(serde_json::json!({
+            "ref": format!("refs/heads/{}", branch),
+            "before": before,
+            "after": after,
+            "commits": [{
+                "id": after,
+                "message": "Update",
+                "author": {"name": "Zarel"},
+                "url": "https://github.com/smogon/pokemon-showdown/commit/1"
+            }],
+            "pusher": {"name": "Zarel"},
+            "repository": {
+                "name": "pokemon-showdown",
+                "full_name": "smogon/pokemon-showdown",
+                "html_url": "https://github.com/smogon/pokemon-showdown"
+            }
+        }))
+    }
+
+    /// Adds a push, sending the announcement of its batch once the
+    /// batch is sent.
+    fn add(
+        batches: &'static Batches<PushEvent<'static>>,
+        push: PushEvent<'static>,
+        tx: &mpsc::UnboundedSender<String>,
+    ) {
+        let tx = tx.clone();
+        batches.add(push, Duration::from_secs(5), move |push| async move {
+            tx.unbounded_send(push.to_plain_text(&UsernameAliases::default()))
+                .unwrap();
+        });
+    }
+
+    fn review_comment(
+        reviewer: &str,
+        review: u64,
+        id: u64,
+    ) -> PullRequestReviewCommentEvent<'static> {
+        let sender = serde_json::json!({
+            "login": reviewer,
+            "html_url": format!("https://github.com/{}", reviewer),
+        });
+        from_json(serde_json::json!({
+            "action": "created",
+            "comment": {
+                "html_url": format!(
+                    "https://github.com/smogon/pokemon-showdown/pull/1#discussion_r{}",
+                    id,
+                ),
+                "body": "Typo",
+                "pull_request_review_id": review,
+            },
+            "pull_request": {
+                "number": 1,
+                "html_url": "https://github.com/smogon/pokemon-showdown/pull/1",
+                "title": "Fix Spikes"
+            },
+            "repository": {
+                "name": "pokemon-showdown",
+                "full_name": "smogon/pokemon-showdown",
+                "html_url": "https://github.com/smogon/pokemon-showdown"
+            },
+            "sender": sender
+        }))
+    }
+
+    fn add_review_comment(
+        batches: &'static Batches<PullRequestReviewCommentEvent<'static>>,
+        comment: PullRequestReviewCommentEvent<'static>,
+        tx: &mpsc::UnboundedSender<String>,
+    ) {
+        let tx = tx.clone();
+        batches.add(comment, Duration::from_secs(5), move |comment| async move {
+            tx.unbounded_send(comment.to_plain_text(&UsernameAliases::default()))
+                .unwrap();
+        });
+    }
+
+    fn announcement(commits: &str, before: &str, after: &str) -> Option<String> {
+        Some(format!(
+            "[server] Zarel pushed {} to master: \
+             https://github.com/smogon/pokemon-showdown/compare/{}...{}",
+            commits, before, after,
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_pushes_within_window_are_merged() {
+        time::pause();
+        let batches = Box::leak(Box::new(Batches::default()));
+        let (tx, mut rx) = mpsc::unbounded();
+        add(batches, push("1111111", "2222222"), &tx);
+        time::advance(Duration::from_secs(3)).await;
+        add(batches, push("2222222", "3333333"), &tx);
+        time::advance(Duration::from_secs(3)).await;
+        assert_eq!(
+            rx.next().await,
+            announcement("2 commits", "1111111", "3333333"),
+        );
+        drop(tx);
+        assert_eq!(rx.next().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_pushes_outside_window_are_separate() {
+        time::pause();
+        let batches = Box::leak(Box::new(Batches::default()));
+        let (tx, mut rx) = mpsc::unbounded();
+        add(batches, push("1111111", "2222222"), &tx);
+        time::advance(Duration::from_secs(6)).await;
+        assert_eq!(
+            rx.next().await,
+            announcement("1 commit", "1111111", "2222222"),
+        );
+        add(batches, push("2222222", "3333333"), &tx);
+        time::advance(Duration::from_secs(6)).await;
+        assert_eq!(
+            rx.next().await,
+            announcement("1 commit", "2222222", "3333333"),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pushes_to_other_branches_are_separate() {
+        time::pause();
+        let batches = Box::leak(Box::new(Batches::default()));
+        let (tx, mut rx) = mpsc::unbounded();
+        add(batches, push("1111111", "2222222"), &tx);
+        add(batches, push_to("beta", "1111111", "3333333"), &tx);
+        time::advance(Duration::from_secs(6)).await;
+        let mut sent = vec![rx.next().await.unwrap(), rx.next().await.unwrap()];
+        sent.sort();
+        assert_eq!(
+            sent,
+            [
+                "[server] Zarel pushed 1 commit to beta: \
+                 https://github.com/smogon/pokemon-showdown/compare/1111111...3333333",
+                "[server] Zarel pushed 1 commit to master: \
+                 https://github.com/smogon/pokemon-showdown/compare/1111111...2222222",
+            ],
+        );
+    }
+
+    #[tokio::test]
+    async fn test_review_comments_are_merged_per_review() {
+        time::pause();
+        let batches = Box::leak(Box::new(Batches::default()));
+        let (tx, mut rx) = mpsc::unbounded();
+        add_review_comment(batches, review_comment("Zarel", 10, 1), &tx);
+        add_review_comment(batches, review_comment("Marty-D", 20, 2), &tx);
+        time::advance(Duration::from_secs(2)).await;
+        add_review_comment(batches, review_comment("Zarel", 10, 3), &tx);
+        add_review_comment(batches, review_comment("Zarel", 10, 4), &tx);
+        add_review_comment(batches, review_comment("Zarel", 40, 6), &tx);
+        time::advance(Duration::from_secs(4)).await;
+        let mut sent = vec![rx.next().await.unwrap(), rx.next().await.unwrap()];
+        sent.sort();
+        assert_eq!(
+            sent,
+            [
+                "[server] Marty-D commented on PR#1 (Fix Spikes): Typo \
+                 https://github.com/smogon/pokemon-showdown/pull/1#discussion_r2",
+                "[server] Zarel left 3 review comments on PR#1 (Fix Spikes): \
+                 https://github.com/smogon/pokemon-showdown/pull/1#pullrequestreview-10",
+            ],
+        );
+        time::advance(Duration::from_secs(2)).await;
+        assert_eq!(
+            rx.next().await.as_deref(),
+            Some(
+                "[server] Zarel commented on PR#1 (Fix Spikes): Typo \
+                 https://github.com/smogon/pokemon-showdown/pull/1#discussion_r6"
+            ),
+        );
+        add_review_comment(batches, review_comment("Zarel", 30, 5), &tx);
+        time::advance(Duration::from_secs(6)).await;
+        assert_eq!(
+            rx.next().await.as_deref(),
+            Some(
+                "[server] Zarel commented on PR#1 (Fix Spikes): Typo \
+                 https://github.com/smogon/pokemon-showdown/pull/1#discussion_r5"
+            ),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_take() {
+        time::pause();
+        let batches = Box::leak(Box::new(Batches::default()));
+        let (tx, mut rx) = mpsc::unbounded();
+        add(batches, push("1111111", "2222222"), &tx);
+        add(batches, push_to("beta", "1111111", "3333333"), &tx);
+        let master = push("2222222", "4444444");
+        let taken = batches.take(&master.key()).unwrap();
+        assert_eq!(taken.head(), "2222222");
+        assert!(batches.take(&master.key()).is_none());
+        time::advance(Duration::from_secs(6)).await;
+        drop(tx);
+        assert_eq!(
+            rx.next().await.as_deref(),
+            Some(
+                "[server] Zarel pushed 1 commit to beta: \
+                 https://github.com/smogon/pokemon-showdown/compare/1111111...3333333"
+            ),
+        );
+        assert_eq!(rx.next().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_flush() {
+        time::pause();
+        let batches = Box::leak(Box::new(Batches::default()));
+        let (tx, mut rx) = mpsc::unbounded();
+        add(batches, push("1111111", "2222222"), &tx);
+        batches.flush().await;
+        assert_eq!(
+            rx.next().await,
+            announcement("1 commit", "1111111", "2222222"),
+        );
+        add(batches, push("2222222", "3333333"), &tx);
+        batches.flush().await;
+        assert_eq!(
+            rx.next().await,
+            announcement("1 commit", "2222222", "3333333"),
+        );
+    }
+}
@@ -1,146 +1,1313 @@
+mod batch;
+mod outbox;
+mod renames;
 mod schema;
+mod stars;
+mod workflows;
 
-use crate::config::{Config, RoomConfigurationRef, UsernameAliases};
-use crate::unbounded::DelayedSender;
+use crate::admin::NOTICES;
+use crate::config::{Config, RoomConfigurationRef, Secrets, SharedConfig};
+use crate::connection::{Connection, Webhook};
+use crate::metrics::METRICS;
+use crate::quiet_hours::{Digests, Format};
+use batch::Batch;
+pub use batch::PendingEvents;
 use futures::channel::oneshot;
 use futures::FutureExt;
 use hmac::{Hmac, Mac, NewMac};
-use log::info;
-use schema::{InitialPayload, PullRequestEvent, PushEvent, PushEventContext};
+use lru::LruCache;
+use once_cell::sync::Lazy;
+use outbox::Outbox;
+use renames::RenamedRepositories;
+use schema::{
+    gitlab, Check, CheckSuiteEvent, CommitCommentEvent, CreateEvent, DeleteEvent,
+    DeploymentStatusEvent, ForkEvent, GollumEvent, InitialPayload, IssueCommentEvent, IssueEvent,
+    MilestoneEvent, PingEvent, PullRequestEvent, PullRequestReviewCommentEvent,
+    PullRequestReviewEvent, PushEvent, PushEventContext, ReleaseEvent, RepositoryEvent, StarEvent,
+    StatusEvent, WorkflowRunEvent,
+};
 use serde::Deserialize;
+use sha1::Sha1;
 use sha2::Sha256;
 use showdown::{RoomId, SendMessage};
+use stars::AnnouncedMilestones;
 use std::collections::HashSet;
 use std::fmt::{self, Debug, Display, Formatter};
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::time::Duration;
-use tokio::time;
+use tokio::time::{self, Instant};
+use tracing::{info, info_span, warn, Instrument, Span};
+use uuid::Uuid;
+use warp::http::StatusCode;
 use warp::hyper::body::Bytes;
+use warp::path::FullPath;
 use warp::reject::Reject;
-use warp::{path, Filter, Rejection};
+use warp::{path, Filter, Rejection, Reply};
+use workflows::{CheckState, MergeCheck, MergeChecks, WorkflowConclusions};
 
-pub fn start_server(config: &'static Config, sender: Arc<DelayedSender>) -> oneshot::Sender<()> {
+/// GitHub events the bot knows how to announce.
+pub const SUPPORTED_EVENTS: &[&str] = &[
+    "push",
+    "pull_request",
+    "pull_request_review",
+    "pull_request_review_comment",
+    "issues",
+    "issue_comment",
+    "release",
+    "workflow_run",
+    "check_suite",
+    "status",
+    "create",
+    "delete",
+    "fork",
+    "star",
+    "gollum",
+    "milestone",
+    "commit_comment",
+    "deployment_status",
+    "repository",
+];
+
+/// Events only announced for projects listing them in `events`.
+pub const OPT_IN_EVENTS: &[&str] = &["fork"];
+
+/// Number of delivery ids remembered to ignore redeliveries.
+const RECENT_DELIVERIES: usize = 500;
+
+/// How long review comments wait for more comments by the same user on
+/// the same pull request. Submitting a review delivers each of its
+/// comments separately, within a few seconds.
+const REVIEW_COMMENT_WINDOW: Duration = Duration::from_secs(10);
+
+/// How long forks wait for more forks of the same repository, as they
+/// come in bursts when a repository gets attention.
+const FORK_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// How long milestone edits wait for later edits to the same
+/// milestone, so that quickly corrected mistakes aren't announced.
+const MILESTONE_WINDOW: Duration = Duration::from_secs(2 * 60);
+
+/// How long checks of a merge commit need to stay quiet before they're
+/// considered to have all passed.
+const CHECKS_SETTLE_TIME: Duration = Duration::from_secs(60);
+
+/// Longest a merge announcement waits for the checks of its commit.
+const MAX_CHECKS_WAIT: Duration = Duration::from_secs(15 * 60);
+
+/// Starts announcements of deleted protected branches.
+const DELETED_BRANCH_WARNING: &str = "\u{26a0}";
+
+/// Number of announced refs remembered, see [`AnnouncedRefs`].
+const ANNOUNCED_REFS: usize = 100;
+
+/// How long an announced ref isn't announced again.
+const ANNOUNCED_REF_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// Number of deliveries for a project failing verification within
+/// `REJECTION_WINDOW` before the admin room is told. A single failure is
+/// often someone trying out a webhook.
+const REJECTIONS_BEFORE_NOTICE: usize = 3;
+
+const REJECTION_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// Number of projects whose rejected deliveries are counted.
+const REJECTED_PROJECTS: usize = 100;
+
+/// Starts the webhook server, serving HTTPS when a certificate is
+/// configured. It runs until the returned sender is used or dropped.
+pub fn start_server(
+    config: &'static SharedConfig,
+    connection: &'static Connection,
+    pending: &'static PendingEvents,
+) -> (SocketAddr, oneshot::Sender<()>) {
     let (tx, rx) = oneshot::channel();
-    let port = config.port;
-    tokio::spawn(
-        warp::serve(get_route(config, sender).with(warp::log("webhook")))
-            .bind_with_graceful_shutdown(([0, 0, 0, 0], port), rx.map(|_| ()))
-            .1,
+    let current = config.get();
+    let address = SocketAddr::new(current.bind, current.port);
+    let shutdown = rx.map(|_| ());
+    let server = warp::serve(
+        get_route(config, connection, pending)
+            .or(health_route(connection))
+            .or(metrics_route())
+            .recover(handle_rejection)
+            .with(warp::log("webhook")),
     );
-    tx
+    let address = match &current.tls {
+        Some(tls) => {
+            let (address, server) = server
+                .tls()
+                .cert(&tls.cert)
+                .key(&tls.key)
+                .bind_with_graceful_shutdown(address, shutdown);
+            tokio::spawn(server);
+            address
+        }
+        None => {
+            let (address, server) = server.bind_with_graceful_shutdown(address, shutdown);
+            tokio::spawn(server);
+            address
+        }
+    };
+    info!("Listening for webhooks on {}", address);
+    (address, tx)
+}
+
+/// State shared between deliveries.
+#[derive(Clone)]
+struct Shared {
+    connection: &'static Connection,
+    pending: &'static PendingEvents,
+    skip_pull_requests: Arc<Mutex<HashSet<u32>>>,
+    workflow_conclusions: Arc<WorkflowConclusions>,
+    merge_checks: Arc<MergeChecks<MergeAnnouncement>>,
+    announced_refs: Arc<AnnouncedRefs>,
+    digests: Arc<Digests>,
+}
+
+/// Created tags and deleted refs announced recently. GitHub delivers
+/// both a push and a `create` or `delete` event for them, and either can
+/// be left out of a webhook's events, so whichever comes first is
+/// announced.
+struct AnnouncedRefs {
+    refs: Mutex<LruCache<String, Instant>>,
+}
+
+impl Default for AnnouncedRefs {
+    fn default() -> Self {
+        Self {
+            refs: Mutex::new(LruCache::new(ANNOUNCED_REFS)),
+        }
+    }
+}
+
+impl AnnouncedRefs {
+    /// Records the announcement of a change to a ref, returning whether
+    /// it wasn't announced already.
+    fn first(&self, repository: &str, change: &str, git_ref: &str) -> bool {
+        let now = Instant::now();
+        let key = format!("{} {} {}", repository, change, git_ref);
+        let mut refs = self.refs.lock().unwrap();
+        match refs.get(&key) {
+            Some(announced) if now.duration_since(*announced) < ANNOUNCED_REF_WINDOW => false,
+            _ => {
+                refs.put(key, now);
+                true
+            }
+        }
+    }
+}
+
+/// Announcement of a merged pull request, held until the checks of its
+/// commit are done.
+struct MergeAnnouncement {
+    outbox: Outbox,
+    rooms: Vec<String>,
+    format: Format,
+    message: String,
+}
+
+impl MergeAnnouncement {
+    async fn send(self, checks_passed: bool) {
+        let message = if checks_passed {
+            format!("{} (all checks passed)", self.message)
+        } else {
+            self.message
+        };
+        for room in &self.rooms {
+            if let Err(e) = self.outbox.send(room, self.format, &message).await {
+                warn!("Failed to announce a merge: {:?}", e);
+            }
+        }
+    }
+}
+
+impl Shared {
+    fn outbox(
+        &self,
+        config: &Config,
+        room_configuration: &RoomConfigurationRef<'_>,
+        event: &str,
+    ) -> Outbox {
+        Outbox {
+            connection: self.connection,
+            digests: Arc::clone(&self.digests),
+            quiet_hours: room_configuration.quiet_hours_for(event),
+            timezone: room_configuration.timezone,
+            dry_run: config.dry_run,
+            prefix: room_configuration.prefix.map(String::from),
+            prefix_color: room_configuration.prefix_color.map(String::from),
+            room_filters: room_configuration.room_filters.clone(),
+            event: event.into(),
+            user: None,
+            branch: None,
+        }
+    }
+}
+
+/// Deliveries seen recently, shared so that they can be saved across
+/// restarts.
+pub static DELIVERIES: Lazy<RecentDeliveries> =
+    Lazy::new(|| RecentDeliveries::new(RECENT_DELIVERIES));
+
+/// Star milestones announced so far, shared so that they can be saved
+/// across restarts.
+pub static MILESTONES: Lazy<AnnouncedMilestones> = Lazy::new(AnnouncedMilestones::default);
+
+static RENAMES: Lazy<RenamedRepositories> = Lazy::new(RenamedRepositories::default);
+
+static REJECTIONS: Lazy<Rejections> = Lazy::new(|| Rejections::new(REJECTED_PROJECTS));
+
+/// Times of recent deliveries failing verification, by project.
+struct Rejections {
+    times: Mutex<LruCache<String, Vec<Instant>>>,
+}
+
+impl Rejections {
+    fn new(capacity: usize) -> Self {
+        Self {
+            times: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    /// Records a rejected delivery, returning whether enough were
+    /// rejected recently to tell the admin room.
+    fn record(&self, project: &str) -> bool {
+        let now = Instant::now();
+        let mut times = self.times.lock().unwrap();
+        let project = project.to_owned();
+        if times.get(&project).is_none() {
+            times.put(project.clone(), Vec::new());
+        }
+        let recent = times.get_mut(&project).unwrap();
+        recent.retain(|time| now.duration_since(*time) < REJECTION_WINDOW);
+        if recent.len() == REJECTIONS_BEFORE_NOTICE {
+            recent.remove(0);
+        }
+        recent.push(now);
+        recent.len() == REJECTIONS_BEFORE_NOTICE
+    }
+}
+
+/// Ids of the latest deliveries, as GitHub sends a delivery again when
+/// answering it takes too long. Each id is kept along with whether the
+/// delivery was handled, as opposed to still being handled.
+pub struct RecentDeliveries {
+    ids: Mutex<LruCache<String, bool>>,
+}
+
+impl RecentDeliveries {
+    fn new(capacity: usize) -> Self {
+        Self {
+            ids: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    /// Remembers a delivery id as being handled, returning whether it's
+    /// new rather than already handled or being handled.
+    fn start(&self, id: &str) -> bool {
+        let mut ids = self.ids.lock().unwrap();
+        let id = id.to_owned();
+        if ids.get(&id).is_some() {
+            false
+        } else {
+            ids.put(id, false);
+            true
+        }
+    }
+
+    /// Records the result of handling a delivery given to
+    /// [`RecentDeliveries::start`]. Failed deliveries are forgotten, so
+    /// that GitHub's retries are handled.
+    fn finish(&self, id: &str, handled: bool) {
+        let mut ids = self.ids.lock().unwrap();
+        let id = id.to_owned();
+        if handled {
+            if let Some(done) = ids.get_mut(&id) {
+                *done = true;
+            }
+        } else {
+            ids.pop(&id);
+        }
+    }
+
+    /// Lists the ids of handled deliveries, oldest first.
+    pub fn ids(&self) -> Vec<String> {
+        let ids = self.ids.lock().unwrap();
+        ids.iter()
+            .rev()
+            .filter(|(_, handled)| **handled)
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    /// Remembers ids listed by [`RecentDeliveries::ids`].
+    pub fn restore(&self, ids: impl IntoIterator<Item = String>) {
+        let mut recent = self.ids.lock().unwrap();
+        for id in ids {
+            recent.put(id, true);
+        }
+    }
 }
 
 fn get_route(
-    config: &'static Config,
-    sender: Arc<DelayedSender>,
+    config: &'static SharedConfig,
+    connection: &'static Connection,
+    pending: &'static PendingEvents,
+) -> impl Clone + Filter<Extract = (&'static str,), Error = Rejection> {
+    let shared = Shared {
+        connection,
+        pending,
+        skip_pull_requests: Arc::new(Mutex::new(HashSet::new())),
+        workflow_conclusions: Arc::new(WorkflowConclusions::default()),
+        merge_checks: Arc::new(MergeChecks::default()),
+        announced_refs: Arc::new(AnnouncedRefs::default()),
+        digests: Arc::new(Digests::default()),
+    };
+    accepting_webhooks(connection)
+        .and(
+            github_route(config, shared.clone())
+                .or(gitlab_route(config, shared))
+                .unify(),
+        )
+        .map(|_webhook, reply| reply)
+}
+
+/// Refuses webhooks once the bot is shutting down, so that GitHub
+/// retries them instead of their announcements getting lost. Accepted
+/// webhooks are handled until they are dropped.
+fn accepting_webhooks(
+    connection: &'static Connection,
+) -> impl Clone + Filter<Extract = (Webhook<'static>,), Error = Rejection> {
+    warp::any().and_then(move || async move {
+        connection
+            .start_webhook()
+            .ok_or_else(|| warp::reject::custom(ShuttingDown))
+    })
+}
+
+fn github_route(
+    config: &'static SharedConfig,
+    shared: Shared,
 ) -> impl Clone + Filter<Extract = (&'static str,), Error = Rejection> {
-    let skip_pull_requests = Arc::new(Mutex::new(HashSet::new()));
-    path!("github" / "callback")
+    full_path(config.get().webhook_path.clone())
+        .and(allowed_source(config))
         .and(warp::header::optional("X-Hub-Signature-256"))
+        .and(warp::header::optional("X-Hub-Signature"))
         .and(warp::header("X-GitHub-Event"))
+        .and(warp::header::optional("X-GitHub-Delivery"))
         .and(warp::body::bytes())
-        .and_then(move |signature, event: String, bytes: Bytes| {
-            let sender = Arc::clone(&sender);
-            let skip_pull_requests = Arc::clone(&skip_pull_requests);
+        .and_then(move |sha256, sha1, event: String, delivery, bytes: Bytes| {
+            let shared = shared.clone();
+            let delivery: Option<String> = delivery;
+            let redelivery_id = delivery.clone();
+            let span = info_span!("delivery", id = %delivery_id(delivery));
             async move {
                 info!("Got event {}", event);
-                let room_configuration = get_rooms(config, signature, &bytes)?;
-                match event.as_str() {
-                    "push" => {
-                        handle_push_event(config, sender, room_configuration, json(&bytes)?).await?
+                let shared_config = config;
+                let config = config.get();
+                if config.disabled_events.contains(&event) {
+                    info!("Ignoring disabled event {}", event);
+                    return Ok("");
+                }
+                let signatures = Signatures { sha256, sha1 };
+                // Answered without announcing anything, so that GitHub shows
+                // the new webhook works. Organization webhooks aren't for a
+                // repository, so they are verified with the default secret.
+                if event == "ping" {
+                    let ping: PingEvent = json(&bytes)?;
+                    match &ping.repository {
+                        Some(repository) => {
+                            let name = configured_name(&config, &repository.full_name, None);
+                            let secret = config.rooms_for(&name).secret;
+                            verify_signature(secret, &signatures, &bytes)
+                                .inspect_err(|_| report_rejected_delivery(&config, &name))?;
+                        }
+                        None => verify_signature(config.secret.as_ref(), &signatures, &bytes)?,
                     }
-                    "pull_request" => {
-                        handle_pull_request(
-                            &config.username_aliases,
-                            skip_pull_requests,
-                            sender,
-                            room_configuration.rooms,
-                            json(&bytes)?,
+                    METRICS.webhook_received(&event);
+                    info!("Got pinged by GitHub: {}", ping.zen);
+                    return Ok("");
+                }
+                // Renamed repositories are routed by their earlier name.
+                let rename = if event == "repository" {
+                    let repository_event: RepositoryEvent = json(&bytes)?;
+                    repository_event.previous_name().map(|previous_name| {
+                        (
+                            previous_name,
+                            repository_event.repository.full_name.to_string(),
                         )
-                        .await?
+                    })
+                } else {
+                    None
+                };
+                let previous_name = rename
+                    .as_ref()
+                    .map(|(previous_name, _)| previous_name.as_str());
+                let room_configuration =
+                    match get_rooms(&config, &signatures, &bytes, previous_name)? {
+                        Some(room_configuration) => room_configuration,
+                        None => return Ok(""),
+                    };
+                // Checked after the signature, so that others can't make
+                // deliveries they didn't see get ignored.
+                if let Some(id) = &redelivery_id {
+                    if !DELIVERIES.start(id) {
+                        info!("Ignoring a delivery that was already received");
+                        return Ok("");
+                    }
+                }
+                let handled = async {
+                    METRICS.webhook_received(&event);
+                    if let Some((previous_name, name)) = &rename {
+                        remember_rename(previous_name, name);
+                    }
+                    if !room_configuration.accepts_event(&event) {
+                        return Ok("");
                     }
-                    _ => {}
+                    let outbox = shared.outbox(&config, &room_configuration, &event);
+                    match event.as_str() {
+                        "push" if !room_configuration.batch_window.is_zero() => {
+                            let push_event: PushEvent<'static> = json_owned(&bytes)?;
+                            if push_event.is_forced()
+                                || push_event.is_deleted()
+                                || push_event.tag().is_some()
+                            {
+                                // Pushes still held back go first, so that they
+                                // aren't announced after the push replacing them.
+                                if let Some(batch) = shared.pending.pushes.take(&push_event.key()) {
+                                    let earlier_configuration =
+                                        rooms_for_repository(&config, &batch.repository.full_name);
+                                    let earlier_outbox =
+                                        shared.outbox(&config, &earlier_configuration, "push");
+                                    handle_push_event(
+                                        &config,
+                                        &shared.announced_refs,
+                                        earlier_outbox,
+                                        earlier_configuration,
+                                        batch,
+                                    )
+                                    .await?
+                                }
+                                handle_push_event(
+                                    &config,
+                                    &shared.announced_refs,
+                                    outbox,
+                                    room_configuration,
+                                    push_event,
+                                )
+                                .await?
+                            } else {
+                                batch_push_event(
+                                    shared_config,
+                                    shared,
+                                    room_configuration,
+                                    push_event,
+                                )
+                            }
+                        }
+                        "push" => {
+                            handle_push_event(
+                                &config,
+                                &shared.announced_refs,
+                                outbox,
+                                room_configuration,
+                                json(&bytes)?,
+                            )
+                            .await?
+                        }
+                        "pull_request" => {
+                            handle_pull_request(
+                                &config,
+                                shared.skip_pull_requests,
+                                &shared.merge_checks,
+                                outbox,
+                                room_configuration,
+                                json(&bytes)?,
+                            )
+                            .await?
+                        }
+                        "issues" => handle_issue(outbox, room_configuration, json(&bytes)?).await?,
+                        "issue_comment" => {
+                            handle_issue_comment(outbox, room_configuration, json(&bytes)?).await?
+                        }
+                        "pull_request_review" => {
+                            handle_pull_request_review(outbox, room_configuration, json(&bytes)?)
+                                .await?
+                        }
+                        "pull_request_review_comment" => batch_review_comment(
+                            shared_config,
+                            shared,
+                            room_configuration,
+                            json_owned(&bytes)?,
+                        ),
+                        "release" => {
+                            handle_release(outbox, room_configuration, json(&bytes)?).await?
+                        }
+                        "workflow_run" => {
+                            handle_workflow_run(
+                                &shared.workflow_conclusions,
+                                outbox,
+                                room_configuration,
+                                json(&bytes)?,
+                            )
+                            .await?
+                        }
+                        "check_suite" => {
+                            handle_check_suite(
+                                &config,
+                                &shared.merge_checks,
+                                outbox,
+                                room_configuration,
+                                json(&bytes)?,
+                            )
+                            .await?
+                        }
+                        "create" => {
+                            handle_create(
+                                &shared.announced_refs,
+                                outbox,
+                                room_configuration,
+                                json(&bytes)?,
+                            )
+                            .await?
+                        }
+                        "star" => handle_star(outbox, room_configuration, json(&bytes)?).await?,
+                        "commit_comment" => {
+                            handle_commit_comment(outbox, room_configuration, json(&bytes)?).await?
+                        }
+                        "deployment_status" => {
+                            handle_deployment_status(outbox, room_configuration, json(&bytes)?)
+                                .await?
+                        }
+                        "repository" => {
+                            handle_repository(outbox, room_configuration, json(&bytes)?).await?
+                        }
+                        "gollum" => {
+                            handle_gollum(outbox, room_configuration, json(&bytes)?).await?
+                        }
+                        "milestone" => batch_milestone(
+                            shared_config,
+                            shared,
+                            room_configuration,
+                            json_owned(&bytes)?,
+                        ),
+                        "fork" => batch_fork(
+                            shared_config,
+                            shared,
+                            room_configuration,
+                            json_owned(&bytes)?,
+                        ),
+                        "delete" => {
+                            handle_delete(
+                                &shared.announced_refs,
+                                outbox,
+                                room_configuration,
+                                json(&bytes)?,
+                            )
+                            .await?
+                        }
+                        "status" => {
+                            handle_status(
+                                &shared.merge_checks,
+                                outbox,
+                                room_configuration,
+                                json(&bytes)?,
+                            )
+                            .await?
+                        }
+                        _ => {}
+                    }
+                    Ok::<_, Rejection>("")
+                }
+                .await;
+                if let Some(id) = &redelivery_id {
+                    DELIVERIES.finish(id, handled.is_ok());
+                }
+                handled
+            }
+            .instrument(span)
+        })
+}
+
+/// Matches requests for exactly the given path, which is configurable
+/// and so can't use `path!`.
+fn full_path(path: String) -> impl Clone + Filter<Extract = (), Error = Rejection> {
+    warp::path::full()
+        .and_then(move |full: FullPath| {
+            let matches = full.as_str() == path;
+            async move {
+                if matches {
+                    Ok(())
+                } else {
+                    Err(warp::reject::not_found())
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// Rejects requests from addresses outside `PSDEVBOT_ALLOWED_CIDRS`
+/// before their body is read. Every address is allowed when it's unset.
+fn allowed_source(
+    config: &'static SharedConfig,
+) -> impl Clone + Filter<Extract = (), Error = Rejection> {
+    let allowed_cidrs = Arc::new(config.get().allowed_cidrs.clone());
+    warp::addr::remote()
+        .and_then(move |remote: Option<SocketAddr>| {
+            let allowed = match &*allowed_cidrs {
+                Some(cidrs) => {
+                    remote.is_some_and(|remote| cidrs.iter().any(|cidr| cidr.contains(remote.ip())))
+                }
+                None => true,
+            };
+            async move {
+                if allowed {
+                    Ok(())
+                } else {
+                    Err(warp::reject::custom(ForbiddenSource(remote)))
                 }
-                Ok::<_, Rejection>("")
             }
         })
+        .untuple_one()
+}
+
+/// Reports whether the bot is connected to Showdown, for liveness
+/// probes. No secret is needed.
+fn health_route(
+    connection: &'static Connection,
+) -> impl Clone + Filter<Extract = (impl Reply,), Error = Rejection> {
+    path!("health")
+        .and(warp::get())
+        .map(move || warp::reply::json(&connection.health()))
+}
+
+/// Exposes metrics in the Prometheus text format. No secret is needed.
+fn metrics_route() -> impl Clone + Filter<Extract = (impl Reply,), Error = Rejection> {
+    path!("metrics").and(warp::get()).map(|| {
+        warp::reply::with_header(
+            METRICS.render(),
+            "Content-Type",
+            "text/plain; version=0.0.4",
+        )
+    })
+}
+
+/// Receives GitLab deliveries, which are announced like the GitHub
+/// events they correspond to. They share the webhook path with GitHub
+/// deliveries and are told apart by their `X-Gitlab-Event` header.
+fn gitlab_route(
+    config: &'static SharedConfig,
+    shared: Shared,
+) -> impl Clone + Filter<Extract = (&'static str,), Error = Rejection> {
+    full_path(config.get().webhook_path.clone())
+        .and(allowed_source(config))
+        .and(warp::header::optional("X-Gitlab-Token"))
+        .and(warp::header("X-Gitlab-Event"))
+        .and(warp::header::optional("X-Gitlab-Event-UUID"))
+        .and(warp::body::bytes())
+        .and_then(
+            move |token: Option<String>, event: String, delivery, bytes: Bytes| {
+                let shared = shared.clone();
+                let delivery: Option<String> = delivery;
+                let redelivery_id = delivery.clone();
+                let span = info_span!("delivery", id = %delivery_id(delivery));
+                async move {
+                    info!("Got GitLab event {}", event);
+                    let config = config.get();
+                    let payload: gitlab::InitialPayload = json(&bytes)?;
+                    let room_configuration = config.rooms_for(&payload.project.path_with_namespace);
+                    verify_token(room_configuration.secret, token.as_deref()).inspect_err(
+                        |_| report_rejected_delivery(&config, &payload.project.path_with_namespace),
+                    )?;
+                    if !room_configuration.has_rooms() {
+                        warn_without_rooms(&payload.project.path_with_namespace);
+                        return Ok("");
+                    }
+                    if let Some(id) = &redelivery_id {
+                        if !DELIVERIES.start(id) {
+                            info!("Ignoring a delivery that was already received");
+                            return Ok("");
+                        }
+                    }
+                    let handled = async {
+                        METRICS.webhook_received(&event);
+                        let event = match event.as_str() {
+                            "Push Hook" => "push",
+                            "Merge Request Hook" => "pull_request",
+                            _ => return Ok(""),
+                        };
+                        if config.disabled_events.contains(event) {
+                            info!("Ignoring disabled event {}", event);
+                            return Ok("");
+                        }
+                        if !room_configuration.accepts_event(event) {
+                            return Ok("");
+                        }
+                        let outbox = shared.outbox(&config, &room_configuration, event);
+                        if event == "push" {
+                            let push_event: gitlab::PushHook = json(&bytes)?;
+                            handle_push_event(
+                                &config,
+                                &shared.announced_refs,
+                                outbox,
+                                room_configuration,
+                                push_event.into(),
+                            )
+                            .await?;
+                        } else {
+                            let merge_request: gitlab::MergeRequestHook = json(&bytes)?;
+                            handle_pull_request(
+                                &config,
+                                shared.skip_pull_requests,
+                                &shared.merge_checks,
+                                outbox,
+                                room_configuration,
+                                merge_request.into(),
+                            )
+                            .await?;
+                        }
+                        Ok::<_, Rejection>("")
+                    }
+                    .await;
+                    if let Some(id) = &redelivery_id {
+                        DELIVERIES.finish(id, handled.is_ok());
+                    }
+                    handled
+                }
+                .instrument(span)
+            },
+        )
+}
+
+/// Identifies a delivery in logs, using the id sent by GitHub or GitLab
+/// when there is one.
+fn delivery_id(header: Option<String>) -> String {
+    header.unwrap_or_else(|| Uuid::new_v4().to_string())
 }
 
+/// Signature headers sent by GitHub.
+struct Signatures {
+    sha256: Option<String>,
+    sha1: Option<String>,
+}
+
+/// Finds the configuration for a delivery, once it's verified. Nothing
+/// is returned for repositories without rooms, as their events can't be
+/// announced.
 fn get_rooms<'a>(
     config: &'a Config,
-    signature: Option<String>,
+    signatures: &Signatures,
     bytes: &[u8],
-) -> Result<RoomConfigurationRef<'a>, Rejection> {
+    previous_name: Option<&str>,
+) -> Result<Option<RoomConfigurationRef<'a>>, Rejection> {
     let payload: InitialPayload = json(bytes)?;
-    let room_configuration = config.rooms_for(&payload.repository.full_name);
-    verify_signature(room_configuration.secret, signature, bytes)?;
-    Ok(room_configuration)
+    let name = configured_name(config, &payload.repository.full_name, previous_name);
+    let room_configuration = config.rooms_for(&name);
+    verify_signature(room_configuration.secret, signatures, bytes)
+        .inspect_err(|_| report_rejected_delivery(config, &name))?;
+    if !room_configuration.has_rooms() {
+        warn_without_rooms(&payload.repository.full_name);
+        return Ok(None);
+    }
+    Ok(Some(room_configuration))
+}
+
+/// Finds the name a repository is configured under. Repositories renamed
+/// while the bot is running keep using the configuration of their
+/// earlier name, unless their new name has one.
+fn configured_name(config: &Config, name: &str, previous_name: Option<&str>) -> String {
+    if config.project_for_repo(name).is_none() {
+        let original = match previous_name {
+            Some(previous_name) => Some(
+                RENAMES
+                    .original(previous_name)
+                    .unwrap_or_else(|| previous_name.into()),
+            ),
+            None => RENAMES.original(name),
+        };
+        if let Some(original) = original {
+            if config.project_for_repo(&original).is_some() {
+                warn!(
+                    "Using the configuration of {} for {}, which was renamed",
+                    original, name,
+                );
+                return original;
+            }
+        }
+    }
+    name.into()
+}
+
+/// Finds the configuration of a repository, following renames.
+fn rooms_for_repository<'a>(config: &'a Config, name: &str) -> RoomConfigurationRef<'a> {
+    config.rooms_for(&configured_name(config, name, None))
+}
+
+fn warn_without_rooms(repository: &str) {
+    warn!(
+        "Dropping a delivery for {}, no rooms are configured for it",
+        repository,
+    );
+}
+
+/// Tells the admin room about deliveries which keep failing
+/// verification. Anyone can send deliveries, so only configured projects
+/// are named, and deliveries are counted by project rather than by
+/// repository so that patterns can't be used to flood the admin room.
+fn report_rejected_delivery(config: &Config, repository: &str) {
+    let project = config.project_name_for_repo(repository);
+    if !REJECTIONS.record(project.unwrap_or_default()) {
+        return;
+    }
+    if let Some(project) = project {
+        NOTICES.notify(
+            &format!("rejected {}", project),
+            format!(
+                "Rejected deliveries for {} failing verification, check its webhook secret",
+                repository,
+            ),
+        );
+    } else {
+        NOTICES.notify(
+            "rejected",
+            "Rejected deliveries failing verification for repositories without configuration",
+        );
+    }
 }
 
+/// Verifies a payload signature against every secret of a project,
+/// preferring SHA-256 over SHA-1 when both are provided. Comparisons are
+/// done in constant time.
+///
+/// Deliveries for projects without a secret are refused, while an empty
+/// secret disables verification.
 fn verify_signature(
-    secret: &str,
-    signature: Option<String>,
+    secrets: Option<&Secrets>,
+    signatures: &Signatures,
     bytes: &[u8],
 ) -> Result<(), Rejection> {
-    if !secret.is_empty() {
-        let signature = signature.ok_or_else(|| reject("Missing signature"))?;
-        let signature = signature
-            .strip_prefix("sha256=")
-            .ok_or_else(|| reject("Signature doesn't start with sha256="))?;
-        let signature = hex::decode(signature).map_err(reject)?;
-        let mut mac =
-            Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("HMAC can take a key of any size");
-        mac.update(bytes);
-        mac.verify(&signature).map_err(reject)?;
+    let secrets = secrets.ok_or_else(|| warp::reject::custom(MissingSecret))?;
+    if secrets.is_disabled() {
+        return Ok(());
     }
-    Ok(())
+    let valid = if let Some(signature) = &signatures.sha256 {
+        let signature = decode_signature(signature, "sha256=")?;
+        secrets.iter().any(|secret| {
+            let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes())
+                .expect("HMAC can take a key of any size");
+            mac.update(bytes);
+            mac.verify(&signature).is_ok()
+        })
+    } else if let Some(signature) = &signatures.sha1 {
+        let signature = decode_signature(signature, "sha1=")?;
+        secrets.iter().any(|secret| {
+            let mut mac = Hmac::<Sha1>::new_varkey(secret.as_bytes())
+                .expect("HMAC can take a key of any size");
+            mac.update(bytes);
+            mac.verify(&signature).is_ok()
+        })
+    } else {
+        return Err(invalid_signature("Missing signature"));
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(invalid_signature("Signature mismatch"))
+    }
+}
+
+/// Verifies the token sent by GitLab, which is one of the secrets
+/// itself. Comparisons are done in constant time.
+fn verify_token(secrets: Option<&Secrets>, token: Option<&str>) -> Result<(), Rejection> {
+    let secrets = secrets.ok_or_else(|| warp::reject::custom(MissingSecret))?;
+    if secrets.is_disabled() {
+        return Ok(());
+    }
+    let token = token.ok_or_else(|| invalid_signature("Missing token"))?;
+    let valid = secrets.iter().any(|secret| {
+        let difference = token
+            .bytes()
+            .zip(secret.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b));
+        token.len() == secret.len() && difference == 0
+    });
+    if valid {
+        Ok(())
+    } else {
+        Err(invalid_signature("Token mismatch"))
+    }
+}
+
+fn decode_signature(signature: &str, prefix: &str) -> Result<Vec<u8>, Rejection> {
+    let signature = signature
+        .strip_prefix(prefix)
+        .ok_or_else(|| invalid_signature("Signature has an unexpected prefix"))?;
+    hex::decode(signature).map_err(|_| invalid_signature("Signature is not hexadecimal"))
 }
 
 fn json<'de, T: Deserialize<'de>>(input: &'de [u8]) -> Result<T, Rejection> {
-    serde_json::from_slice(input).map_err(reject)
+    serde_json::from_slice(input).map_err(|e| {
+        warn!("Failed to parse a delivery: {}", e);
+        reject(e)
+    })
+}
+
+/// Parses a delivery without borrowing from it, for events kept after
+/// the request is answered.
+fn json_owned<T: Deserialize<'static>>(input: &[u8]) -> Result<T, Rejection> {
+    let value: serde_json::Value = json(input)?;
+    T::deserialize(value).map_err(|e| {
+        warn!("Failed to parse a delivery: {}", e);
+        reject(e)
+    })
+}
+
+/// Holds back a push for the batch window, announcing it along with
+/// later pushes to the same ref. The configuration is looked up again
+/// once the window ends, as it may have been reloaded in the meantime.
+fn batch_push_event(
+    config: &'static SharedConfig,
+    shared: Shared,
+    room_configuration: RoomConfigurationRef<'_>,
+    push_event: PushEvent<'static>,
+) {
+    // Ignored users are checked before merging, so that their pushes
+    // don't hide the commits of others.
+    if room_configuration.ignores_user(push_event.pusher()) {
+        return;
+    }
+    let pending = shared.pending;
+    let span = Span::current();
+    let send = move |push_event: PushEvent<'static>| {
+        async move {
+            let config = config.get();
+            let room_configuration =
+                rooms_for_repository(&config, &push_event.repository.full_name);
+            let outbox = shared.outbox(&config, &room_configuration, "push");
+            let announced_refs = &shared.announced_refs;
+            if let Err(e) = handle_push_event(
+                &config,
+                announced_refs,
+                outbox,
+                room_configuration,
+                push_event,
+            )
+            .await
+            {
+                warn!("Failed to announce a batch of pushes: {:?}", e);
+            }
+        }
+        .instrument(span)
+    };
+    pending
+        .pushes
+        .add(push_event, room_configuration.batch_window, send);
+}
+
+/// Holds back a review comment, announcing it along with later comments
+/// left by the same user on the same pull request.
+fn batch_review_comment(
+    config: &'static SharedConfig,
+    shared: Shared,
+    room_configuration: RoomConfigurationRef<'_>,
+    comment: PullRequestReviewCommentEvent<'static>,
+) {
+    if room_configuration.ignores_user(comment.sender()) || !comment.is_announced() {
+        return;
+    }
+    let pending = shared.pending;
+    let span = Span::current();
+    let send = move |comment: PullRequestReviewCommentEvent<'static>| {
+        async move {
+            let config = config.get();
+            let room_configuration = rooms_for_repository(&config, &comment.repository.full_name);
+            let outbox = shared.outbox(&config, &room_configuration, "pull_request_review_comment");
+            if let Err(e) = handle_review_comment(outbox, room_configuration, comment).await {
+                warn!("Failed to announce review comments: {:?}", e);
+            }
+        }
+        .instrument(span)
+    };
+    pending
+        .review_comments
+        .add(comment, REVIEW_COMMENT_WINDOW, send);
+}
+
+/// Holds back a fork, announcing it along with later forks of the same
+/// repository.
+fn batch_fork(
+    config: &'static SharedConfig,
+    shared: Shared,
+    room_configuration: RoomConfigurationRef<'_>,
+    fork: ForkEvent<'static>,
+) {
+    if room_configuration.ignores_user(fork.owner()) {
+        return;
+    }
+    let pending = shared.pending;
+    let span = Span::current();
+    let send = move |fork: ForkEvent<'static>| {
+        async move {
+            let config = config.get();
+            let room_configuration = rooms_for_repository(&config, &fork.repository.full_name);
+            let outbox = shared.outbox(&config, &room_configuration, "fork");
+            if let Err(e) = handle_fork(outbox, room_configuration, fork).await {
+                warn!("Failed to announce forks: {:?}", e);
+            }
+        }
+        .instrument(span)
+    };
+    pending.forks.add(fork, FORK_WINDOW, send);
+}
+
+/// Holds back a milestone edit, so that it's announced as later
+/// edits to the milestone left it.
+fn batch_milestone(
+    config: &'static SharedConfig,
+    shared: Shared,
+    room_configuration: RoomConfigurationRef<'_>,
+    milestone: MilestoneEvent<'static>,
+) {
+    if room_configuration.ignores_user(milestone.sender()) {
+        return;
+    }
+    let pending = shared.pending;
+    let span = Span::current();
+    let send = move |milestone: MilestoneEvent<'static>| {
+        async move {
+            let config = config.get();
+            let room_configuration = rooms_for_repository(&config, &milestone.repository.full_name);
+            let outbox = shared.outbox(&config, &room_configuration, "milestone");
+            if let Err(e) = handle_milestone(outbox, room_configuration, milestone).await {
+                warn!("Failed to announce a milestone: {:?}", e);
+            }
+        }
+        .instrument(span)
+    };
+    pending
+        .milestone_edits
+        .add(milestone, MILESTONE_WINDOW, send);
+}
+
+/// Announces the latest commit of a project's default branch like a
+/// push of that commit, returning a description of what went wrong
+/// otherwise. Quiet hours don't apply, as someone asked for it.
+pub async fn announce_latest_commit(
+    config: &Config,
+    connection: &'static Connection,
+    repository: &str,
+) -> Result<(), String> {
+    if config.project_for_repo(repository).is_none() {
+        return Err(format!("There is no project for {}", repository));
+    }
+    let github_api = config
+        .github_api
+        .as_ref()
+        .ok_or("The GitHub API isn't configured")?;
+    let push_event = {
+        let mut github_api = github_api.lock().await;
+        let branch = github_api
+            .fetch_default_branch(repository)
+            .await
+            .ok_or_else(|| format!("Cannot find {} on GitHub", repository))?
+            .to_owned();
+        let commit = github_api
+            .fetch_latest_commit(repository, &branch)
+            .await
+            .ok_or_else(|| format!("Cannot fetch the latest commit of {}", repository))?;
+        PushEvent::from_commit(repository, &branch, commit)
+    };
+    let room_configuration = config.rooms_for(repository);
+    let outbox = Outbox {
+        connection,
+        digests: Arc::new(Digests::default()),
+        quiet_hours: None,
+        timezone: room_configuration.timezone,
+        dry_run: config.dry_run,
+        prefix: room_configuration.prefix.map(String::from),
+        prefix_color: room_configuration.prefix_color.map(String::from),
+        room_filters: room_configuration.room_filters.clone(),
+        event: "push".into(),
+        user: None,
+        branch: None,
+    };
+    handle_push_event(
+        config,
+        &AnnouncedRefs::default(),
+        outbox,
+        room_configuration,
+        push_event,
+    )
+    .await
+    .map_err(|_| format!("Failed to announce the latest commit of {}", repository))
 }
 
 async fn handle_push_event<'a>(
-    config: &'static Config,
-    sender: Arc<DelayedSender>,
+    config: &'a Config,
+    announced_refs: &AnnouncedRefs,
+    outbox: Outbox,
     room_configuration: RoomConfigurationRef<'a>,
-    push_event: PushEvent<'a>,
+    mut push_event: PushEvent<'a>,
 ) -> Result<(), Rejection> {
-    let mut github_api = match &config.github_api {
-        Some(github_api) => Some(github_api.lock().await),
-        None => None,
+    if room_configuration.ignores_user(push_event.pusher()) {
+        return Ok(());
+    }
+    let outbox = outbox.by_user(push_event.pusher());
+    push_event.repository.display_name = room_configuration.display_name;
+    if push_event.repository.default_branch.is_empty() {
+        if let Some(github_api) = &config.github_api {
+            let mut github_api = github_api.lock().await;
+            if let Some(default_branch) = github_api
+                .fetch_default_branch(&push_event.repository.full_name)
+                .await
+            {
+                push_event.repository.default_branch = default_branch.to_owned().into();
+            }
+        }
+    }
+    let default_branch = &push_event.repository.default_branch;
+    let is_branch = push_event.git_ref().starts_with("refs/heads/");
+    // Deleting a protected branch is announced whatever the branch
+    // filters, with a warning.
+    let protected = push_event.is_deleted()
+        && is_branch
+        && room_configuration.protects_branch(push_event.branch(), default_branch);
+    if !protected && !room_configuration.accepts_branch(push_event.branch(), default_branch) {
+        return Ok(());
+    }
+    let outbox = if is_branch && !protected {
+        outbox.on_branch(push_event.branch(), default_branch)
+    } else {
+        outbox
     };
-    if push_event.repository.default_branch == push_event.branch() {
-        for room in room_configuration.rooms {
-            let message = html_command(
-                room,
-                &format!(
-                    "addhtmlbox {}",
-                    push_event
+    // Deletions are also announced by `handle_delete`.
+    if push_event.is_deleted()
+        && !announced_refs.first(
+            &push_event.repository.full_name,
+            "deleted",
+            push_event.git_ref(),
+        )
+    {
+        return Ok(());
+    }
+    // Created tags are also announced by `handle_create`.
+    if push_event.tag().is_some()
+        && !push_event.is_deleted()
+        && !announced_refs.first(
+            &push_event.repository.full_name,
+            "created",
+            push_event.git_ref(),
+        )
+    {
+        return Ok(());
+    }
+    if push_event.is_annotated_tag() && !push_event.is_deleted() {
+        if let Some(github_api) = &config.github_api {
+            let mut github_api = github_api.lock().await;
+            push_event.tag_message = github_api
+                .fetch_tag_message(&push_event.repository.full_name, push_event.head())
+                .await
+                .map(String::from);
+        }
+    }
+    if room_configuration.pull_request_titles
+        && !push_event.is_deleted()
+        && push_event.git_ref().starts_with("refs/heads/")
+        && push_event.branch() != push_event.repository.default_branch
+    {
+        if let Some(github_api) = &config.github_api {
+            let mut github_api = github_api.lock().await;
+            push_event.pull_request = github_api
+                .fetch_pull_request_for_branch(
+                    &push_event.repository.full_name,
+                    push_event.branch(),
+                )
+                .await
+                .cloned();
+        }
+    }
+    let aliases = room_configuration.username_aliases;
+    let prefix = room_configuration.force_push_prefix;
+    let warning = Some(DELETED_BRANCH_WARNING).filter(|_| protected);
+    // Plain text, deletion, tag and force push announcements are the same
+    // for all rooms.
+    let all_rooms = || {
+        room_configuration
+            .rooms_for_paths(push_event.changed_files())
+            .into_iter()
+            .chain(room_configuration.simple_rooms.iter().map(String::as_str))
+    };
+    if let Some(template) = room_configuration.message_templates.get("push") {
+        if !push_event.is_deleted() && push_event.tag().is_none() && !push_event.is_forced() {
+            let format = room_configuration.format();
+            let message = push_event.format_template(template, aliases, format);
+            for room in all_rooms() {
+                outbox.send(room, format, &message).await?;
+            }
+            return Ok(());
+        }
+    }
+    if room_configuration.plain_text {
+        let text = if push_event.is_deleted() {
+            push_event.deleted_to_plain_text(aliases, warning)
+        } else if push_event.tag().is_some() {
+            push_event.tag_to_plain_text(aliases)
+        } else if push_event.is_forced() {
+            push_event.forced_to_plain_text(aliases, prefix)
+        } else {
+            push_event.to_plain_text(aliases)
+        };
+        for room in all_rooms() {
+            outbox.send_text(room, &text).await?;
+        }
+    } else if push_event.is_deleted() {
+        let html = push_event.format_deleted(aliases, warning).to_string();
+        for room in all_rooms() {
+            outbox.send_html(room, &html).await?;
+        }
+    } else if push_event.tag().is_some() {
+        let html = push_event.format_tag(aliases).to_string();
+        for room in all_rooms() {
+            outbox.send_html(room, &html).await?;
+        }
+    } else if push_event.is_forced() {
+        let html = push_event.format_forced(aliases, prefix).to_string();
+        for room in all_rooms() {
+            outbox.send_html(room, &html).await?;
+        }
+    } else {
+        let mut github_api = match &config.github_api {
+            Some(github_api) => Some(github_api.lock().await),
+            None => None,
+        };
+        for room in room_configuration.rooms_for_paths(push_event.changed_files()) {
+            outbox
+                .send_html(
+                    room,
+                    &push_event
                         .to_view(PushEventContext {
                             github_api: github_api.as_deref_mut(),
-                            username_aliases: &config.username_aliases,
+                            username_aliases: room_configuration.username_aliases,
+                            timezone: room_configuration.timezone,
+                            max_commits: room_configuration.max_commits,
+                            max_commit_title_length: room_configuration.max_commit_title_length,
+                            sha_length: room_configuration.sha_length,
                         })
                         .await
-                ),
-            );
-            sender.send(message).await.map_err(reject)?;
+                        .to_string(),
+                )
+                .await?;
         }
         for room in room_configuration.simple_rooms {
-            let message = html_command(
-                room,
-                &format!(
-                    "addhtmlbox {}",
-                    push_event
-                        .to_simple_view(PushEventContext {
-                            github_api: github_api.as_deref_mut(),
-                            username_aliases: &config.username_aliases,
-                        })
-                        .await
-                ),
-            );
-            sender.send(message).await.map_err(reject)?;
+            outbox
+                .send_html(
+                    room,
+                    &push_event
+                        .format_simple(room_configuration.username_aliases)
+                        .to_string(),
+                )
+                .await?;
         }
     }
     Ok(())
@@ -148,19 +1315,54 @@ async fn handle_push_event<'a>(
 
 const IGNORE_ACTIONS: &[&str] = &[
     "ready_for_review",
-    "labeled",
-    "unlabeled",
     "converted_to_draft",
     "review_request_removed",
 ];
 
 async fn handle_pull_request<'a>(
-    username_aliases: &'static UsernameAliases,
+    config: &'a Config,
     skip_pull_requests: Arc<Mutex<HashSet<u32>>>,
-    sender: Arc<DelayedSender>,
-    rooms: &'a [String],
-    pull_request: PullRequestEvent<'a>,
+    merge_checks: &Arc<MergeChecks<MergeAnnouncement>>,
+    outbox: Outbox,
+    room_configuration: RoomConfigurationRef<'a>,
+    mut pull_request: PullRequestEvent<'a>,
 ) -> Result<(), Rejection> {
+    if room_configuration.ignores_user(pull_request.sender()) {
+        return Ok(());
+    }
+    let outbox = outbox.by_user(pull_request.sender());
+    pull_request.repository.display_name = room_configuration.display_name;
+    if pull_request.is_label_change() {
+        if pull_request.changes_label(room_configuration.announced_labels) {
+            let aliases = room_configuration.username_aliases;
+            let format = room_configuration.format();
+            // Simple rooms get the label change as a chat message, instead
+            // of the colored label.
+            let (message, simple_format, simple_message) =
+                match room_configuration.message_templates.get("pull_request") {
+                    Some(template) => {
+                        let message = pull_request.format_template(template, aliases, format);
+                        (message.clone(), format, message)
+                    }
+                    None if room_configuration.plain_text => {
+                        let message = pull_request.label_to_plain_text(aliases);
+                        (message.clone(), format, message)
+                    }
+                    None => (
+                        pull_request.label_to_view(aliases).to_string(),
+                        Format::PlainText,
+                        pull_request.label_to_plain_text(aliases),
+                    ),
+                };
+            for room in room_configuration.rooms {
+                outbox.send(room, format, &message).await?;
+            }
+            for room in room_configuration.simple_rooms {
+                outbox.send(room, simple_format, &simple_message).await?;
+            }
+        }
+        return Ok(());
+    }
     let number = pull_request.pull_request.number;
     if !IGNORE_ACTIONS.contains(&&pull_request.action[..])
         && skip_pull_requests.lock().unwrap().insert(number)
@@ -169,32 +1371,1836 @@ async fn handle_pull_request<'a>(
             time::sleep(Duration::from_secs(10 * 60)).await;
             skip_pull_requests.lock().unwrap().remove(&number);
         });
-        for room in rooms {
-            let message = html_command(
-                room,
-                &format!("addhtmlbox {}", pull_request.to_view(username_aliases)),
-            );
-            sender.send(message).await.map_err(reject)?;
+        let files = match &config.github_api {
+            Some(github_api) if !room_configuration.paths.is_empty() => {
+                let mut github_api = github_api.lock().await;
+                github_api
+                    .fetch_pull_request_files(&pull_request.repository.full_name, number)
+                    .await
+            }
+            _ => None,
+        };
+        let files = files.iter().flatten().map(String::as_str);
+        let aliases = room_configuration.username_aliases;
+        let format = room_configuration.format();
+        let message = match room_configuration.message_templates.get("pull_request") {
+            Some(template) => pull_request.format_template(template, aliases, format),
+            None if room_configuration.plain_text => pull_request.to_plain_text(aliases),
+            None => pull_request.to_view(aliases).to_string(),
+        };
+        let rooms = room_configuration.rooms_for_paths(files);
+        let merge_commit = match &pull_request.pull_request.merge_commit_sha {
+            Some(sha)
+                if room_configuration.checks_passed
+                    && pull_request.action == "closed"
+                    && pull_request.pull_request.merged =>
+            {
+                sha
+            }
+            _ => {
+                for room in rooms {
+                    outbox.send(room, format, &message).await?;
+                }
+                return Ok(());
+            }
+        };
+        let announcement = MergeAnnouncement {
+            outbox,
+            rooms: rooms.into_iter().map(String::from).collect(),
+            format,
+            message,
+        };
+        if let Some(evicted) = merge_checks.track(merge_commit, announcement) {
+            evicted.send(false).await;
         }
+        let merge_checks = Arc::clone(merge_checks);
+        let sha = merge_commit.to_string();
+        tokio::spawn(
+            async move {
+                time::sleep(CHECKS_SETTLE_TIME).await;
+                if let Some(announcement) = merge_checks.stop(&sha, true) {
+                    return announcement.send(false).await;
+                }
+                time::sleep(MAX_CHECKS_WAIT - CHECKS_SETTLE_TIME).await;
+                if let Some(announcement) = merge_checks.stop(&sha, false) {
+                    announcement.send(false).await;
+                }
+            }
+            .instrument(Span::current()),
+        );
     }
     Ok(())
 }
 
-fn reject<T: Display + Send + Sync + 'static>(error: T) -> Rejection {
-    warp::reject::custom(ErrorRejection(error))
+async fn handle_issue<'a>(
+    outbox: Outbox,
+    room_configuration: RoomConfigurationRef<'a>,
+    mut issue: IssueEvent<'a>,
+) -> Result<(), Rejection> {
+    if room_configuration.ignores_user(issue.sender()) {
+        return Ok(());
+    }
+    let outbox = outbox.by_user(issue.sender());
+    issue.repository.display_name = room_configuration.display_name;
+    if issue.is_announced() {
+        let aliases = room_configuration.username_aliases;
+        let format = room_configuration.format();
+        let message = match room_configuration.message_templates.get("issues") {
+            Some(template) => issue.format_template(template, aliases, format),
+            None if room_configuration.plain_text => issue.to_plain_text(aliases),
+            None => issue.to_view(aliases).to_string(),
+        };
+        // The message is a single line, so it suits simple rooms too.
+        for room in room_configuration
+            .rooms
+            .iter()
+            .chain(room_configuration.simple_rooms)
+        {
+            outbox.send(room, format, &message).await?;
+        }
+    }
+    Ok(())
 }
 
-struct ErrorRejection<T>(T);
-
-impl<T: Display> Debug for ErrorRejection<T> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.0)
+async fn handle_issue_comment<'a>(
+    outbox: Outbox,
+    room_configuration: RoomConfigurationRef<'a>,
+    mut comment: IssueCommentEvent<'a>,
+) -> Result<(), Rejection> {
+    if room_configuration.ignores_user(comment.sender()) {
+        return Ok(());
+    }
+    let outbox = outbox.by_user(comment.sender());
+    comment.repository.display_name = room_configuration.display_name;
+    if comment.is_announced(room_configuration.closed_issue_comments) {
+        let aliases = room_configuration.username_aliases;
+        let format = room_configuration.format();
+        let (message, simple_message) =
+            match room_configuration.message_templates.get("issue_comment") {
+                Some(template) => {
+                    let message = comment.format_template(template, aliases, format);
+                    (message.clone(), message)
+                }
+                None if room_configuration.plain_text => {
+                    let message = comment.to_plain_text(aliases);
+                    (message.clone(), message)
+                }
+                None => (
+                    comment.to_view(aliases).to_string(),
+                    comment.format_simple(aliases).to_string(),
+                ),
+            };
+        for room in room_configuration.rooms {
+            outbox.send(room, format, &message).await?;
+        }
+        for room in room_configuration.simple_rooms {
+            outbox.send(room, format, &simple_message).await?;
+        }
     }
+    Ok(())
 }
 
-impl<T: Display + Send + Sync + 'static> Reject for ErrorRejection<T> {}
-
-fn html_command(room_id: &str, input: &str) -> SendMessage {
-    // Workaround for https://github.com/smogon/pokemon-showdown/pull/7611
-    SendMessage::chat_command(RoomId(room_id), input.replace("here", "her&#101;"))
+async fn handle_commit_comment<'a>(
+    outbox: Outbox,
+    room_configuration: RoomConfigurationRef<'a>,
+    mut comment: CommitCommentEvent<'a>,
+) -> Result<(), Rejection> {
+    if room_configuration.ignores_user(comment.sender()) || !comment.is_announced() {
+        return Ok(());
+    }
+    let outbox = outbox.by_user(comment.sender());
+    comment.repository.display_name = room_configuration.display_name;
+    comment.repository.sha_length = room_configuration.sha_length;
+    let aliases = room_configuration.username_aliases;
+    let format = room_configuration.format();
+    let (message, simple_message) = match room_configuration.message_templates.get("commit_comment")
+    {
+        Some(template) => {
+            let message = comment.format_template(template, aliases, format);
+            (message.clone(), message)
+        }
+        None if room_configuration.plain_text => {
+            let message = comment.to_plain_text(aliases);
+            (message.clone(), message)
+        }
+        None => (
+            comment.to_view(aliases).to_string(),
+            comment.format_simple(aliases).to_string(),
+        ),
+    };
+    for room in room_configuration.rooms {
+        outbox.send(room, format, &message).await?;
+    }
+    for room in room_configuration.simple_rooms {
+        outbox.send(room, format, &simple_message).await?;
+    }
+    Ok(())
+}
+
+async fn handle_pull_request_review<'a>(
+    outbox: Outbox,
+    room_configuration: RoomConfigurationRef<'a>,
+    mut review: PullRequestReviewEvent<'a>,
+) -> Result<(), Rejection> {
+    if room_configuration.ignores_user(review.sender()) {
+        return Ok(());
+    }
+    let outbox = outbox.by_user(review.sender());
+    review.repository.display_name = room_configuration.display_name;
+    if review.is_announced(room_configuration.commented_reviews) {
+        let aliases = room_configuration.username_aliases;
+        let format = room_configuration.format();
+        let message = match room_configuration
+            .message_templates
+            .get("pull_request_review")
+        {
+            Some(template) => review.format_template(template, aliases, format),
+            None if room_configuration.plain_text => review.to_plain_text(aliases),
+            None => review.to_view(aliases).to_string(),
+        };
+        for room in room_configuration
+            .rooms
+            .iter()
+            .chain(room_configuration.simple_rooms)
+        {
+            outbox.send(room, format, &message).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn handle_review_comment<'a>(
+    outbox: Outbox,
+    room_configuration: RoomConfigurationRef<'a>,
+    mut comment: PullRequestReviewCommentEvent<'a>,
+) -> Result<(), Rejection> {
+    let outbox = outbox.by_user(comment.sender());
+    comment.repository.display_name = room_configuration.display_name;
+    let aliases = room_configuration.username_aliases;
+    let format = room_configuration.format();
+    let (message, simple_message) = match room_configuration
+        .message_templates
+        .get("pull_request_review_comment")
+    {
+        Some(template) => {
+            let message = comment.format_template(template, aliases, format);
+            (message.clone(), message)
+        }
+        None if room_configuration.plain_text => {
+            let message = comment.to_plain_text(aliases);
+            (message.clone(), message)
+        }
+        None => (
+            comment.to_view(aliases).to_string(),
+            comment.format_simple(aliases).to_string(),
+        ),
+    };
+    for room in room_configuration.rooms {
+        outbox.send(room, format, &message).await?;
+    }
+    for room in room_configuration.simple_rooms {
+        outbox.send(room, format, &simple_message).await?;
+    }
+    Ok(())
+}
+
+async fn handle_fork<'a>(
+    outbox: Outbox,
+    room_configuration: RoomConfigurationRef<'a>,
+    mut fork: ForkEvent<'a>,
+) -> Result<(), Rejection> {
+    fork.repository.display_name = room_configuration.display_name;
+    let aliases = room_configuration.username_aliases;
+    let format = room_configuration.format();
+    let message = match room_configuration.message_templates.get("fork") {
+        Some(template) => fork.format_template(template, aliases, format),
+        None if room_configuration.plain_text => fork.to_plain_text(aliases),
+        None => fork.to_view(aliases).to_string(),
+    };
+    for room in room_configuration
+        .rooms
+        .iter()
+        .chain(room_configuration.simple_rooms)
+    {
+        outbox.send(room, format, &message).await?;
+    }
+    Ok(())
+}
+
+async fn handle_release<'a>(
+    outbox: Outbox,
+    room_configuration: RoomConfigurationRef<'a>,
+    mut release: ReleaseEvent<'a>,
+) -> Result<(), Rejection> {
+    if room_configuration.ignores_user(release.sender()) {
+        return Ok(());
+    }
+    let outbox = outbox.by_user(release.sender());
+    release.repository.display_name = room_configuration.display_name;
+    if release.is_announced(room_configuration.prereleases) {
+        let format = room_configuration.format();
+        let aliases = room_configuration.username_aliases;
+        let (message, simple_message) = match room_configuration.message_templates.get("release") {
+            Some(template) => {
+                let message = release.format_template(template, aliases, format);
+                (message.clone(), message)
+            }
+            None if room_configuration.plain_text => {
+                let message = release.to_plain_text(aliases);
+                (message.clone(), message)
+            }
+            None => (
+                release.to_view(aliases).to_string(),
+                release.format_simple(aliases).to_string(),
+            ),
+        };
+        for room in room_configuration.rooms {
+            outbox.send(room, format, &message).await?;
+        }
+        for room in room_configuration.simple_rooms {
+            outbox.send(room, format, &simple_message).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Announces failing workflows and ones passing again, for pushes to the
+/// default branch.
+async fn handle_workflow_run<'a>(
+    conclusions: &WorkflowConclusions,
+    outbox: Outbox,
+    room_configuration: RoomConfigurationRef<'a>,
+    mut run: WorkflowRunEvent<'a>,
+) -> Result<(), Rejection> {
+    if room_configuration.ignores_user(run.sender()) || !run.is_tracked() {
+        return Ok(());
+    }
+    let outbox = outbox.by_user(run.sender());
+    let transition = match conclusions.record(&run.key(), run.conclusion()) {
+        Some(transition) => transition,
+        None => return Ok(()),
+    };
+    run.repository.display_name = room_configuration.display_name;
+    run.repository.sha_length = room_configuration.sha_length;
+    let format = room_configuration.format();
+    let max_title_length = room_configuration.max_commit_title_length;
+    let message = match room_configuration.message_templates.get("workflow_run") {
+        Some(template) => run.format_template(template, transition, format),
+        None if room_configuration.plain_text => run.to_plain_text(transition, max_title_length),
+        None => run.to_view(transition, max_title_length).to_string(),
+    };
+    for room in room_configuration
+        .rooms
+        .iter()
+        .chain(room_configuration.simple_rooms)
+    {
+        outbox.send(room, format, &message).await?;
+    }
+    Ok(())
+}
+
+async fn handle_deployment_status<'a>(
+    outbox: Outbox,
+    room_configuration: RoomConfigurationRef<'a>,
+    mut deployment: DeploymentStatusEvent<'a>,
+) -> Result<(), Rejection> {
+    if room_configuration.ignores_user(deployment.deployer())
+        || !deployment.is_finished()
+        || !room_configuration.accepts_environment(deployment.environment())
+    {
+        return Ok(());
+    }
+    let outbox = outbox.by_user(deployment.deployer());
+    deployment.repository.display_name = room_configuration.display_name;
+    deployment.repository.sha_length = room_configuration.sha_length;
+    let aliases = room_configuration.username_aliases;
+    let format = room_configuration.format();
+    let message = match room_configuration
+        .message_templates
+        .get("deployment_status")
+    {
+        Some(template) => deployment.format_template(template, aliases, format),
+        None if room_configuration.plain_text => deployment.to_plain_text(aliases),
+        None => deployment.to_view(aliases).to_string(),
+    };
+    for room in room_configuration
+        .rooms
+        .iter()
+        .chain(room_configuration.simple_rooms)
+    {
+        outbox.send(room, format, &message).await?;
+    }
+    Ok(())
+}
+
+/// Remembers a repository was renamed or transferred, so that its
+/// deliveries keep reaching the same rooms, and tells the admin room its
+/// configuration needs to be updated.
+fn remember_rename(previous_name: &str, name: &str) {
+    let notice = format!(
+        "Repository {} is now {}, update the configuration to use the new name",
+        previous_name, name,
+    );
+    warn!("{}", notice);
+    NOTICES.notify(&format!("renamed {}", previous_name), notice);
+    RENAMES.renamed(previous_name, name);
+}
+
+async fn handle_repository<'a>(
+    outbox: Outbox,
+    room_configuration: RoomConfigurationRef<'a>,
+    mut event: RepositoryEvent<'a>,
+) -> Result<(), Rejection> {
+    if room_configuration.ignores_user(event.sender()) || !event.is_announced() {
+        return Ok(());
+    }
+    let outbox = outbox.by_user(event.sender());
+    event.repository.display_name = room_configuration.display_name;
+    let aliases = room_configuration.username_aliases;
+    let format = room_configuration.format();
+    let message = match room_configuration.message_templates.get("repository") {
+        Some(template) => event.format_template(template, aliases, format),
+        None if room_configuration.plain_text => event.to_plain_text(aliases),
+        None => event.to_view(aliases).to_string(),
+    };
+    for room in room_configuration
+        .rooms
+        .iter()
+        .chain(room_configuration.simple_rooms)
+    {
+        outbox.send(room, format, &message).await?;
+    }
+    Ok(())
+}
+
+async fn handle_create<'a>(
+    announced_refs: &AnnouncedRefs,
+    outbox: Outbox,
+    room_configuration: RoomConfigurationRef<'a>,
+    mut create: CreateEvent<'a>,
+) -> Result<(), Rejection> {
+    if room_configuration.ignores_user(create.sender()) {
+        return Ok(());
+    }
+    let outbox = outbox.by_user(create.sender());
+    let announced = if create.is_tag() {
+        // Publishing a release usually creates its tag.
+        let released =
+            room_configuration.skip_release_tags && room_configuration.accepts_event("release");
+        !released
+            && !room_configuration.ignores_tag(create.name())
+            && announced_refs.first(
+                &create.repository.full_name,
+                "created",
+                &format!("refs/tags/{}", create.name()),
+            )
+    } else {
+        room_configuration.accepts_branch(create.name(), &create.repository.default_branch)
+    };
+    if !announced {
+        return Ok(());
+    }
+    let outbox = if create.is_tag() {
+        outbox
+    } else {
+        outbox.on_branch(create.name(), &create.repository.default_branch)
+    };
+    create.repository.display_name = room_configuration.display_name;
+    let aliases = room_configuration.username_aliases;
+    let format = room_configuration.format();
+    let message = match room_configuration.message_templates.get("create") {
+        Some(template) => create.format_template(template, aliases, format),
+        None if room_configuration.plain_text => create.to_plain_text(aliases),
+        None => create.to_view(aliases).to_string(),
+    };
+    for room in room_configuration
+        .rooms
+        .iter()
+        .chain(room_configuration.simple_rooms)
+    {
+        outbox.send(room, format, &message).await?;
+    }
+    Ok(())
+}
+
+async fn handle_gollum<'a>(
+    outbox: Outbox,
+    room_configuration: RoomConfigurationRef<'a>,
+    mut gollum: GollumEvent<'a>,
+) -> Result<(), Rejection> {
+    if room_configuration.ignores_user(gollum.sender()) {
+        return Ok(());
+    }
+    let outbox = outbox.by_user(gollum.sender());
+    gollum.repository.display_name = room_configuration.display_name;
+    let aliases = room_configuration.username_aliases;
+    let format = room_configuration.format();
+    let (message, simple_message) = match room_configuration.message_templates.get("gollum") {
+        Some(template) => {
+            let message = gollum.format_template(template, aliases, format);
+            (message.clone(), message)
+        }
+        None if room_configuration.plain_text => (
+            gollum.to_plain_text(aliases),
+            gollum.simple_to_plain_text(aliases),
+        ),
+        None => (
+            gollum.to_view(aliases).to_string(),
+            gollum.format_simple(aliases).to_string(),
+        ),
+    };
+    for room in room_configuration.rooms {
+        outbox.send(room, format, &message).await?;
+    }
+    for room in room_configuration.simple_rooms {
+        outbox.send(room, format, &simple_message).await?;
+    }
+    Ok(())
+}
+
+async fn handle_milestone<'a>(
+    outbox: Outbox,
+    room_configuration: RoomConfigurationRef<'a>,
+    mut milestone: MilestoneEvent<'a>,
+) -> Result<(), Rejection> {
+    if !milestone.is_announced() {
+        return Ok(());
+    }
+    let outbox = outbox.by_user(milestone.sender());
+    milestone.repository.display_name = room_configuration.display_name;
+    let aliases = room_configuration.username_aliases;
+    let format = room_configuration.format();
+    let message = match room_configuration.message_templates.get("milestone") {
+        Some(template) => milestone.format_template(template, aliases, format),
+        None if room_configuration.plain_text => milestone.to_plain_text(aliases),
+        None => milestone.to_view(aliases).to_string(),
+    };
+    for room in room_configuration
+        .rooms
+        .iter()
+        .chain(room_configuration.simple_rooms)
+    {
+        outbox.send(room, format, &message).await?;
+    }
+    Ok(())
+}
+
+async fn handle_star<'a>(
+    outbox: Outbox,
+    room_configuration: RoomConfigurationRef<'a>,
+    mut star: StarEvent<'a>,
+) -> Result<(), Rejection> {
+    // Removed stars are never announced, later stars count from the
+    // lower number on their own.
+    if star.action != "created" {
+        return Ok(());
+    }
+    let stars = match star.stars() {
+        Some(stars) => stars,
+        None => return Ok(()),
+    };
+    let milestone = match MILESTONES.starred(
+        &star.repository.full_name,
+        stars,
+        room_configuration.star_milestones,
+    ) {
+        Some(milestone) => milestone,
+        None => return Ok(()),
+    };
+    star.repository.display_name = room_configuration.display_name;
+    let aliases = room_configuration.username_aliases;
+    let format = room_configuration.format();
+    let message = match room_configuration.message_templates.get("star") {
+        Some(template) => star.format_template(template, aliases, format, milestone),
+        None if room_configuration.plain_text => star.to_plain_text(aliases, milestone),
+        None => star.to_view(aliases, milestone).to_string(),
+    };
+    for room in room_configuration
+        .rooms
+        .iter()
+        .chain(room_configuration.simple_rooms)
+    {
+        outbox.send(room, format, &message).await?;
+    }
+    Ok(())
+}
+
+async fn handle_delete<'a>(
+    announced_refs: &AnnouncedRefs,
+    outbox: Outbox,
+    room_configuration: RoomConfigurationRef<'a>,
+    mut delete: DeleteEvent<'a>,
+) -> Result<(), Rejection> {
+    if room_configuration.ignores_user(delete.sender()) {
+        return Ok(());
+    }
+    let outbox = outbox.by_user(delete.sender());
+    let default_branch = &delete.repository.default_branch;
+    let protected =
+        !delete.is_tag() && room_configuration.protects_branch(delete.name(), default_branch);
+    let (announced, git_ref) = if delete.is_tag() {
+        (
+            !room_configuration.ignores_tag(delete.name()),
+            format!("refs/tags/{}", delete.name()),
+        )
+    } else {
+        (
+            protected || room_configuration.accepts_branch(delete.name(), default_branch),
+            format!("refs/heads/{}", delete.name()),
+        )
+    };
+    // Deletions are also announced along with other pushes.
+    if !announced || !announced_refs.first(&delete.repository.full_name, "deleted", &git_ref) {
+        return Ok(());
+    }
+    // Protected branches are announced whatever the branch filters.
+    let outbox = if delete.is_tag() || protected {
+        outbox
+    } else {
+        outbox.on_branch(delete.name(), default_branch)
+    };
+    delete.repository.display_name = room_configuration.display_name;
+    let aliases = room_configuration.username_aliases;
+    let warning = Some(DELETED_BRANCH_WARNING).filter(|_| protected);
+    let format = room_configuration.format();
+    let message = match room_configuration.message_templates.get("delete") {
+        Some(template) => delete.format_template(template, aliases, format),
+        None if room_configuration.plain_text => delete.to_plain_text(aliases, warning),
+        None => delete.to_view(aliases, warning).to_string(),
+    };
+    for room in room_configuration
+        .rooms
+        .iter()
+        .chain(room_configuration.simple_rooms)
+    {
+        outbox.send(room, format, &message).await?;
+    }
+    Ok(())
+}
+
+async fn handle_status<'a>(
+    merge_checks: &Arc<MergeChecks<MergeAnnouncement>>,
+    outbox: Outbox,
+    room_configuration: RoomConfigurationRef<'a>,
+    mut status: StatusEvent<'a>,
+) -> Result<(), Rejection> {
+    if room_configuration.ignores_user(status.sender()) {
+        return Ok(());
+    }
+    let outbox = outbox.by_user(status.sender());
+    status.repository.display_name = room_configuration.display_name;
+    status.repository.sha_length = room_configuration.sha_length;
+    match status.check() {
+        Some(check) => {
+            handle_check(
+                merge_checks,
+                outbox,
+                room_configuration,
+                "status",
+                check,
+                None,
+            )
+            .await
+        }
+        None => Ok(()),
+    }
+}
+
+async fn handle_check_suite<'a>(
+    config: &Config,
+    merge_checks: &Arc<MergeChecks<MergeAnnouncement>>,
+    outbox: Outbox,
+    room_configuration: RoomConfigurationRef<'a>,
+    mut suite: CheckSuiteEvent<'a>,
+) -> Result<(), Rejection> {
+    if room_configuration.ignores_user(suite.sender()) {
+        return Ok(());
+    }
+    let outbox = outbox.by_user(suite.sender());
+    suite.repository.display_name = room_configuration.display_name;
+    suite.repository.sha_length = room_configuration.sha_length;
+    // Failed Actions runs are already announced by `handle_workflow_run`,
+    // which can tell which workflow failed.
+    let announced_by = Some("workflow_run").filter(|event| {
+        suite.is_from_actions()
+            && !config.disabled_events.contains(*event)
+            && room_configuration.accepts_event(event)
+    });
+    match suite.check() {
+        Some(check) => {
+            handle_check(
+                merge_checks,
+                outbox,
+                room_configuration,
+                "check_suite",
+                check,
+                announced_by,
+            )
+            .await
+        }
+        None => Ok(()),
+    }
+}
+
+/// Announces a failed check on the default branch. Passed checks are
+/// only announced all at once, along with the pull request merging the
+/// commit.
+///
+/// Rooms announcing `announced_by` events don't get the failure, as that
+/// event already tells about it.
+async fn handle_check(
+    merge_checks: &Arc<MergeChecks<MergeAnnouncement>>,
+    outbox: Outbox,
+    room_configuration: RoomConfigurationRef<'_>,
+    event: &str,
+    check: Check<'_>,
+    announced_by: Option<&str>,
+) -> Result<(), Rejection> {
+    match merge_checks.record(check.sha, check.context, check.state) {
+        Some(MergeCheck::Failed(announcement)) => announcement.send(false).await,
+        Some(MergeCheck::Waiting(update)) => {
+            let merge_checks = Arc::clone(merge_checks);
+            let sha = check.sha.to_string();
+            tokio::spawn(
+                async move {
+                    time::sleep(CHECKS_SETTLE_TIME).await;
+                    if let Some(announcement) = merge_checks.passed(&sha, update) {
+                        announcement.send(true).await;
+                    }
+                }
+                .instrument(Span::current()),
+            );
+        }
+        None => {}
+    }
+    if check.state == CheckState::Failure {
+        let format = room_configuration.format();
+        let message = match room_configuration.message_templates.get(event) {
+            Some(template) => check.format_template(template, format),
+            None if room_configuration.plain_text => check.to_plain_text(),
+            None => check.to_view().to_string(),
+        };
+        for room in room_configuration
+            .rooms
+            .iter()
+            .chain(room_configuration.simple_rooms)
+        {
+            if announced_by.is_some_and(|event| outbox.accepts_event(room, event)) {
+                continue;
+            }
+            outbox.send(room, format, &message).await?;
+        }
+    }
+    Ok(())
+}
+
+fn reject<T: Display + Send + Sync + 'static>(error: T) -> Rejection {
+    warp::reject::custom(ErrorRejection(error))
+}
+
+struct ErrorRejection<T>(T);
+
+#[derive(Debug)]
+struct InvalidSignature(&'static str);
+
+impl Reject for InvalidSignature {}
+
+fn invalid_signature(reason: &'static str) -> Rejection {
+    warp::reject::custom(InvalidSignature(reason))
+}
+
+#[derive(Debug)]
+struct MissingSecret;
+
+impl Reject for MissingSecret {}
+
+#[derive(Debug)]
+struct ForbiddenSource(Option<SocketAddr>);
+
+impl Reject for ForbiddenSource {}
+
+#[derive(Debug)]
+struct ShuttingDown;
+
+impl Reject for ShuttingDown {}
+
+async fn handle_rejection(rejection: Rejection) -> Result<impl Reply, Rejection> {
+    if let Some(InvalidSignature(reason)) = rejection.find() {
+        info!("Rejected a delivery: {}", reason);
+        Ok(warp::reply::with_status(*reason, StatusCode::UNAUTHORIZED))
+    } else if let Some(MissingSecret) = rejection.find() {
+        warn!("Rejected a delivery for a project without a secret");
+        Ok(warp::reply::with_status(
+            "No secret configured",
+            StatusCode::FORBIDDEN,
+        ))
+    } else if let Some(ShuttingDown) = rejection.find() {
+        Ok(warp::reply::with_status(
+            "Shutting down",
+            StatusCode::SERVICE_UNAVAILABLE,
+        ))
+    } else if let Some(ForbiddenSource(remote)) = rejection.find() {
+        warn!("Rejected a delivery from {:?}, which isn't allowed", remote);
+        Ok(warp::reply::with_status(
+            "Address not allowed",
+            StatusCode::FORBIDDEN,
+        ))
+    } else {
+        Err(rejection)
+    }
+}
+
+impl<T: Display> Debug for ErrorRejection<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl<T: Display + Send + Sync + 'static> Reject for ErrorRejection<T> {}
+
+fn html_command(room_id: &str, input: &str) -> SendMessage {
+    // Workaround for https://github.com/smogon/pokemon-showdown/pull/7611
+    SendMessage::chat_command(RoomId(room_id), input.replace("here", "her&#101;"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        delivery_id, get_rooms, get_route, handle_rejection, health_route, start_server,
+        verify_signature, verify_token, PendingEvents, RecentDeliveries, Rejections, Signatures,
+        REJECTIONS_BEFORE_NOTICE, REJECTION_WINDOW,
+    };
+    use crate::args::Args;
+    use crate::config::{Config, ConfigFile, Secrets, SharedConfig};
+    use crate::connection::Connection;
+    use crate::unbounded::DelayedSender;
+    use futures::channel::mpsc;
+    use futures::StreamExt;
+    use hmac::{Hmac, Mac, NewMac};
+    use sha1::Sha1;
+    use sha2::Sha256;
+    use showdown::SendMessage;
+    use std::net::IpAddr;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::time;
+    use uuid::Uuid;
+    use warp::http::StatusCode;
+    use warp::{Filter, Reply};
+
+    const PAYLOAD: &[u8] = br#"{"repository": {"full_name": "a/b"}}"#;
+
+    const ISSUE_PAYLOAD: &[u8] = br#"{
+        "action": "opened",
+        "issue": {
+            "number": 1,
+            "html_url": "https://github.com/a/b/issues/1",
+            "title": "Crash"
+        },
+        "repository": {
+            "name": "b",
+            "full_name": "a/b",
+            "html_url": "https://github.com/a/b"
+        },
+        "sender": {"login": "Zarel", "html_url": "https://github.com/Zarel"}
+    }"#;
+
+    const PING_PAYLOAD: &[u8] = br#"{
+        "zen": "Design for failure.",
+        "hook_id": 109948940,
+        "hook": {
+            "type": "Repository",
+            "id": 109948940,
+            "name": "web",
+            "active": true,
+            "events": ["*"],
+            "config": {
+                "content_type": "json",
+                "insecure_ssl": "0",
+                "url": "https://psdevbot.example.com/github/callback"
+            }
+        },
+        "repository": {
+            "name": "b",
+            "full_name": "a/b",
+            "html_url": "https://github.com/a/b"
+        },
+        "sender": {"login": "Zarel", "html_url": "https://github.com/Zarel"}
+    }"#;
+
+    const RENAME_PAYLOAD: &[u8] = br#"{
+        "action": "renamed",
+        "changes": {"repository": {"name": {"from": "old"}}},
+        "repository": {
+            "name": "new",
+            "full_name": "renamed/new",
+            "html_url": "https://github.com/renamed/new"
+        },
+        "sender": {"login": "Zarel", "html_url": "https://github.com/Zarel"}
+    }"#;
+
+    fn sha256(secret: &str) -> String {
+        sha256_of(secret, PAYLOAD)
+    }
+
+    fn sha256_of(secret: &str, payload: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).unwrap();
+        mac.update(payload);
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    fn sha1(secret: &str) -> String {
+        let mut mac = Hmac::<Sha1>::new_varkey(secret.as_bytes()).unwrap();
+        mac.update(PAYLOAD);
+        format!("sha1={}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    fn secrets(secrets: &[&str]) -> Secrets {
+        Secrets::from(
+            secrets
+                .iter()
+                .map(|secret| secret.to_string())
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    async fn status(secret: Option<&str>, signatures: Signatures) -> StatusCode {
+        let secrets = secret.map(|secret| secrets(&[secret]));
+        match verify_signature(secrets.as_ref(), &signatures, PAYLOAD) {
+            Ok(()) => StatusCode::OK,
+            Err(rejection) => handle_rejection(rejection)
+                .await
+                .unwrap()
+                .into_response()
+                .status(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sha256_signature() {
+        let signatures = Signatures {
+            sha256: Some(sha256("secret")),
+            sha1: None,
+        };
+        assert_eq!(status(Some("secret"), signatures).await, StatusCode::OK);
+        let signatures = Signatures {
+            sha256: Some(sha256("wrong")),
+            sha1: None,
+        };
+        assert_eq!(
+            status(Some("secret"), signatures).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sha1_signature_fallback() {
+        let signatures = Signatures {
+            sha256: None,
+            sha1: Some(sha1("secret")),
+        };
+        assert_eq!(status(Some("secret"), signatures).await, StatusCode::OK);
+        let signatures = Signatures {
+            sha256: None,
+            sha1: Some(sha1("wrong")),
+        };
+        assert_eq!(
+            status(Some("secret"), signatures).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sha256_signature_is_preferred() {
+        let signatures = Signatures {
+            sha256: Some(sha256("wrong")),
+            sha1: Some(sha1("secret")),
+        };
+        assert_eq!(
+            status(Some("secret"), signatures).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_missing_signature() {
+        let signatures = Signatures {
+            sha256: None,
+            sha1: None,
+        };
+        assert_eq!(
+            status(Some("secret"), signatures).await,
+            StatusCode::UNAUTHORIZED
+        );
+        let signatures = Signatures {
+            sha256: None,
+            sha1: None,
+        };
+        assert_eq!(status(Some(""), signatures).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_missing_secret() {
+        let signatures = Signatures {
+            sha256: Some(sha256("secret")),
+            sha1: None,
+        };
+        assert_eq!(status(None, signatures).await, StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn test_multiple_secrets() {
+        let secrets = secrets(&["new", "old"]);
+        for secret in ["new", "old"] {
+            let signatures = Signatures {
+                sha256: Some(sha256(secret)),
+                sha1: None,
+            };
+            assert!(verify_signature(Some(&secrets), &signatures, PAYLOAD).is_ok());
+        }
+        let signatures = Signatures {
+            sha256: None,
+            sha1: Some(sha1("older")),
+        };
+        assert!(verify_signature(Some(&secrets), &signatures, PAYLOAD).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_gitlab_token() {
+        let secret = secrets(&["secret"]);
+        assert!(verify_token(Some(&secret), Some("secret")).is_ok());
+        assert!(verify_token(Some(&secrets(&[""])), None).is_ok());
+        assert!(verify_token(Some(&secrets(&["new", "old"])), Some("old")).is_ok());
+        for token in [None, Some("secreT"), Some("secret2")] {
+            let rejection = verify_token(Some(&secret), token).unwrap_err();
+            let response = handle_rejection(rejection).await.unwrap().into_response();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        let rejection = verify_token(None, Some("secret")).unwrap_err();
+        let response = handle_rejection(rejection).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn test_delivery_id() {
+        assert_eq!(
+            delivery_id(Some("72d3162e-cc78-11e3-81ab-4c9367dc0958".into())),
+            "72d3162e-cc78-11e3-81ab-4c9367dc0958",
+        );
+        let generated = delivery_id(None);
+        assert!(Uuid::parse_str(&generated).is_ok(), "{}", generated);
+        assert_ne!(generated, delivery_id(None));
+    }
+
+    /// Handles a delivery successfully, returning whether it was new.
+    fn receive(deliveries: &RecentDeliveries, id: &str) -> bool {
+        let new = deliveries.start(id);
+        deliveries.finish(id, true);
+        new
+    }
+
+    #[test]
+    fn test_redeliveries() {
+        let deliveries = RecentDeliveries::new(2);
+        assert!(receive(&deliveries, "a"));
+        assert!(!receive(&deliveries, "a"));
+        assert!(receive(&deliveries, "b"));
+        assert!(receive(&deliveries, "c"));
+        // The oldest id is forgotten once the limit is reached.
+        assert!(receive(&deliveries, "a"));
+        assert!(!receive(&deliveries, "c"));
+    }
+
+    #[test]
+    fn test_failed_deliveries_are_retried() {
+        let deliveries = RecentDeliveries::new(2);
+        assert!(deliveries.start("a"));
+        // Sent again while it's still being handled.
+        assert!(!deliveries.start("a"));
+        assert!(deliveries.ids().is_empty());
+        deliveries.finish("a", false);
+        assert!(deliveries.start("a"));
+        deliveries.finish("a", true);
+        assert!(!deliveries.start("a"));
+        assert_eq!(deliveries.ids(), ["a"]);
+    }
+
+    #[test]
+    fn test_restore_deliveries() {
+        let deliveries = RecentDeliveries::new(2);
+        receive(&deliveries, "a");
+        receive(&deliveries, "b");
+        receive(&deliveries, "a");
+        assert_eq!(deliveries.ids(), ["b", "a"]);
+        let restored = RecentDeliveries::new(2);
+        restored.restore(deliveries.ids());
+        receive(&restored, "c");
+        // The order is kept, so the oldest id is still forgotten first.
+        assert!(!receive(&restored, "a"));
+        assert!(receive(&restored, "b"));
+    }
+
+    #[tokio::test]
+    async fn test_repeated_rejections() {
+        time::pause();
+        let rejections = Rejections::new(2);
+        for _ in 1..REJECTIONS_BEFORE_NOTICE {
+            assert!(!rejections.record("smogon/*"));
+        }
+        assert!(!rejections.record("xfix/psdevbot-rust"));
+        assert!(rejections.record("smogon/*"));
+        assert!(rejections.record("smogon/*"));
+        time::advance(REJECTION_WINDOW).await;
+        assert!(!rejections.record("smogon/*"));
+        // Only the latest projects are counted.
+        for _ in 1..REJECTIONS_BEFORE_NOTICE {
+            rejections.record("xfix/psdevbot-rust");
+        }
+        rejections.record("a/b");
+        rejections.record("c/d");
+        assert!(!rejections.record("xfix/psdevbot-rust"));
+    }
+
+    fn shared_config(variables: &[(&str, &str)]) -> &'static SharedConfig {
+        let config = Config::from_sources(ConfigFile::default(), |name| {
+            let value = match name {
+                "PSDEVBOT_SERVER" => Some("wss://localhost/showdown/websocket"),
+                "PSDEVBOT_USER" => Some("user"),
+                "PSDEVBOT_PASSWORD" => Some("password"),
+                "PSDEVBOT_SECRET" => Some("secret"),
+                "PSDEVBOT_ROOM" => Some("room"),
+                _ => None,
+            };
+            variables
+                .iter()
+                .find(|(variable, _)| *variable == name)
+                .map(|(_, value)| *value)
+                .or(value)
+                .map(String::from)
+        })
+        .unwrap();
+        Box::leak(Box::new(SharedConfig::new(config, Args::default())))
+    }
+
+    fn pending() -> &'static PendingEvents {
+        Box::leak(Box::new(PendingEvents::default()))
+    }
+
+    /// Sends a `ping` delivery for `a/b` signed with `secret` through the
+    /// whole webhook route.
+    async fn delivery_status(variables: &[(&str, &str)], path: &str, secret: &str) -> StatusCode {
+        let config = shared_config(variables);
+        let connection = Box::leak(Box::new(Connection::new()));
+        warp::test::request()
+            .method("POST")
+            .path(path)
+            .header("X-GitHub-Event", "ping")
+            .header("X-Hub-Signature-256", sha256(secret))
+            .body(PAYLOAD)
+            .reply(&get_route(config, connection, pending()).recover(handle_rejection))
+            .await
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_allowed_cidrs() {
+        let config = shared_config(&[("PSDEVBOT_ALLOWED_CIDRS", "192.30.252.0/22")]);
+        let connection = Box::leak(Box::new(Connection::new()));
+        let route = get_route(config, connection, pending()).recover(handle_rejection);
+        for (remote, status) in [
+            ("192.30.252.10:4000", StatusCode::OK),
+            ("10.0.0.1:4000", StatusCode::FORBIDDEN),
+        ] {
+            let response = warp::test::request()
+                .method("POST")
+                .path("/github/callback")
+                .remote_addr(remote.parse().unwrap())
+                .header("X-GitHub-Event", "ping")
+                .header("X-Hub-Signature-256", sha256("secret"))
+                .body(PAYLOAD)
+                .reply(&route)
+                .await;
+            assert_eq!(response.status(), status, "{}", remote);
+        }
+    }
+
+    /// Sends a delivery through the whole webhook route, returning how
+    /// many messages it sent to Showdown.
+    async fn messages(disabled_events: &str, event: &str, payload: &'static [u8]) -> usize {
+        let config = shared_config(&[("PSDEVBOT_DISABLED_EVENTS", disabled_events)]);
+        deliver(config, event, payload).await
+    }
+
+    async fn deliver(config: &'static SharedConfig, event: &str, payload: &'static [u8]) -> usize {
+        sent(config, event, payload).await.len()
+    }
+
+    /// Sends a delivery through the whole webhook route, returning the
+    /// messages it sent to Showdown.
+    async fn sent(
+        config: &'static SharedConfig,
+        event: &str,
+        payload: &'static [u8],
+    ) -> Vec<SendMessage> {
+        let connection = Box::leak(Box::new(Connection::new()));
+        let (tx, rx) = mpsc::unbounded();
+        let sender = Arc::new(DelayedSender::new(tx, Duration::from_millis(1)));
+        connection.connect(Arc::clone(&sender));
+        let response = warp::test::request()
+            .method("POST")
+            .path("/github/callback")
+            .header("X-GitHub-Event", event)
+            .header("X-Hub-Signature-256", sha256_of("secret", payload))
+            .body(payload)
+            .reply(&get_route(config, connection, pending()).recover(handle_rejection))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        sender.close().await;
+        rx.collect().await
+    }
+
+    /// Delivers several events to the same route, returning the messages
+    /// sent until `wait` after the last one.
+    async fn sent_together(
+        config: &'static SharedConfig,
+        deliveries: &[(&str, &'static [u8])],
+        wait: Duration,
+    ) -> Vec<SendMessage> {
+        let connection = Box::leak(Box::new(Connection::new()));
+        let (tx, rx) = mpsc::unbounded();
+        let sender = Arc::new(DelayedSender::new(tx, Duration::from_millis(1)));
+        connection.connect(Arc::clone(&sender));
+        let route = get_route(config, connection, pending()).recover(handle_rejection);
+        for (event, payload) in deliveries {
+            let response = warp::test::request()
+                .method("POST")
+                .path("/github/callback")
+                .header("X-GitHub-Event", *event)
+                .header("X-Hub-Signature-256", sha256_of("secret", payload))
+                .body(payload)
+                .reply(&route)
+                .await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        time::sleep(wait).await;
+        sender.close().await;
+        rx.collect().await
+    }
+
+    /// Lists the rooms messages were sent to, in order.
+    fn rooms(messages: &[SendMessage]) -> Vec<String> {
+        messages
+            .iter()
+            .map(|message| {
+                let message = format!("{:?}", message);
+                let start = message.find('"').unwrap() + 1;
+                let end = message.find('|').unwrap();
+                message[start..end].to_owned()
+            })
+            .collect()
+    }
+
+    /// Builds a payload from a fixture, setting the fields at the given
+    /// JSON pointers.
+    fn changed_fixture(fixture: &str, changes: &[(&str, serde_json::Value)]) -> &'static [u8] {
+        let mut payload: serde_json::Value = serde_json::from_str(fixture).unwrap();
+        for (pointer, value) in changes {
+            let (parent, key) = pointer.rsplit_once('/').unwrap();
+            let parent = payload
+                .pointer_mut(parent)
+                .unwrap()
+                .as_object_mut()
+                .unwrap();
+            parent.insert(key.into(), value.clone());
+        }
+        Box::leak(serde_json::to_vec(&payload).unwrap().into_boxed_slice())
+    }
+
+    #[tokio::test]
+    async fn test_merge_announcement_waits_for_checks() {
+        time::pause();
+        let config = shared_config(&[(
+            "PSDEVBOT_PROJECT_CONFIGURATION",
+            r#"{"smogon/pokemon-showdown": {"rooms": ["dev"], "checks_passed": true}}"#,
+        )]);
+        let sha = "8c3b6f0d2e4a9f1b7c5d3e2a1f0b9c8d7e6f5a4b";
+        let merged = changed_fixture(
+            include_str!("../../testdata/pull_request_labeled.json"),
+            &[
+                ("/action", "closed".into()),
+                ("/pull_request/merged", true.into()),
+                ("/pull_request/merge_commit_sha", sha.into()),
+            ],
+        );
+        let status = |state: &str| {
+            changed_fixture(
+                include_str!("../../testdata/status_failure.json"),
+                &[("/state", state.into())],
+            )
+        };
+        let messages = sent_together(
+            config,
+            &[
+                ("pull_request", merged),
+                ("status", status("pending")),
+                ("status", status("success")),
+            ],
+            super::CHECKS_SETTLE_TIME * 2,
+        )
+        .await;
+        assert_eq!(rooms(&messages), ["dev"]);
+        let message = format!("{:?}", messages[0]);
+        assert!(message.contains(" (all checks passed)"), "{}", message);
+    }
+
+    #[tokio::test]
+    async fn test_merge_announcement_with_failed_check() {
+        let config = shared_config(&[(
+            "PSDEVBOT_PROJECT_CONFIGURATION",
+            r#"{"smogon/pokemon-showdown": {"rooms": ["dev"], "checks_passed": true}}"#,
+        )]);
+        let merged = changed_fixture(
+            include_str!("../../testdata/pull_request_labeled.json"),
+            &[
+                ("/action", "closed".into()),
+                ("/pull_request/merged", true.into()),
+                (
+                    "/pull_request/merge_commit_sha",
+                    "8c3b6f0d2e4a9f1b7c5d3e2a1f0b9c8d7e6f5a4b".into(),
+                ),
+            ],
+        );
+        let failure = include_bytes!("../../testdata/status_failure.json");
+        let messages = sent_together(
+            config,
+            &[("pull_request", merged), ("status", failure)],
+            Duration::ZERO,
+        )
+        .await;
+        assert_eq!(rooms(&messages), ["dev", "dev"]);
+        assert!(!format!("{:?}", messages[0]).contains("all checks passed"));
+        assert!(format!("{:?}", messages[1]).contains(" failed on "));
+    }
+
+    #[tokio::test]
+    async fn test_created_tags_are_announced_once() {
+        let config = shared_config(&[(
+            "PSDEVBOT_PROJECT_CONFIGURATION",
+            r#"{"smogon/pokemon-showdown": {"rooms": ["dev"], "branches": ["master", "refs/tags/*"]}}"#,
+        )]);
+        let create = include_bytes!("../../testdata/create_tag.json");
+        assert_eq!(rooms(&sent(config, "create", create).await), ["dev"]);
+        let push = include_bytes!("../../testdata/push_tag.json");
+        assert_eq!(rooms(&sent(config, "push", push).await), ["dev"]);
+        let create = changed_fixture(
+            include_str!("../../testdata/create_tag.json"),
+            &[("/ref", "v1.2.3".into())],
+        );
+        for deliveries in [
+            [("push", &push[..]), ("create", create)],
+            [("create", create), ("push", &push[..])],
+        ] {
+            let messages = sent_together(config, &deliveries, Duration::ZERO).await;
+            assert_eq!(rooms(&messages), ["dev"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ignored_tags_are_not_created() {
+        let create = include_bytes!("../../testdata/create_tag.json");
+        let config = shared_config(&[(
+            "PSDEVBOT_PROJECT_CONFIGURATION",
+            r#"{"smogon/pokemon-showdown": {"rooms": ["dev"], "ignored_branches": ["refs/tags/v1.*"]}}"#,
+        )]);
+        assert!(sent(config, "create", create).await.is_empty());
+        let config = shared_config(&[("PSDEVBOT_IGNORED_BRANCHES", "v1.*, refs/tags/v1.2.*")]);
+        assert!(sent(config, "create", create).await.is_empty());
+        let config = shared_config(&[("PSDEVBOT_IGNORED_BRANCHES", "v1.*")]);
+        assert_eq!(sent(config, "create", create).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_deleted_protected_branch() {
+        let config = shared_config(&[(
+            "PSDEVBOT_PROJECT_CONFIGURATION",
+            r#"{"smogon/pokemon-showdown": {
+                "rooms": ["dev"],
+                "branches": ["master"],
+                "protected_branches": ["old-*"]
+            }}"#,
+        )]);
+        let push = changed_fixture(
+            include_str!("../../testdata/push_tag.json"),
+            &[
+                ("/ref", "refs/heads/old-feature".into()),
+                ("/after", "0000000000000000000000000000000000000000".into()),
+                ("/created", false.into()),
+                ("/deleted", true.into()),
+                ("/commits", serde_json::json!([])),
+                ("/head_commit", serde_json::Value::Null),
+            ],
+        );
+        let delete = include_bytes!("../../testdata/delete_branch.json");
+        for deliveries in [
+            &[("push", push)][..],
+            &[("delete", &delete[..])],
+            &[("push", push), ("delete", &delete[..])],
+            &[("delete", &delete[..]), ("push", push)],
+        ] {
+            let messages = sent_together(config, deliveries, Duration::ZERO).await;
+            assert_eq!(rooms(&messages), ["dev"]);
+            let message = format!("{:?}", messages[0]);
+            assert!(message.contains("<b>\u{26a0} "), "{}", message);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_room_filters() {
+        let config = shared_config(&[(
+            "PSDEVBOT_PROJECT_CONFIGURATION",
+            r#"{"smogon/pokemon-showdown": {
+                "rooms": ["dev", "Lobby"],
+                "branches": ["release-*"],
+                "room_filters": {
+                    "Lobby": {"events": ["create"], "default_branch_only": true},
+                    "dev": {"ignored_users": ["zarel"]}
+                }
+            }}"#,
+        )]);
+        let issue = include_bytes!("../../testdata/issues_opened.json");
+        assert!(rooms(&sent(config, "issues", issue).await).is_empty());
+        let create = include_bytes!("../../testdata/create_branch.json");
+        assert!(rooms(&sent(config, "create", create).await).is_empty());
+        let config = shared_config(&[(
+            "PSDEVBOT_PROJECT_CONFIGURATION",
+            r#"{"smogon/pokemon-showdown": {
+                "rooms": ["dev", "lobby"],
+                "branches": ["release-*"],
+                "prefix": "[PS]",
+                "room_filters": {
+                    "lobby": {"events": ["create"], "prefix": "[dev]"},
+                    "dev": {"branches": ["master"]}
+                }
+            }}"#,
+        )]);
+        assert_eq!(rooms(&sent(config, "issues", issue).await), ["dev"]);
+        let messages = sent(config, "create", create).await;
+        assert_eq!(rooms(&messages), ["lobby"]);
+        assert!(format!("{:?}", messages[0]).contains("addhtmlbox [dev] "));
+    }
+
+    #[tokio::test]
+    async fn test_pull_request_review_in_simple_rooms() {
+        let config = shared_config(&[(
+            "PSDEVBOT_PROJECT_CONFIGURATION",
+            r#"{"smogon/pokemon-showdown": {"rooms": ["dev"], "simple_rooms": ["lobby"]}}"#,
+        )]);
+        let review = include_bytes!("../../testdata/pull_request_review_approved.json");
+        assert_eq!(
+            rooms(&sent(config, "pull_request_review", review).await),
+            ["dev", "lobby"],
+        );
+    }
+
+    #[tokio::test]
+    async fn test_commit_comment_in_simple_rooms() {
+        let config = shared_config(&[(
+            "PSDEVBOT_PROJECT_CONFIGURATION",
+            r#"{"smogon/pokemon-showdown": {"rooms": ["dev"], "simple_rooms": ["lobby"]}}"#,
+        )]);
+        let comment = include_bytes!("../../testdata/commit_comment.json");
+        let messages = sent(config, "commit_comment", comment).await;
+        assert_eq!(rooms(&messages), ["dev", "lobby"]);
+        assert!(!format!("{:?}", messages[1]).contains("Z-Moves"));
+    }
+
+    #[tokio::test]
+    async fn test_labels_in_simple_rooms() {
+        let config = shared_config(&[(
+            "PSDEVBOT_PROJECT_CONFIGURATION",
+            r#"{"smogon/pokemon-showdown": {"rooms": ["dev"], "simple_rooms": ["lobby"], "announced_labels": ["Needs Review"]}}"#,
+        )]);
+        let labeled = include_bytes!("../../testdata/pull_request_labeled.json");
+        let messages = sent(config, "pull_request", labeled).await;
+        assert_eq!(rooms(&messages), ["dev", "lobby"]);
+        let message = format!("{:?}", messages[1]);
+        assert!(
+            message.contains("added label Needs Review to PR#"),
+            "{}",
+            message
+        );
+        assert!(!message.contains("htmlbox"), "{}", message);
+    }
+
+    #[tokio::test]
+    async fn test_actions_suites_not_announced_twice() {
+        let projects = r#"{"smogon/pokemon-showdown": {
+            "rooms": ["dev", "lobby"],
+            "room_filters": {"lobby": {"events": ["check_suite"]}}
+        }}"#;
+        let suite = include_bytes!("../../testdata/check_suite_completed.json");
+        let config = shared_config(&[("PSDEVBOT_PROJECT_CONFIGURATION", projects)]);
+        assert_eq!(rooms(&sent(config, "check_suite", suite).await), ["lobby"]);
+        let config = shared_config(&[
+            ("PSDEVBOT_PROJECT_CONFIGURATION", projects),
+            ("PSDEVBOT_DISABLED_EVENTS", "workflow_run"),
+        ]);
+        assert_eq!(
+            rooms(&sent(config, "check_suite", suite).await),
+            ["dev", "lobby"],
+        );
+    }
+
+    #[tokio::test]
+    async fn test_disabled_events() {
+        assert_eq!(messages("pull_request", "issues", ISSUE_PAYLOAD).await, 1);
+        assert_eq!(
+            messages("pull_request,issues", "issues", ISSUE_PAYLOAD).await,
+            0,
+        );
+    }
+
+    /// Sends an `issues` delivery with the given id through the whole
+    /// webhook route.
+    async fn delivery_with_id(
+        config: &'static SharedConfig,
+        connection: &'static Connection,
+        id: &str,
+    ) -> StatusCode {
+        warp::test::request()
+            .method("POST")
+            .path("/github/callback")
+            .header("X-GitHub-Event", "issues")
+            .header("X-GitHub-Delivery", id)
+            .header("X-Hub-Signature-256", sha256_of("secret", ISSUE_PAYLOAD))
+            .body(ISSUE_PAYLOAD)
+            .reply(&get_route(config, connection, pending()).recover(handle_rejection))
+            .await
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_failed_delivery_is_retried() {
+        let config = shared_config(&[]);
+        let id = Uuid::new_v4().to_string();
+        // Announcing fails while disconnected.
+        let disconnected = Box::leak(Box::new(Connection::new()));
+        assert_ne!(
+            delivery_with_id(config, disconnected, &id).await,
+            StatusCode::OK,
+        );
+        let connection = Box::leak(Box::new(Connection::new()));
+        let (tx, rx) = mpsc::unbounded();
+        let sender = Arc::new(DelayedSender::new(tx, Duration::from_millis(1)));
+        connection.connect(Arc::clone(&sender));
+        for _ in 0..2 {
+            assert_eq!(
+                delivery_with_id(config, connection, &id).await,
+                StatusCode::OK,
+            );
+        }
+        sender.close().await;
+        assert_eq!(rx.collect::<Vec<_>>().await.len(), 1);
+    }
+
+    const MERGE_REQUEST_HOOK: &[u8] = br#"{
+        "object_kind": "merge_request",
+        "user": {"name": "Administrator", "username": "root"},
+        "project": {
+            "name": "Diaspora",
+            "path_with_namespace": "mike/diaspora",
+            "web_url": "http://example.com/mike/diaspora",
+            "default_branch": "master"
+        },
+        "object_attributes": {
+            "iid": 1,
+            "title": "MS-Viewport",
+            "url": "http://example.com/mike/diaspora/merge_requests/1",
+            "action": "open"
+        }
+    }"#;
+
+    /// Sends a GitLab merge request delivery with the given id to `path`,
+    /// returning its status and the messages it sent to Showdown.
+    async fn gitlab_delivery(
+        config: &'static SharedConfig,
+        path: &str,
+        id: &str,
+    ) -> (StatusCode, Vec<SendMessage>) {
+        let connection = Box::leak(Box::new(Connection::new()));
+        let (tx, rx) = mpsc::unbounded();
+        let sender = Arc::new(DelayedSender::new(tx, Duration::from_millis(1)));
+        connection.connect(Arc::clone(&sender));
+        let response = warp::test::request()
+            .method("POST")
+            .path(path)
+            .header("X-Gitlab-Event", "Merge Request Hook")
+            .header("X-Gitlab-Event-UUID", id)
+            .header("X-Gitlab-Token", "secret")
+            .body(MERGE_REQUEST_HOOK)
+            .reply(&get_route(config, connection, pending()).recover(handle_rejection))
+            .await;
+        sender.close().await;
+        (response.status(), rx.collect().await)
+    }
+
+    #[tokio::test]
+    async fn test_gitlab_webhook_path() {
+        let config = shared_config(&[("PSDEVBOT_WEBHOOK_PATH", "/psdevbot")]);
+        let id = Uuid::new_v4().to_string();
+        let (status, messages) = gitlab_delivery(config, "/psdevbot", &id).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(messages.len(), 1);
+        let id = Uuid::new_v4().to_string();
+        let (status, _) = gitlab_delivery(config, "/gitlab/callback", &id).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_gitlab_redelivery_is_ignored() {
+        let config = shared_config(&[]);
+        let id = Uuid::new_v4().to_string();
+        let (status, messages) = gitlab_delivery(config, "/github/callback", &id).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(messages.len(), 1);
+        let (status, messages) = gitlab_delivery(config, "/github/callback", &id).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(messages.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_ping() {
+        assert_eq!(messages("", "ping", PING_PAYLOAD).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_organization_ping() {
+        let payload = br#"{
+            "zen": "Keep it logically awesome.",
+            "hook_id": 1,
+            "hook": {"type": "Organization"},
+            "organization": {"login": "smogon"}
+        }"#;
+        assert_eq!(messages("", "ping", payload).await, 0);
+        let config = shared_config(&[]);
+        let connection = Box::leak(Box::new(Connection::new()));
+        let response = warp::test::request()
+            .method("POST")
+            .path("/github/callback")
+            .header("X-GitHub-Event", "ping")
+            .header("X-Hub-Signature-256", sha256_of("other", payload))
+            .body(&payload[..])
+            .reply(&get_route(config, connection, pending()).recover(handle_rejection))
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_star_milestones() {
+        let payload = include_bytes!("../../testdata/star.json");
+        assert_eq!(messages("", "star", payload).await, 1);
+        // Starring again after unstarring doesn't repeat the milestone.
+        assert_eq!(messages("", "star", payload).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_renamed_repository() {
+        let config = shared_config(&[(
+            "PSDEVBOT_PROJECT_CONFIGURATION",
+            r#"{"renamed/old": {"rooms": ["dev"]}}"#,
+        )]);
+        let push = br#"{"ref": "refs/heads/master", "repository": {"full_name": "renamed/new"}}"#;
+        let signatures = Signatures {
+            sha256: Some(sha256_of("secret", push)),
+            sha1: None,
+        };
+        let rooms = || {
+            get_rooms(&config.get(), &signatures, push, None)
+                .ok()
+                .flatten()
+                .map(|room_configuration| room_configuration.rooms.to_vec())
+        };
+        assert_eq!(rooms(), Some(vec!["room".to_string()]));
+        assert_eq!(deliver(config, "repository", RENAME_PAYLOAD).await, 1);
+        // Pushes under the new name keep using the old configuration.
+        assert_eq!(rooms(), Some(vec!["dev".to_string()]));
+    }
+
+    /// Log output captured by a test.
+    #[derive(Clone, Default)]
+    struct Logs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Logs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_push_after_rename() {
+        let config = shared_config(&[(
+            "PSDEVBOT_PROJECT_CONFIGURATION",
+            r#"{"moved/old": {"rooms": ["dev"], "branches": ["refs/tags/*"]}}"#,
+        )]);
+        // Renames are remembered by the whole process, so this uses other
+        // names than test_renamed_repository.
+        let rename = changed_fixture(
+            std::str::from_utf8(RENAME_PAYLOAD).unwrap(),
+            &[("/repository/full_name", "moved/new".into())],
+        );
+        assert_eq!(deliver(config, "repository", rename).await, 1);
+        let push = changed_fixture(
+            include_str!("../../testdata/push_tag.json"),
+            &[
+                ("/repository/name", "new".into()),
+                ("/repository/full_name", "moved/new".into()),
+            ],
+        );
+        let logs = Logs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .finish();
+        let messages = {
+            let _guard = tracing::subscriber::set_default(subscriber);
+            sent(config, "push", push).await
+        };
+        assert_eq!(rooms(&messages), ["dev"]);
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(
+            logs.contains("Using the configuration of moved/old for moved/new, which was renamed"),
+            "{}",
+            logs,
+        );
+    }
+
+    #[tokio::test]
+    async fn test_webhooks_are_refused_when_shutting_down() {
+        let config = shared_config(&[]);
+        let connection = Box::leak(Box::new(Connection::new()));
+        connection.shut_down();
+        let response = warp::test::request()
+            .method("POST")
+            .path("/github/callback")
+            .header("X-GitHub-Event", "ping")
+            .header("X-Hub-Signature-256", sha256("secret"))
+            .body(PAYLOAD)
+            .reply(&get_route(config, connection, pending()).recover(handle_rejection))
+            .await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_webhook_path() {
+        assert_eq!(
+            delivery_status(&[], "/github/callback", "secret").await,
+            StatusCode::OK,
+        );
+        let variables = [("PSDEVBOT_WEBHOOK_PATH", "/github/psdevbot")];
+        assert_eq!(
+            delivery_status(&variables, "/github/psdevbot", "secret").await,
+            StatusCode::OK,
+        );
+        assert_eq!(
+            delivery_status(&variables, "/github/callback", "secret").await,
+            StatusCode::NOT_FOUND,
+        );
+    }
+
+    #[tokio::test]
+    async fn test_project_secret_overrides_default_secret() {
+        let variables = [(
+            "PSDEVBOT_PROJECT_CONFIGURATION",
+            r#"{"a/b": {"rooms": ["dev"], "secret": "project"}}"#,
+        )];
+        let path = "/github/callback";
+        assert_eq!(
+            delivery_status(&variables, path, "project").await,
+            StatusCode::OK,
+        );
+        assert_eq!(
+            delivery_status(&variables, path, "secret").await,
+            StatusCode::UNAUTHORIZED,
+        );
+    }
+
+    #[tokio::test]
+    async fn test_default_secret_is_used_for_other_projects() {
+        let variables = [(
+            "PSDEVBOT_PROJECT_CONFIGURATION",
+            r#"{"c/d": {"rooms": ["dev"], "secret": "project"}}"#,
+        )];
+        let path = "/github/callback";
+        assert_eq!(
+            delivery_status(&variables, path, "secret").await,
+            StatusCode::OK,
+        );
+        assert_eq!(
+            delivery_status(&variables, path, "project").await,
+            StatusCode::UNAUTHORIZED,
+        );
+    }
+
+    #[tokio::test]
+    async fn test_https_delivery() {
+        let config = shared_config(&[
+            ("PSDEVBOT_PORT", "0"),
+            (
+                "PSDEVBOT_TLS_CERT",
+                concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/localhost.crt"),
+            ),
+            (
+                "PSDEVBOT_TLS_KEY",
+                concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/localhost.key"),
+            ),
+        ]);
+        let connection = Box::leak(Box::new(Connection::new()));
+        let (address, _server) = start_server(config, connection, pending());
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap();
+        let response = client
+            .post(format!(
+                "https://localhost:{}/github/callback",
+                address.port()
+            ))
+            .header("X-GitHub-Event", "ping")
+            .header("X-Hub-Signature-256", sha256("secret"))
+            .body(PAYLOAD)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_bind_address() {
+        let config = shared_config(&[("PSDEVBOT_PORT", "0"), ("PSDEVBOT_BIND", "127.0.0.1")]);
+        let connection = Box::leak(Box::new(Connection::new()));
+        let (address, _server) = start_server(config, connection, pending());
+        assert_eq!(address.ip(), IpAddr::from([127, 0, 0, 1]));
+        let response = reqwest::get(format!("http://127.0.0.1:{}/health", address.port()))
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_health_route() {
+        let connection = Box::leak(Box::new(Connection::new()));
+        let response = warp::test::request()
+            .path("/health")
+            .reply(&health_route(connection))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let health: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(health["showdown"], "reconnecting");
+        assert!(health["uptime_secs"].is_u64());
+    }
 }
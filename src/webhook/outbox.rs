@@ -0,0 +1,297 @@
+use super::schema::escape_plain;
+use super::{html_command, reject};
+use crate::config::RoomFilters;
+use crate::connection::Connection;
+use crate::metrics::METRICS;
+use crate::quiet_hours::{Digests, Format, QuietHours};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use htmlescape::encode_minimal as h;
+use showdown::{RoomId, SendMessage};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::time;
+use tracing::{error, info, warn};
+use warp::Rejection;
+
+/// Sends announcements for a single delivery, holding them back during
+/// the project's quiet hours.
+pub struct Outbox {
+    pub connection: &'static Connection,
+    pub digests: Arc<Digests>,
+    pub quiet_hours: Option<QuietHours>,
+    pub timezone: Tz,
+    pub dry_run: bool,
+    /// Shown at the start of every message, see `prefix` in the project
+    /// configuration.
+    pub prefix: Option<String>,
+    /// Validated when loading the configuration, see `prefix_color`.
+    pub prefix_color: Option<String>,
+    /// See `room_filters` in the project configuration.
+    pub room_filters: BTreeMap<String, RoomFilters>,
+    /// GitHub event type being announced, checked against the rooms'
+    /// `events`.
+    pub event: String,
+    /// User causing the event, see [`Outbox::by_user`].
+    pub user: Option<String>,
+    /// Branch the event is about and the repository's default branch, see
+    /// [`Outbox::on_branch`].
+    pub branch: Option<(String, String)>,
+}
+
+impl Outbox {
+    /// Records who caused the event, so that rooms ignoring them are
+    /// skipped.
+    pub fn by_user(mut self, login: &str) -> Self {
+        self.user = Some(login.into());
+        self
+    }
+
+    /// Records the branch the event is about, so that rooms not following
+    /// it are skipped.
+    pub fn on_branch(mut self, branch: &str, default_branch: &str) -> Self {
+        self.branch = Some((branch.into(), default_branch.into()));
+        self
+    }
+
+    /// Checks the room's filters, if it has any.
+    fn accepts(&self, room: &str) -> bool {
+        self.accepts_event(room, &self.event)
+    }
+
+    /// Checks whether the room's filters let through the given event,
+    /// from the same user and on the same branch as this one.
+    pub fn accepts_event(&self, room: &str, event: &str) -> bool {
+        let filters = match self.room_filters.get(room) {
+            Some(filters) => filters,
+            None => return true,
+        };
+        filters.accepts_event(event)
+            && self
+                .user
+                .as_ref()
+                .is_none_or(|user| !filters.ignores_user(user))
+            && self.branch.as_ref().is_none_or(|(branch, default_branch)| {
+                filters.accepts_branch(branch, default_branch)
+            })
+    }
+
+    pub async fn send_html(&self, room: &str, html: &str) -> Result<(), Rejection> {
+        self.send(room, Format::Html, html).await
+    }
+
+    /// Sends a plain chat message, which needs to be a single line.
+    pub async fn send_text(&self, room: &str, text: &str) -> Result<(), Rejection> {
+        self.send(room, Format::PlainText, text).await
+    }
+
+    /// Sends a message in the given format, see [`Outbox::send_html`] and
+    /// [`Outbox::send_text`].
+    pub async fn send(&self, room: &str, format: Format, text: &str) -> Result<(), Rejection> {
+        if !self.accepts(room) {
+            info!("Not announcing {} in {}, filtered out", self.event, room);
+            return Ok(());
+        }
+        let text = &*self.prefixed(room, format, text);
+        if self.dry_run {
+            info!("Dry run, not sending a message to {}: {}", room, text);
+            return Ok(());
+        }
+        if let Some(quiet_hours) = self.quiet_hours {
+            let now = DateTime::<Utc>::from(SystemTime::now())
+                .with_timezone(&self.timezone)
+                .time();
+            if quiet_hours.contains(now) {
+                info!(
+                    "Holding back an announcement for {} until quiet hours end",
+                    room
+                );
+                if self.digests.push(room, format, text.into()) {
+                    self.schedule_digest(room.into(), format, quiet_hours.remaining(now));
+                }
+                return Ok(());
+            }
+        }
+        let sender = self.connection.sender().ok_or_else(|| {
+            error!("Failed to send a message to {}: not connected", room);
+            reject("Not connected to Showdown")
+        })?;
+        sender
+            .send_to_room(room, message(room, format, text))
+            .await
+            .map_err(|e| {
+                error!("Failed to send a message to {}: {}", room, e);
+                reject(e)
+            })?;
+        info!("Sent a message to {}", room);
+        METRICS.message_sent(room);
+        Ok(())
+    }
+
+    /// Prefixes a message with the room's prefix, or the project's one
+    /// when the room doesn't set its own.
+    fn prefixed<'a>(&self, room: &str, format: Format, text: &'a str) -> Cow<'a, str> {
+        let prefix = match self.room_filters.get(room) {
+            Some(RoomFilters {
+                prefix: Some(prefix),
+                ..
+            }) => Some(prefix).filter(|prefix| !prefix.is_empty()),
+            _ => self.prefix.as_ref(),
+        };
+        match (prefix, format) {
+            (None, _) => Cow::Borrowed(text),
+            (Some(prefix), Format::Html) => match &self.prefix_color {
+                Some(color) => Cow::Owned(format!(
+                    "<font color=\"{}\">{}</font> {}",
+                    color,
+                    h(prefix),
+                    text,
+                )),
+                None => Cow::Owned(format!("{} {}", h(prefix), text)),
+            },
+            // Markup in the prefix would otherwise run into the message.
+            (Some(prefix), Format::PlainText) => {
+                Cow::Owned(format!("{} {}", escape_plain(prefix), text))
+            }
+        }
+    }
+
+    fn schedule_digest(&self, room: String, format: Format, delay: time::Duration) {
+        let connection = self.connection;
+        let digests = Arc::clone(&self.digests);
+        tokio::spawn(async move {
+            time::sleep(delay).await;
+            for digest in digests.take(&room, format) {
+                let sent = match connection.sender() {
+                    Some(sender) => sender
+                        .send_to_room(&room, message(&room, format, &digest))
+                        .await
+                        .is_ok(),
+                    None => false,
+                };
+                if sent {
+                    METRICS.message_sent(&room);
+                } else {
+                    warn!("Lost the quiet hours digest for {}, disconnected", room);
+                }
+            }
+        });
+    }
+}
+
+fn message(room: &str, format: Format, text: &str) -> SendMessage {
+    match format {
+        Format::Html => html_command(room, &format!("addhtmlbox {}", text)),
+        // A zero width space keeps Showdown from running a command.
+        Format::PlainText if text.starts_with(&['/', '!'][..]) => {
+            SendMessage::chat_message(RoomId(room), format_args!("\u{200B}{}", text))
+        }
+        Format::PlainText => SendMessage::chat_message(RoomId(room), text),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{message, Outbox};
+    use crate::config::RoomFilters;
+    use crate::connection::Connection;
+    use crate::quiet_hours::{Digests, Format};
+    use chrono_tz::Tz;
+    use showdown::{RoomId, SendMessage};
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    fn outbox(dry_run: bool) -> Outbox {
+        Outbox {
+            connection: Box::leak(Box::new(Connection::new())),
+            digests: Arc::new(Digests::default()),
+            quiet_hours: None,
+            timezone: Tz::UTC,
+            dry_run,
+            prefix: None,
+            prefix_color: None,
+            room_filters: BTreeMap::new(),
+            event: "push".into(),
+            user: None,
+            branch: None,
+        }
+    }
+
+    #[test]
+    fn test_prefix() {
+        let html = "<b>[server]</b> Zarel pushed 1 commit";
+        let text = "[server] Zarel pushed 1 commit";
+        let mut prefixed = outbox(true);
+        prefixed.prefix = Some("\u{2699} <dev>".into());
+        let mut colored = outbox(true);
+        colored.prefix = Some("**[core]**".into());
+        colored.prefix_color = Some("#6688aa".into());
+        let plain = outbox(true);
+        assert_eq!(
+            prefixed.prefixed("dev", Format::Html, html),
+            "\u{2699} &lt;dev&gt; <b>[server]</b> Zarel pushed 1 commit",
+        );
+        assert_eq!(
+            prefixed.prefixed("dev", Format::PlainText, text),
+            "\u{2699} <dev> [server] Zarel pushed 1 commit",
+        );
+        assert_eq!(
+            colored.prefixed("dev", Format::Html, html),
+            "<font color=\"#6688aa\">**[core]**</font> <b>[server]</b> Zarel pushed 1 commit",
+        );
+        assert_eq!(
+            colored.prefixed("dev", Format::PlainText, text),
+            "*\u{200B}*[core]*\u{200B}* [server] Zarel pushed 1 commit",
+        );
+        assert_eq!(plain.prefixed("dev", Format::Html, html), html);
+        assert_eq!(plain.prefixed("dev", Format::PlainText, text), text);
+    }
+
+    #[test]
+    fn test_room_prefix() {
+        let mut outbox = outbox(true);
+        outbox.prefix = Some("[project]".into());
+        for (room, prefix) in [("lobby", Some("[lobby]")), ("help", Some(""))] {
+            let filters = RoomFilters {
+                prefix: prefix.map(String::from),
+                ..RoomFilters::default()
+            };
+            outbox.room_filters.insert(room.into(), filters);
+        }
+        let text = "Zarel pushed 1 commit";
+        assert_eq!(
+            outbox.prefixed("dev", Format::PlainText, text),
+            "[project] Zarel pushed 1 commit",
+        );
+        assert_eq!(
+            outbox.prefixed("lobby", Format::PlainText, text),
+            "[lobby] Zarel pushed 1 commit",
+        );
+        assert_eq!(outbox.prefixed("help", Format::PlainText, text), text);
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_does_not_send() {
+        assert!(outbox(false).send_html("dev", "hi").await.is_err());
+        assert!(outbox(true).send_html("dev", "hi").await.is_ok());
+        assert!(outbox(true).send_text("dev", "hi").await.is_ok());
+    }
+
+    #[test]
+    fn test_plain_text_commands_are_escaped() {
+        assert_eq!(
+            message("dev", Format::PlainText, "/ban everyone"),
+            SendMessage::chat_message(RoomId("dev"), "\u{200B}/ban everyone"),
+        );
+        assert_eq!(
+            message("dev", Format::PlainText, "!dt pikachu"),
+            SendMessage::chat_message(RoomId("dev"), "\u{200B}!dt pikachu"),
+        );
+        assert_eq!(
+            message("dev", Format::PlainText, "[server] hi"),
+            SendMessage::chat_message(RoomId("dev"), "[server] hi"),
+        );
+    }
+}
@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Repositories renamed or transferred while the bot was running, so that
+/// they keep using the configuration of their earlier name until it's
+/// updated. Kept in memory only, as the configuration should be fixed
+/// before the next restart.
+#[derive(Debug, Default)]
+pub struct RenamedRepositories {
+    /// Names the repositories had when the bot started, by their current
+    /// name in lowercase.
+    original: Mutex<HashMap<String, String>>,
+}
+
+impl RenamedRepositories {
+    /// Remembers that a repository got a new name. Renaming a repository
+    /// twice still leads to its first name.
+    pub fn renamed(&self, previous_name: &str, name: &str) {
+        let mut original = self.original.lock().unwrap();
+        let first_name = original
+            .remove(&previous_name.to_lowercase())
+            .unwrap_or_else(|| previous_name.into());
+        original.insert(name.to_lowercase(), first_name);
+    }
+
+    /// Returns the name a renamed repository had when the bot started.
+    pub fn original(&self, name: &str) -> Option<String> {
+        self.original
+            .lock()
+            .unwrap()
+            .get(&name.to_lowercase())
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RenamedRepositories;
+
+    #[test]
+    fn test_renames() {
+        let renames = RenamedRepositories::default();
+        assert_eq!(renames.original("a/b"), None);
+        renames.renamed("a/b", "a/c");
+        assert_eq!(renames.original("A/C").as_deref(), Some("a/b"));
+        renames.renamed("a/c", "d/c");
+        assert_eq!(renames.original("d/c").as_deref(), Some("a/b"));
+        assert_eq!(renames.original("a/c"), None);
+    }
+}
@@ -1,9 +1,17 @@
-use crate::config::UsernameAliases;
-use crate::github_api::{GitHubApi, User};
+pub mod gitlab;
+
+use crate::config::{UsernameAliases, DEFAULT_SHA_LENGTH};
+use crate::github_api::{CommitInfo, GitHubApi, PullRequestSummary, User};
+use crate::message_template::MessageTemplate;
+use crate::quiet_hours::Format;
+use crate::webhook::workflows::{CheckState, Transition};
 use askama::Template;
+use chrono::{DateTime, FixedOffset};
+use chrono_tz::Tz;
 use htmlescape::encode_minimal as h;
 use once_cell::sync::Lazy;
 use regex::{Captures, Regex};
+use serde::de::IgnoredAny;
 use serde::Deserialize;
 use std::borrow::Cow;
 
@@ -19,49 +27,388 @@ pub struct InitialRepository<'a> {
     pub full_name: Cow<'a, str>,
 }
 
+/// Sent by GitHub when a webhook is added, to check that it works.
+#[derive(Deserialize)]
+pub struct PingEvent<'a> {
+    /// A random saying of GitHub's.
+    #[serde(borrow, default)]
+    pub zen: Cow<'a, str>,
+    /// Missing for organization webhooks.
+    #[serde(borrow, default)]
+    pub repository: Option<InitialRepository<'a>>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct PushEvent<'a> {
     #[serde(borrow, rename = "ref")]
     git_ref: Cow<'a, str>,
     #[serde(borrow)]
+    before: Cow<'a, str>,
+    #[serde(borrow)]
+    after: Cow<'a, str>,
+    #[serde(borrow)]
     commits: Vec<Commit<'a>>,
     #[serde(borrow)]
     pusher: Pusher<'a>,
     #[serde(borrow)]
     pub repository: Repository<'a>,
+    /// Whether the push rewrote history.
+    #[serde(default)]
+    forced: bool,
+    /// Whether the push deleted the branch or tag.
+    #[serde(default)]
+    deleted: bool,
+    /// Open pull request made from the branch, when looked up with
+    /// `pull_request_titles`.
+    #[serde(skip)]
+    pub pull_request: Option<PullRequestSummary>,
+    /// Message of the pushed tag, when it's an annotated tag looked up
+    /// with the GitHub API.
+    #[serde(skip)]
+    pub tag_message: Option<String>,
+    /// Commit the ref points to after the push, missing for deletions.
+    #[serde(borrow, default)]
+    head_commit: Option<PushedCommit<'a>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PushedCommit<'a> {
+    #[serde(borrow)]
+    id: Cow<'a, str>,
 }
 
 pub struct PushEventContext<'a> {
     pub github_api: Option<&'a mut GitHubApi>,
     pub username_aliases: &'a UsernameAliases,
+    pub timezone: Tz,
+    /// How many commits to show before linking to the comparison instead.
+    pub max_commits: usize,
+    /// Longest commit title shown, in characters, before it's cut off.
+    pub max_commit_title_length: usize,
+    /// Number of hex digits of commit ids shown.
+    pub sha_length: usize,
 }
 
-macro_rules! view_method {
-    ($name:ident($s:ident, $($ex:tt)*)) => {
-        pub async fn $name<'a>(&'a $s, mut ctx: PushEventContext<'a>) -> ViewPushEvent<'a> {
-            let mut commits_view = Vec::new();
-            for commit in &$s.commits {
-                commits_view.push(
-                    commit
-                        .$name($($ex)* &mut ctx)
-                        .await
-                        .to_string(),
-                );
-            }
-            ViewPushEvent {
-                commits: commits_view,
-                repository: $s.repository.to_view(),
-            }
+impl PushEvent<'_> {
+    pub async fn to_view<'a>(&'a self, mut ctx: PushEventContext<'a>) -> ViewPushEvent<'a> {
+        let mut commits_view = Vec::new();
+        for commit in self.commits.iter().take(ctx.max_commits) {
+            commits_view.push(
+                commit
+                    .to_view(&self.repository.html_url, &mut ctx)
+                    .await
+                    .to_string(),
+            );
         }
-    };
-}
+        ViewPushEvent {
+            remaining_commits: self.commits.len() - commits_view.len(),
+            commits: commits_view,
+            compare_url: self.compare_url(),
+            pull_request: self.pull_request.as_ref(),
+            repository: self.repository.to_view(),
+        }
+    }
 
-impl PushEvent<'_> {
-    view_method!(to_view(self, &self.repository.html_url,));
-    view_method!(to_simple_view(self,));
+    /// Summarizes the push in a single line linking to the changes,
+    /// without listing the commits. This is used for `simple_rooms`,
+    /// while `rooms` get the detailed [`PushEvent::to_view`].
+    pub fn format_simple<'a>(
+        &'a self,
+        username_aliases: &'a UsernameAliases,
+    ) -> ViewSimplePushEvent<'a> {
+        ViewSimplePushEvent {
+            pusher: username_aliases.get(&self.pusher.name),
+            commits: self.commits.len(),
+            branch: self.branch(),
+            pull_request: self.pull_request.as_ref(),
+            compare_url: self.compare_url(),
+            repository: self.repository.to_view(),
+        }
+    }
+
+    /// Announces the deletion of a branch or tag, which has no commits
+    /// or changes to link to, starting with the warning for deletions of
+    /// protected branches.
+    pub fn format_deleted<'a>(
+        &'a self,
+        username_aliases: &'a UsernameAliases,
+        warning: Option<&'a str>,
+    ) -> ViewDeletedRefEvent<'a> {
+        let (kind, name) = self.ref_name();
+        ViewDeletedRefEvent {
+            warning,
+            pusher: username_aliases.get(&self.pusher.name),
+            kind,
+            name,
+            repository: self.repository.to_view(),
+        }
+    }
+
+    /// Announces a pushed tag, along with the first line of its message
+    /// for annotated tags. Tags have no commits of their own to list.
+    pub fn format_tag<'a>(&'a self, username_aliases: &'a UsernameAliases) -> ViewTagEvent<'a> {
+        ViewTagEvent {
+            pusher: username_aliases.get(&self.pusher.name),
+            tag: self.tag().unwrap_or_default(),
+            tag_url: self.tag_url(),
+            title: self.tag_title(),
+            repository: self.repository.to_view(),
+        }
+    }
+
+    pub fn tag_to_plain_text(&self, username_aliases: &UsernameAliases) -> String {
+        let mut text = format!(
+            "{} {} tagged {} ({})",
+            self.repository.to_plain_text(),
+            escape_plain(username_aliases.get(&self.pusher.name)),
+            escape_plain(self.tag().unwrap_or_default()),
+            self.tag_url(),
+        );
+        if let Some(title) = self.tag_title() {
+            text += ": ";
+            text += &escape_plain(title);
+        }
+        text
+    }
+
+    pub fn deleted_to_plain_text(
+        &self,
+        username_aliases: &UsernameAliases,
+        warning: Option<&str>,
+    ) -> String {
+        let (kind, name) = self.ref_name();
+        let mut text = self.repository.to_plain_text();
+        if let Some(warning) = warning {
+            text += " ";
+            text += &escape_plain(warning);
+        }
+        text += &format!(
+            " {} deleted {} {}",
+            escape_plain(username_aliases.get(&self.pusher.name)),
+            kind,
+            escape_plain(name),
+        );
+        text
+    }
+
+    /// Warns about a force push, linking to the new head of the branch, as
+    /// comparing with the rewritten history often doesn't work.
+    pub fn format_forced<'a>(
+        &'a self,
+        username_aliases: &'a UsernameAliases,
+        prefix: &'a str,
+    ) -> ViewForcedPushEvent<'a> {
+        ViewForcedPushEvent {
+            prefix,
+            pusher: username_aliases.get(&self.pusher.name),
+            branch: self.branch(),
+            head_url: self.head_url(),
+            repository: self.repository.to_view(),
+        }
+    }
+
+    pub fn forced_to_plain_text(&self, username_aliases: &UsernameAliases, prefix: &str) -> String {
+        format!(
+            "{} {} {} force-pushed to {}: {}",
+            self.repository.to_plain_text(),
+            escape_plain(prefix),
+            escape_plain(username_aliases.get(&self.pusher.name)),
+            escape_plain(self.branch()),
+            self.head_url(),
+        )
+    }
+
+    /// Summarizes the push as a plain chat message, for rooms where the
+    /// bot can't use HTML.
+    pub fn to_plain_text(&self, username_aliases: &UsernameAliases) -> String {
+        let commits = self.commits.len();
+        let pull_request = match &self.pull_request {
+            Some(pull_request) => format!(
+                " (PR #{}: {})",
+                pull_request.number,
+                escape_plain(&pull_request.title),
+            ),
+            None => String::new(),
+        };
+        format!(
+            "{} {} pushed {} commit{} to {}{}: {}",
+            self.repository.to_plain_text(),
+            escape_plain(username_aliases.get(&self.pusher.name)),
+            commits,
+            if commits == 1 { "" } else { "s" },
+            escape_plain(self.branch()),
+            pull_request,
+            self.compare_url(),
+        )
+    }
+
+    pub fn format_template(
+        &self,
+        template: &MessageTemplate,
+        username_aliases: &UsernameAliases,
+        format: Format,
+    ) -> String {
+        fill_template(template, format, |placeholder| match placeholder {
+            "user" => username_aliases.get(&self.pusher.name).into(),
+            "repo" => self.repository.to_view().name.into(),
+            "branch" => self.branch().into(),
+            "count" => self.commits.len().to_string(),
+            "url" => self.compare_url(),
+            _ => String::new(),
+        })
+    }
+
+    /// Lists files added, removed or modified by any of the commits.
+    pub fn changed_files(&self) -> impl Iterator<Item = &str> {
+        self.commits.iter().flat_map(|commit| {
+            commit
+                .added
+                .iter()
+                .chain(&commit.removed)
+                .chain(&commit.modified)
+                .map(|file| &**file)
+        })
+    }
+
+    fn compare_url(&self) -> String {
+        format!(
+            "{}/compare/{}...{}",
+            self.repository.html_url,
+            &self.before[..self.before.len().min(12)],
+            &self.after[..self.after.len().min(12)],
+        )
+    }
+
+    fn head_url(&self) -> String {
+        self.repository.commit_url(&self.after)
+    }
+
+    fn tag_url(&self) -> String {
+        format!(
+            "{}/releases/tag/{}",
+            self.repository.html_url,
+            self.tag().unwrap_or_default(),
+        )
+    }
+
+    fn tag_title(&self) -> Option<&str> {
+        let message = self.tag_message.as_deref()?;
+        Some(message.lines().next()?.trim()).filter(|title| !title.is_empty())
+    }
+
+    /// Returns the name of the pushed tag, or `None` for branches.
+    pub fn tag(&self) -> Option<&str> {
+        self.git_ref.strip_prefix("refs/tags/")
+    }
+
+    /// Returns the hash the ref points to after the push, which for
+    /// annotated tags is the tag object.
+    pub fn head(&self) -> &str {
+        &self.after
+    }
+
+    /// Whether the push points a tag to a tag object rather than directly
+    /// to a commit, so that the tag has a message.
+    pub fn is_annotated_tag(&self) -> bool {
+        self.tag().is_some()
+            && self
+                .head_commit
+                .as_ref()
+                .is_some_and(|commit| commit.id != self.after)
+    }
+
+    pub fn pusher(&self) -> &str {
+        &self.pusher.name
+    }
+
+    pub fn is_forced(&self) -> bool {
+        self.forced
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.deleted
+    }
+
+    /// Describes the pushed ref, returning whether it's a branch or a tag
+    /// along with its name.
+    fn ref_name(&self) -> (&'static str, &str) {
+        match self.tag() {
+            Some(tag) => ("tag", tag),
+            None => ("branch", self.branch()),
+        }
+    }
 
     pub fn branch(&self) -> &str {
-        self.git_ref.rsplit('/').next().unwrap()
+        self.git_ref
+            .strip_prefix("refs/heads/")
+            .unwrap_or(&self.git_ref)
+    }
+
+    pub fn git_ref(&self) -> &str {
+        &self.git_ref
+    }
+}
+
+impl<'a> PushEvent<'a> {
+    /// Adds the commits of a later push to the same ref, so that both
+    /// are announced as a single push.
+    pub fn merge(&mut self, later: PushEvent<'a>) {
+        self.after = later.after;
+        self.commits.extend(later.commits);
+    }
+}
+
+impl PushEvent<'static> {
+    /// Describes a commit fetched from the GitHub API as if it was just
+    /// pushed to the branch, so that it can be announced again.
+    pub fn from_commit(repository: &str, branch: &str, commit: &CommitInfo) -> Self {
+        let login = commit.author.as_ref().map(|author| author.login.clone());
+        let before = match commit.parents.first() {
+            Some(parent) => parent.sha.clone(),
+            None => commit.sha.clone(),
+        };
+        PushEvent {
+            git_ref: format!("refs/heads/{}", branch).into(),
+            before: before.into(),
+            after: commit.sha.clone().into(),
+            commits: vec![Commit {
+                id: commit.sha.clone().into(),
+                message: commit.commit.message.clone().into(),
+                author: Author {
+                    name: commit.commit.author.name.clone().into(),
+                    username: login.clone(),
+                },
+                url: commit.html_url.clone().into(),
+                timestamp: commit.commit.author.date,
+                added: Vec::new(),
+                removed: Vec::new(),
+                modified: commit.files().map(|file| file.to_owned().into()).collect(),
+            }],
+            pusher: Pusher {
+                name: login
+                    .unwrap_or_else(|| commit.commit.author.name.clone())
+                    .into(),
+            },
+            repository: Repository {
+                name: repository
+                    .split_once('/')
+                    .map_or(repository, |(_, name)| name)
+                    .to_owned()
+                    .into(),
+                full_name: repository.to_owned().into(),
+                html_url: format!("https://github.com/{}", repository).into(),
+                default_branch: branch.to_owned().into(),
+                forks_count: None,
+                stargazers_count: None,
+                display_name: None,
+                sha_length: DEFAULT_SHA_LENGTH,
+            },
+            forced: false,
+            deleted: false,
+            pull_request: None,
+            tag_message: None,
+            head_commit: None,
+        }
     }
 }
 
@@ -69,6 +416,50 @@ impl PushEvent<'_> {
 #[template(path = "push_event.html")]
 pub struct ViewPushEvent<'a> {
     commits: Vec<String>,
+    remaining_commits: usize,
+    compare_url: String,
+    pull_request: Option<&'a PullRequestSummary>,
+    repository: ViewRepository<'a>,
+}
+
+#[derive(Template)]
+#[template(path = "simple_push_event.html")]
+pub struct ViewSimplePushEvent<'a> {
+    pusher: &'a str,
+    commits: usize,
+    branch: &'a str,
+    pull_request: Option<&'a PullRequestSummary>,
+    compare_url: String,
+    repository: ViewRepository<'a>,
+}
+
+#[derive(Template)]
+#[template(path = "deleted_ref_event.html")]
+pub struct ViewDeletedRefEvent<'a> {
+    warning: Option<&'a str>,
+    pusher: &'a str,
+    kind: &'a str,
+    name: &'a str,
+    repository: ViewRepository<'a>,
+}
+
+#[derive(Template)]
+#[template(path = "tag_event.html")]
+pub struct ViewTagEvent<'a> {
+    pusher: &'a str,
+    tag: &'a str,
+    tag_url: String,
+    title: Option<&'a str>,
+    repository: ViewRepository<'a>,
+}
+
+#[derive(Template)]
+#[template(path = "force_push_event.html")]
+pub struct ViewForcedPushEvent<'a> {
+    prefix: &'a str,
+    pusher: &'a str,
+    branch: &'a str,
+    head_url: String,
     repository: ViewRepository<'a>,
 }
 
@@ -82,35 +473,39 @@ struct Commit<'a> {
     author: Author<'a>,
     #[serde(borrow)]
     url: Cow<'a, str>,
+    #[serde(default)]
+    timestamp: Option<DateTime<FixedOffset>>,
+    #[serde(borrow, default)]
+    added: Vec<Cow<'a, str>>,
+    #[serde(borrow, default)]
+    removed: Vec<Cow<'a, str>>,
+    #[serde(borrow, default)]
+    modified: Vec<Cow<'a, str>>,
 }
 
 impl Commit<'_> {
     async fn to_view<'a>(&'a self, url: &str, ctx: &'a mut PushEventContext<'_>) -> ViewCommit<'a> {
-        let message = self.short_message();
+        let (title, truncated) = commit_title(&self.message, ctx.max_commit_title_length);
         ViewCommit {
-            id: &self.id[..6],
-            message,
-            full_message: &self.message,
-            formatted_message: format_title(message, url),
-            author: self.author.to_view(ctx).await,
-            url: &self.url,
-        }
-    }
-
-    async fn to_simple_view<'a>(
-        &'a self,
-        ctx: &'a mut PushEventContext<'_>,
-    ) -> ViewSimpleCommit<'a> {
-        ViewSimpleCommit {
-            message: self.short_message(),
-            full_message: &self.message,
+            id: self.id.get(..ctx.sha_length).unwrap_or(&self.id),
+            formatted_message: format_title(title, url),
+            truncated,
+            timestamp: self
+                .timestamp
+                .map(|timestamp| format_time(timestamp, ctx.timezone)),
             author: self.author.to_view(ctx).await,
             url: &self.url,
         }
     }
+}
 
-    fn short_message(&self) -> &str {
-        self.message.split('\n').next().unwrap()
+/// Takes the first line of a commit message, cutting it off after
+/// `max_length` characters. Returns whether it was cut off.
+fn commit_title(message: &str, max_length: usize) -> (&str, bool) {
+    let title = message.lines().next().unwrap_or_default().trim_end();
+    match title.char_indices().nth(max_length) {
+        Some((index, _)) => (title[..index].trim_end(), true),
+        None => (title, false),
     }
 }
 
@@ -118,26 +513,96 @@ impl Commit<'_> {
 #[template(path = "commit.html")]
 struct ViewCommit<'a> {
     id: &'a str,
-    message: &'a str,
-    full_message: &'a str,
     formatted_message: String,
+    truncated: bool,
+    timestamp: Option<String>,
     author: ViewAuthor<'a>,
     url: &'a str,
 }
 
-#[derive(Template)]
-#[template(path = "simple_commit.html")]
-struct ViewSimpleCommit<'a> {
-    message: &'a str,
-    full_message: &'a str,
-    author: ViewAuthor<'a>,
-    url: &'a str,
+fn format_time(time: DateTime<FixedOffset>, timezone: Tz) -> String {
+    time.with_timezone(&timezone)
+        .format("%Y-%m-%d %H:%M %Z")
+        .to_string()
+}
+
+/// Escapes text for HTML, also making sure Showdown doesn't interpret
+/// it as chat formatting or a command.
+///
+/// Hexadecimal character references are used, as decimal ones would be
+/// mistaken for issue references by [`format_title`].
+fn escape_showdown(text: &str) -> String {
+    const SEQUENCES: &[(&str, &str)] = &[
+        ("**", "&#x2a;&#x2a;"),
+        ("__", "&#x5f;&#x5f;"),
+        ("~~", "&#x7e;&#x7e;"),
+        ("^^", "&#x5e;&#x5e;"),
+        ("\\\\", "&#x5c;&#x5c;"),
+        ("``", "&#x60;&#x60;"),
+        ("[[", "&#x5b;&#x5b;"),
+        ("\n/", "\n&#x2f;"),
+    ];
+    let mut escaped = h(text);
+    for (sequence, replacement) in SEQUENCES {
+        escaped = escaped.replace(sequence, replacement);
+    }
+    if escaped.starts_with('/') {
+        escaped.replace_range(..1, "&#x2f;");
+    }
+    escaped
+}
+
+/// Prepares text for a plain chat message, keeping it on a single line
+/// and separating repeated characters Showdown would interpret as chat
+/// formatting with a zero width space.
+pub fn escape_plain(text: &str) -> String {
+    const MARKUP: &[char] = &['*', '_', '~', '^', '\\', '`', '['];
+    let mut escaped = String::with_capacity(text.len());
+    let mut previous = None;
+    for word in text.split_whitespace() {
+        if previous.is_some() {
+            escaped.push(' ');
+            previous = Some(' ');
+        }
+        for c in word.chars() {
+            if previous == Some(c) && MARKUP.contains(&c) {
+                escaped.push('\u{200B}');
+            }
+            escaped.push(c);
+            previous = Some(c);
+        }
+    }
+    escaped
+}
+
+/// Fills in a message template configured by the user, escaping the
+/// values for the message format.
+fn fill_template(
+    template: &MessageTemplate,
+    format: Format,
+    value: impl Fn(&str) -> String,
+) -> String {
+    template.render(|placeholder| {
+        let value = value(placeholder);
+        match format {
+            Format::Html => escape_showdown(&value),
+            Format::PlainText => escape_plain(&value),
+        }
+    })
+}
+
+mod filters {
+    use std::fmt::Display;
+
+    pub fn showdown(text: impl Display) -> askama::Result<String> {
+        Ok(super::escape_showdown(&text.to_string()))
+    }
 }
 
 fn format_title(message: &str, url: &str) -> String {
     static ISSUE_PATTERN: Lazy<Regex> = Lazy::new(|| Regex::new(r#"#([0-9]+)"#).unwrap());
     ISSUE_PATTERN
-        .replace_all(&h(message), |c: &Captures| {
+        .replace_all(&escape_showdown(message), |c: &Captures| {
             format!("<a href='{}/issues/{}'>{}</a>", h(url), h(&c[1]), &c[0])
         })
         .to_string()
@@ -197,23 +662,54 @@ pub struct Repository<'a> {
     #[serde(borrow)]
     name: Cow<'a, str>,
     #[serde(borrow)]
-    html_url: Cow<'a, str>,
+    pub full_name: Cow<'a, str>,
     #[serde(borrow)]
+    html_url: Cow<'a, str>,
+    /// Empty when the payload doesn't include it.
+    #[serde(borrow, default)]
     pub default_branch: Cow<'a, str>,
+    /// Only included in some payloads.
+    forks_count: Option<u32>,
+    /// Only included in some payloads, like `forks_count`.
+    stargazers_count: Option<u32>,
+    /// Name shown in messages instead of the GitHub name, configured
+    /// with `display_name`.
+    #[serde(skip)]
+    pub display_name: Option<&'a str>,
+    /// Number of hex digits of commit ids shown in messages, configured
+    /// with `sha_length`.
+    #[serde(skip, default = "default_sha_length")]
+    pub sha_length: usize,
+}
+
+fn default_sha_length() -> usize {
+    DEFAULT_SHA_LENGTH
 }
 
 impl Repository<'_> {
     fn to_view(&self) -> ViewRepository<'_> {
-        let name = match &*self.name {
+        let name = self.display_name.unwrap_or(match &*self.name {
             "pokemon-showdown" => "server",
             "pokemon-showdown-client" => "client",
             name => name,
-        };
+        });
         ViewRepository {
             name,
             html_url: &self.html_url,
         }
     }
+
+    fn to_plain_text(&self) -> String {
+        format!("[{}]", escape_plain(self.to_view().name))
+    }
+
+    fn commit_url(&self, sha: &str) -> String {
+        format!("{}/commit/{}", self.html_url, sha)
+    }
+
+    fn short_sha<'b>(&self, sha: &'b str) -> &'b str {
+        sha.get(..self.sha_length).unwrap_or(sha)
+    }
 }
 
 #[derive(Template)]
@@ -233,24 +729,122 @@ pub struct PullRequestEvent<'a> {
     pub repository: Repository<'a>,
     #[serde(borrow)]
     sender: Sender<'a>,
+    /// Label added or removed by `labeled` and `unlabeled` actions.
+    #[serde(borrow, default)]
+    label: Option<Label<'a>>,
 }
 
 impl PullRequestEvent<'_> {
+    pub fn sender(&self) -> &str {
+        &self.sender.login
+    }
+
+    pub fn is_label_change(&self) -> bool {
+        matches!(&*self.action, "labeled" | "unlabeled")
+    }
+
+    /// Checks whether the label added or removed is one of the given
+    /// labels. Names are compared ignoring case, like GitHub does.
+    pub fn changes_label(&self, labels: &[String]) -> bool {
+        self.is_label_change()
+            && self.label.as_ref().is_some_and(|label| {
+                labels
+                    .iter()
+                    .any(|name| name.to_lowercase() == label.name.to_lowercase())
+            })
+    }
+
+    pub fn label_to_view<'a>(
+        &'a self,
+        username_aliases: &'a UsernameAliases,
+    ) -> ViewPullRequestLabelEvent<'a> {
+        let (color, text_color) = self
+            .label
+            .as_ref()
+            .map_or((DEFAULT_LABEL_COLOR, "000000"), Label::colors);
+        ViewPullRequestLabelEvent {
+            added: self.action == "labeled",
+            label: self.label_name(),
+            color,
+            text_color,
+            pull_request: &self.pull_request,
+            repository: self.repository.to_view(),
+            sender: self.sender.to_view(username_aliases),
+        }
+    }
+
+    pub fn label_to_plain_text(&self, username_aliases: &UsernameAliases) -> String {
+        let (change, preposition) = if self.action == "labeled" {
+            ("added", "to")
+        } else {
+            ("removed", "from")
+        };
+        format!(
+            "{} {} {} label {} {} PR#{} ({}): {}",
+            self.repository.to_plain_text(),
+            escape_plain(username_aliases.get(&self.sender.login)),
+            change,
+            escape_plain(self.label_name()),
+            preposition,
+            self.pull_request.number,
+            self.pull_request.html_url,
+            escape_plain(&self.pull_request.title),
+        )
+    }
+
+    fn label_name(&self) -> &str {
+        self.label.as_ref().map_or("", |label| &label.name)
+    }
+
     pub fn to_view<'a>(
         &'a self,
         username_aliases: &'a UsernameAliases,
     ) -> ViewPullRequestEvent<'a> {
         ViewPullRequestEvent {
-            action: match &*self.action {
-                "synchronize" => "updated",
-                "review_requested" => "requested a review for",
-                action => action,
-            },
+            action: self.describe_action(),
             pull_request: &self.pull_request,
             repository: self.repository.to_view(),
             sender: self.sender.to_view(username_aliases),
         }
     }
+
+    pub fn to_plain_text(&self, username_aliases: &UsernameAliases) -> String {
+        format!(
+            "{} {} {} PR#{} ({}): {}",
+            self.repository.to_plain_text(),
+            escape_plain(username_aliases.get(&self.sender.login)),
+            self.describe_action(),
+            self.pull_request.number,
+            self.pull_request.html_url,
+            escape_plain(&self.pull_request.title),
+        )
+    }
+
+    pub fn format_template(
+        &self,
+        template: &MessageTemplate,
+        username_aliases: &UsernameAliases,
+        format: Format,
+    ) -> String {
+        fill_template(template, format, |placeholder| match placeholder {
+            "user" => username_aliases.get(&self.sender.login).into(),
+            "repo" => self.repository.to_view().name.into(),
+            "action" => self.describe_action().into(),
+            "number" => self.pull_request.number.to_string(),
+            "title" => self.pull_request.title.to_string(),
+            "url" => self.pull_request.html_url.to_string(),
+            "label" => self.label_name().into(),
+            _ => String::new(),
+        })
+    }
+
+    fn describe_action(&self) -> &str {
+        match &*self.action {
+            "synchronize" => "updated",
+            "review_requested" => "requested a review for",
+            action => action,
+        }
+    }
 }
 
 #[derive(Template)]
@@ -262,6 +856,45 @@ pub struct ViewPullRequestEvent<'a> {
     sender: ViewSender<'a>,
 }
 
+#[derive(Template)]
+#[template(path = "pull_request_label_event.html")]
+pub struct ViewPullRequestLabelEvent<'a> {
+    added: bool,
+    label: &'a str,
+    color: &'a str,
+    text_color: &'static str,
+    pull_request: &'a PullRequest<'a>,
+    repository: ViewRepository<'a>,
+    sender: ViewSender<'a>,
+}
+
+/// Background of labels whose color isn't a hex code, GitHub's default
+/// label color.
+const DEFAULT_LABEL_COLOR: &str = "ededed";
+
+#[derive(Debug, Deserialize)]
+struct Label<'a> {
+    #[serde(borrow)]
+    name: Cow<'a, str>,
+    /// Hex code without the `#`, like `d73a4a`.
+    #[serde(borrow)]
+    color: Cow<'a, str>,
+}
+
+impl Label<'_> {
+    /// Returns the background color of the label and a text color
+    /// readable on it, the way GitHub shows labels.
+    fn colors(&self) -> (&str, &'static str) {
+        let color = &*self.color;
+        if color.len() != 6 || !color.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return (DEFAULT_LABEL_COLOR, "000000");
+        }
+        let channel = |i: usize| u32::from_str_radix(&color[i..i + 2], 16).unwrap();
+        let brightness = (299 * channel(0) + 587 * channel(2) + 114 * channel(4)) / 1000;
+        (color, if brightness > 150 { "000000" } else { "ffffff" })
+    }
+}
+
 #[derive(Debug, Deserialize, Template)]
 #[template(path = "pull_request.html")]
 pub struct PullRequest<'a> {
@@ -270,146 +903,3863 @@ pub struct PullRequest<'a> {
     html_url: Cow<'a, str>,
     #[serde(borrow)]
     title: Cow<'a, str>,
+    #[serde(default)]
+    pub merged: bool,
+    /// Commit created by merging the pull request, once it's merged.
+    #[serde(borrow, default)]
+    pub merge_commit_sha: Option<Cow<'a, str>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PullRequestReviewEvent<'a> {
+    #[serde(borrow)]
+    pub action: Cow<'a, str>,
+    #[serde(borrow)]
+    review: Review<'a>,
+    #[serde(borrow)]
+    pull_request: PullRequest<'a>,
+    #[serde(borrow)]
+    pub repository: Repository<'a>,
+    #[serde(borrow)]
+    sender: Sender<'a>,
+}
+
+impl PullRequestReviewEvent<'_> {
+    pub fn sender(&self) -> &str {
+        &self.sender.login
+    }
+
+    /// Checks whether the review should be announced. Reviews only
+    /// leaving comments are announced when `commented_reviews` is set,
+    /// as long as they say something besides their inline comments.
+    pub fn is_announced(&self, commented_reviews: bool) -> bool {
+        self.action == "submitted"
+            && match &*self.review.state {
+                "approved" | "changes_requested" => true,
+                "commented" => {
+                    commented_reviews
+                        && self
+                            .review
+                            .body
+                            .as_deref()
+                            .is_some_and(|body| !body.trim().is_empty())
+                }
+                _ => false,
+            }
+    }
+
+    pub fn to_view<'a>(
+        &'a self,
+        username_aliases: &'a UsernameAliases,
+    ) -> ViewPullRequestReviewEvent<'a> {
+        ViewPullRequestReviewEvent {
+            action: self.describe_state(),
+            number: self.pull_request.number,
+            title: &self.pull_request.title,
+            html_url: &self.review.html_url,
+            repository: self.repository.to_view(),
+            sender: self.sender.to_view(username_aliases),
+        }
+    }
+
+    /// Announces the review as a plain chat message, linking to the
+    /// review rather than the pull request.
+    pub fn to_plain_text(&self, username_aliases: &UsernameAliases) -> String {
+        format!(
+            "{} {} {} PR#{} ({}): {}",
+            self.repository.to_plain_text(),
+            escape_plain(username_aliases.get(&self.sender.login)),
+            self.describe_state(),
+            self.pull_request.number,
+            self.review.html_url,
+            escape_plain(&self.pull_request.title),
+        )
+    }
+
+    pub fn format_template(
+        &self,
+        template: &MessageTemplate,
+        username_aliases: &UsernameAliases,
+        format: Format,
+    ) -> String {
+        fill_template(template, format, |placeholder| match placeholder {
+            "user" => username_aliases.get(&self.sender.login).into(),
+            "repo" => self.repository.to_view().name.into(),
+            "action" => self.describe_state().into(),
+            "number" => self.pull_request.number.to_string(),
+            "title" => self.pull_request.title.to_string(),
+            "url" => self.review.html_url.to_string(),
+            _ => String::new(),
+        })
+    }
+
+    fn describe_state(&self) -> &str {
+        match &*self.review.state {
+            "changes_requested" => "requested changes on",
+            "commented" => "reviewed",
+            state => state,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Review<'a> {
+    /// `approved`, `changes_requested` or `commented`.
+    #[serde(borrow)]
+    state: Cow<'a, str>,
+    #[serde(borrow)]
+    html_url: Cow<'a, str>,
+    /// Summary written when submitting the review, without the inline
+    /// comments.
+    #[serde(borrow)]
+    body: Option<Cow<'a, str>>,
+}
+
+#[derive(Template)]
+#[template(path = "pull_request_review_event.html")]
+pub struct ViewPullRequestReviewEvent<'a> {
+    action: &'a str,
+    number: u32,
+    title: &'a str,
+    html_url: &'a str,
+    repository: ViewRepository<'a>,
+    sender: ViewSender<'a>,
 }
 
 #[derive(Debug, Deserialize)]
 struct Sender<'a> {
     #[serde(borrow)]
     login: Cow<'a, str>,
+    #[serde(borrow)]
+    html_url: Cow<'a, str>,
 }
 
 impl Sender<'_> {
     fn to_view<'a>(&'a self, username_aliases: &'a UsernameAliases) -> ViewSender<'a> {
         ViewSender {
-            login: &self.login,
+            html_url: &self.html_url,
             renamed_login: username_aliases.get(&self.login),
         }
     }
 }
 
 struct ViewSender<'a> {
-    login: &'a str,
+    html_url: &'a str,
     renamed_login: &'a str,
 }
 
-#[cfg(test)]
+#[derive(Debug, Deserialize)]
+pub struct IssueEvent<'a> {
+    #[serde(borrow)]
+    pub action: Cow<'a, str>,
+    #[serde(borrow)]
+    issue: Issue<'a>,
+    #[serde(borrow)]
+    pub repository: Repository<'a>,
+    #[serde(borrow)]
+    sender: Sender<'a>,
+}
+
+impl IssueEvent<'_> {
+    pub fn sender(&self) -> &str {
+        &self.sender.login
+    }
+
+    /// Checks whether the issue should be announced. Only opening,
+    /// closing and reopening issues is, not changes like labels.
+    pub fn is_announced(&self) -> bool {
+        matches!(&*self.action, "opened" | "closed" | "reopened")
+    }
+
+    pub fn to_view<'a>(&'a self, username_aliases: &'a UsernameAliases) -> ViewIssueEvent<'a> {
+        ViewIssueEvent {
+            action: &self.action,
+            close_reason: self.close_reason(),
+            issue: &self.issue,
+            repository: self.repository.to_view(),
+            sender: self.sender.to_view(username_aliases),
+        }
+    }
+
+    pub fn to_plain_text(&self, username_aliases: &UsernameAliases) -> String {
+        format!(
+            "{} {} {} issue #{}{} ({}): {}",
+            self.repository.to_plain_text(),
+            escape_plain(username_aliases.get(&self.sender.login)),
+            escape_plain(&self.action),
+            self.issue.number,
+            self.close_reason(),
+            self.issue.html_url,
+            escape_plain(&self.issue.title),
+        )
+    }
+
+    pub fn format_template(
+        &self,
+        template: &MessageTemplate,
+        username_aliases: &UsernameAliases,
+        format: Format,
+    ) -> String {
+        fill_template(template, format, |placeholder| match placeholder {
+            "user" => username_aliases.get(&self.sender.login).into(),
+            "repo" => self.repository.to_view().name.into(),
+            "action" => self.action.to_string(),
+            "number" => self.issue.number.to_string(),
+            "title" => self.issue.title.to_string(),
+            "url" => self.issue.html_url.to_string(),
+            _ => String::new(),
+        })
+    }
+
+    /// Tells apart issues closed as completed from ones closed as not
+    /// planned, when GitHub says which it was.
+    fn close_reason(&self) -> &'static str {
+        match (&*self.action, self.issue.state_reason.as_deref()) {
+            ("closed", Some("completed")) => " as completed",
+            ("closed", Some("not_planned")) => " as not planned",
+            _ => "",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Issue<'a> {
+    number: u32,
+    #[serde(borrow)]
+    html_url: Cow<'a, str>,
+    #[serde(borrow)]
+    title: Cow<'a, str>,
+    /// Why the issue was closed, `completed` or `not_planned`. Older
+    /// payloads don't include it.
+    #[serde(borrow, default)]
+    state_reason: Option<Cow<'a, str>>,
+}
+
+#[derive(Template)]
+#[template(path = "issue_event.html")]
+pub struct ViewIssueEvent<'a> {
+    action: &'a str,
+    close_reason: &'static str,
+    issue: &'a Issue<'a>,
+    repository: ViewRepository<'a>,
+    sender: ViewSender<'a>,
+}
+
+/// Longest comment excerpt, in characters.
+const COMMENT_EXCERPT_LENGTH: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct IssueCommentEvent<'a> {
+    #[serde(borrow)]
+    pub action: Cow<'a, str>,
+    #[serde(borrow)]
+    issue: CommentedIssue<'a>,
+    #[serde(borrow)]
+    comment: Comment<'a>,
+    #[serde(borrow)]
+    pub repository: Repository<'a>,
+    #[serde(borrow)]
+    sender: Sender<'a>,
+}
+
+impl IssueCommentEvent<'_> {
+    pub fn sender(&self) -> &str {
+        &self.sender.login
+    }
+
+    /// Checks whether the comment should be announced. Only new comments
+    /// are, and comments on closed issues only with `closed_comments`.
+    pub fn is_announced(&self, closed_comments: bool) -> bool {
+        self.action == "created" && (closed_comments || self.issue.state != "closed")
+    }
+
+    pub fn to_view<'a>(
+        &'a self,
+        username_aliases: &'a UsernameAliases,
+    ) -> ViewIssueCommentEvent<'a> {
+        let (excerpt, truncated) = self.excerpt();
+        ViewIssueCommentEvent {
+            reference: self.reference(),
+            title: &self.issue.title,
+            html_url: &self.comment.html_url,
+            excerpt,
+            truncated,
+            repository: self.repository.to_view(),
+            sender: self.sender.to_view(username_aliases),
+        }
+    }
+
+    /// Announces the comment on a single line, leaving out its text.
+    /// This is used for `simple_rooms`.
+    pub fn format_simple<'a>(
+        &'a self,
+        username_aliases: &'a UsernameAliases,
+    ) -> ViewIssueCommentEvent<'a> {
+        ViewIssueCommentEvent {
+            excerpt: String::new(),
+            truncated: false,
+            ..self.to_view(username_aliases)
+        }
+    }
+
+    pub fn to_plain_text(&self, username_aliases: &UsernameAliases) -> String {
+        let (excerpt, truncated) = self.excerpt();
+        format!(
+            "{} {} commented on {} ({}): {}{} {}",
+            self.repository.to_plain_text(),
+            escape_plain(username_aliases.get(&self.sender.login)),
+            self.reference(),
+            escape_plain(&self.issue.title),
+            escape_plain(&excerpt),
+            if truncated { "\u{2026}" } else { "" },
+            self.comment.html_url,
+        )
+    }
+
+    pub fn format_template(
+        &self,
+        template: &MessageTemplate,
+        username_aliases: &UsernameAliases,
+        format: Format,
+    ) -> String {
+        fill_template(template, format, |placeholder| match placeholder {
+            "user" => username_aliases.get(&self.sender.login).into(),
+            "repo" => self.repository.to_view().name.into(),
+            "number" => self.issue.number.to_string(),
+            "title" => self.issue.title.to_string(),
+            "url" => self.comment.html_url.to_string(),
+            "excerpt" => self.excerpt().0,
+            _ => String::new(),
+        })
+    }
+
+    /// Names what was commented on, as the event covers both issues and
+    /// pull requests.
+    fn reference(&self) -> String {
+        if self.issue.pull_request.is_some() {
+            format!("PR#{}", self.issue.number)
+        } else {
+            format!("issue #{}", self.issue.number)
+        }
+    }
+
+    fn excerpt(&self) -> (String, bool) {
+        comment_excerpt(&self.comment.body)
+    }
+}
+
+/// Returns the start of a comment as plain text, along with whether it
+/// was cut off.
+fn comment_excerpt(body: &str) -> (String, bool) {
+    let mut excerpt = strip_markdown(body);
+    let cut = excerpt
+        .char_indices()
+        .nth(COMMENT_EXCERPT_LENGTH)
+        .map(|(index, _)| index);
+    if let Some(index) = cut {
+        excerpt.truncate(index);
+        excerpt.truncate(excerpt.trim_end().len());
+    }
+    (excerpt, cut.is_some())
+}
+
+/// Comment left on a commit, either on the whole commit or on one of its
+/// lines.
+#[derive(Debug, Deserialize)]
+pub struct CommitCommentEvent<'a> {
+    #[serde(borrow)]
+    pub action: Cow<'a, str>,
+    #[serde(borrow)]
+    comment: CommitComment<'a>,
+    #[serde(borrow)]
+    pub repository: Repository<'a>,
+    #[serde(borrow)]
+    sender: Sender<'a>,
+}
+
+impl CommitCommentEvent<'_> {
+    pub fn sender(&self) -> &str {
+        &self.sender.login
+    }
+
+    pub fn is_announced(&self) -> bool {
+        self.action == "created"
+    }
+
+    pub fn to_view<'a>(
+        &'a self,
+        username_aliases: &'a UsernameAliases,
+    ) -> ViewCommitCommentEvent<'a> {
+        let (excerpt, truncated) = self.excerpt();
+        ViewCommitCommentEvent {
+            sha: self.short_sha(),
+            html_url: &self.comment.html_url,
+            excerpt,
+            truncated,
+            repository: self.repository.to_view(),
+            sender: self.sender.to_view(username_aliases),
+        }
+    }
+
+    /// Announces the comment without its text, on a single line. This is
+    /// used for `simple_rooms`.
+    pub fn format_simple<'a>(
+        &'a self,
+        username_aliases: &'a UsernameAliases,
+    ) -> ViewCommitCommentEvent<'a> {
+        ViewCommitCommentEvent {
+            excerpt: String::new(),
+            truncated: false,
+            ..self.to_view(username_aliases)
+        }
+    }
+
+    pub fn to_plain_text(&self, username_aliases: &UsernameAliases) -> String {
+        let (excerpt, truncated) = self.excerpt();
+        format!(
+            "{} {} commented on {}: {}{} {}",
+            self.repository.to_plain_text(),
+            escape_plain(username_aliases.get(&self.sender.login)),
+            self.short_sha(),
+            escape_plain(&excerpt),
+            if truncated { "\u{2026}" } else { "" },
+            self.comment.html_url,
+        )
+    }
+
+    pub fn format_template(
+        &self,
+        template: &MessageTemplate,
+        username_aliases: &UsernameAliases,
+        format: Format,
+    ) -> String {
+        fill_template(template, format, |placeholder| match placeholder {
+            "user" => username_aliases.get(&self.sender.login).into(),
+            "repo" => self.repository.to_view().name.into(),
+            "sha" => self.short_sha().into(),
+            "url" => self.comment.html_url.to_string(),
+            "excerpt" => self.excerpt().0,
+            _ => String::new(),
+        })
+    }
+
+    fn short_sha(&self) -> &str {
+        self.repository.short_sha(&self.comment.commit_id)
+    }
+
+    /// Returns the start of the first line of the comment, along with
+    /// whether anything was left out.
+    fn excerpt(&self) -> (String, bool) {
+        let mut lines = self
+            .comment
+            .body
+            .lines()
+            .filter(|line| !line.trim().is_empty());
+        let (excerpt, truncated) = comment_excerpt(lines.next().unwrap_or_default());
+        (excerpt, truncated || lines.next().is_some())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CommitComment<'a> {
+    #[serde(borrow)]
+    html_url: Cow<'a, str>,
+    #[serde(borrow)]
+    commit_id: Cow<'a, str>,
+    #[serde(borrow)]
+    body: Cow<'a, str>,
+}
+
+#[derive(Template)]
+#[template(path = "commit_comment_event.html")]
+pub struct ViewCommitCommentEvent<'a> {
+    sha: &'a str,
+    html_url: &'a str,
+    excerpt: String,
+    truncated: bool,
+    repository: ViewRepository<'a>,
+    sender: ViewSender<'a>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeploymentStatusEvent<'a> {
+    #[serde(borrow)]
+    deployment_status: DeploymentStatus<'a>,
+    #[serde(borrow)]
+    deployment: Deployment<'a>,
+    #[serde(borrow)]
+    pub repository: Repository<'a>,
+}
+
+impl DeploymentStatusEvent<'_> {
+    /// Returns who started the deployment. Statuses are usually reported
+    /// by the CI running it instead.
+    pub fn deployer(&self) -> &str {
+        &self.deployment.creator.login
+    }
+
+    /// Checks whether the deployment finished. Pending and in progress
+    /// deployments aren't announced.
+    pub fn is_finished(&self) -> bool {
+        self.describe_state().is_some()
+    }
+
+    pub fn environment(&self) -> &str {
+        self.deployment_status
+            .environment
+            .as_deref()
+            .unwrap_or(&self.deployment.environment)
+    }
+
+    pub fn to_view<'a>(
+        &'a self,
+        username_aliases: &'a UsernameAliases,
+    ) -> ViewDeploymentStatusEvent<'a> {
+        ViewDeploymentStatusEvent {
+            environment: self.environment(),
+            result: self.describe_state().unwrap_or_default(),
+            git_ref: &self.deployment.git_ref,
+            sha: self.short_sha(),
+            target_url: self.target_url(),
+            repository: self.repository.to_view(),
+            deployer: self.deployment.creator.to_view(username_aliases),
+        }
+    }
+
+    pub fn to_plain_text(&self, username_aliases: &UsernameAliases) -> String {
+        format!(
+            "{} Deployment to {}{} {}: {} {} by {}",
+            self.repository.to_plain_text(),
+            escape_plain(self.environment()),
+            self.target_url()
+                .map_or_else(String::new, |url| format!(" ({})", url)),
+            self.describe_state().unwrap_or_default(),
+            escape_plain(&self.deployment.git_ref),
+            self.short_sha(),
+            escape_plain(username_aliases.get(self.deployer())),
+        )
+    }
+
+    pub fn format_template(
+        &self,
+        template: &MessageTemplate,
+        username_aliases: &UsernameAliases,
+        format: Format,
+    ) -> String {
+        fill_template(template, format, |placeholder| match placeholder {
+            "user" => username_aliases.get(self.deployer()).into(),
+            "repo" => self.repository.to_view().name.into(),
+            "environment" => self.environment().into(),
+            "state" => self.deployment_status.state.to_string(),
+            "ref" => self.deployment.git_ref.to_string(),
+            "sha" => self.short_sha().into(),
+            "url" => self.target_url().unwrap_or_default().into(),
+            _ => String::new(),
+        })
+    }
+
+    fn describe_state(&self) -> Option<&'static str> {
+        match &*self.deployment_status.state {
+            "success" => Some("succeeded"),
+            "failure" => Some("failed"),
+            "error" => Some("errored"),
+            _ => None,
+        }
+    }
+
+    fn short_sha(&self) -> &str {
+        self.repository.short_sha(&self.deployment.sha)
+    }
+
+    fn target_url(&self) -> Option<&str> {
+        self.deployment_status
+            .target_url
+            .as_deref()
+            .filter(|url| !url.is_empty())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DeploymentStatus<'a> {
+    /// One of `error`, `failure`, `inactive`, `in_progress`, `queued`,
+    /// `pending` or `success`.
+    #[serde(borrow)]
+    state: Cow<'a, str>,
+    /// Missing from older payloads, which only have the environment of
+    /// the deployment.
+    #[serde(borrow, default)]
+    environment: Option<Cow<'a, str>>,
+    /// Often empty rather than missing.
+    #[serde(borrow, default)]
+    target_url: Option<Cow<'a, str>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Deployment<'a> {
+    #[serde(borrow)]
+    sha: Cow<'a, str>,
+    /// Branch, tag or commit that was deployed.
+    #[serde(borrow, rename = "ref")]
+    git_ref: Cow<'a, str>,
+    #[serde(borrow)]
+    environment: Cow<'a, str>,
+    #[serde(borrow)]
+    creator: Sender<'a>,
+}
+
+#[derive(Template)]
+#[template(path = "deployment_status_event.html")]
+pub struct ViewDeploymentStatusEvent<'a> {
+    environment: &'a str,
+    result: &'a str,
+    git_ref: &'a str,
+    sha: &'a str,
+    target_url: Option<&'a str>,
+    repository: ViewRepository<'a>,
+    deployer: ViewSender<'a>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RepositoryEvent<'a> {
+    #[serde(borrow)]
+    pub action: Cow<'a, str>,
+    #[serde(borrow, default)]
+    changes: Option<RepositoryChanges<'a>>,
+    #[serde(borrow)]
+    pub repository: Repository<'a>,
+    #[serde(borrow)]
+    sender: Sender<'a>,
+}
+
+impl RepositoryEvent<'_> {
+    pub fn sender(&self) -> &str {
+        &self.sender.login
+    }
+
+    /// Only changes affecting where the repository is found are, not
+    /// edits of its settings.
+    pub fn is_announced(&self) -> bool {
+        matches!(
+            &*self.action,
+            "renamed" | "transferred" | "archived" | "unarchived"
+        )
+    }
+
+    /// Returns the full name the repository had before it was renamed or
+    /// transferred.
+    pub fn previous_name(&self) -> Option<String> {
+        let changes = self.changes.as_ref()?;
+        let (owner, name) = self.repository.full_name.split_once('/')?;
+        match &*self.action {
+            "renamed" => {
+                let previous = &changes.repository.as_ref()?.name.as_ref()?.from;
+                Some(format!("{}/{}", owner, previous))
+            }
+            "transferred" => {
+                let from = &changes.owner.as_ref()?.from;
+                let previous = from.user.as_ref().or(from.organization.as_ref())?;
+                Some(format!("{}/{}", previous.login, name))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn to_view<'a>(&'a self, username_aliases: &'a UsernameAliases) -> ViewRepositoryEvent<'a> {
+        ViewRepositoryEvent {
+            action: &self.action,
+            previous_name: self.previous_name(),
+            full_name: &self.repository.full_name,
+            html_url: &self.repository.html_url,
+            repository: self.repository.to_view(),
+            sender: self.sender.to_view(username_aliases),
+        }
+    }
+
+    pub fn to_plain_text(&self, username_aliases: &UsernameAliases) -> String {
+        let moved = match self.previous_name() {
+            Some(previous_name) => format!(
+                " from {} to {}",
+                escape_plain(&previous_name),
+                escape_plain(&self.repository.full_name),
+            ),
+            None => String::new(),
+        };
+        format!(
+            "{} {} {} the repository{} ({})",
+            self.repository.to_plain_text(),
+            escape_plain(username_aliases.get(&self.sender.login)),
+            self.action,
+            moved,
+            self.repository.html_url,
+        )
+    }
+
+    pub fn format_template(
+        &self,
+        template: &MessageTemplate,
+        username_aliases: &UsernameAliases,
+        format: Format,
+    ) -> String {
+        fill_template(template, format, |placeholder| match placeholder {
+            "user" => username_aliases.get(&self.sender.login).into(),
+            "repo" => self.repository.to_view().name.into(),
+            "action" => self.action.to_string(),
+            "from" => self.previous_name().unwrap_or_default(),
+            "name" => self.repository.full_name.to_string(),
+            "url" => self.repository.html_url.to_string(),
+            _ => String::new(),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RepositoryChanges<'a> {
+    /// Set when the repository is renamed.
+    #[serde(borrow, default)]
+    repository: Option<RenamedRepository<'a>>,
+    /// Set when the repository is transferred.
+    #[serde(borrow, default)]
+    owner: Option<PreviousOwner<'a>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RenamedRepository<'a> {
+    #[serde(borrow, default)]
+    name: Option<Change<'a>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Change<'a> {
+    #[serde(borrow)]
+    from: Cow<'a, str>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PreviousOwner<'a> {
+    #[serde(borrow)]
+    from: PreviousOwnerAccount<'a>,
+}
+
+/// Either a user or an organization.
+#[derive(Debug, Deserialize)]
+struct PreviousOwnerAccount<'a> {
+    #[serde(borrow, default)]
+    user: Option<Owner<'a>>,
+    #[serde(borrow, default)]
+    organization: Option<Owner<'a>>,
+}
+
+#[derive(Template)]
+#[template(path = "repository_event.html")]
+pub struct ViewRepositoryEvent<'a> {
+    action: &'a str,
+    previous_name: Option<String>,
+    full_name: &'a str,
+    html_url: &'a str,
+    repository: ViewRepository<'a>,
+    sender: ViewSender<'a>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CommentedIssue<'a> {
+    number: u32,
+    #[serde(borrow)]
+    title: Cow<'a, str>,
+    /// `open` or `closed`.
+    #[serde(borrow)]
+    state: Cow<'a, str>,
+    /// Only present for pull requests.
+    #[serde(default)]
+    pull_request: Option<IgnoredAny>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Comment<'a> {
+    /// Links to the comment itself, not just the issue.
+    #[serde(borrow)]
+    html_url: Cow<'a, str>,
+    #[serde(borrow)]
+    body: Cow<'a, str>,
+}
+
+#[derive(Template)]
+#[template(path = "issue_comment_event.html")]
+pub struct ViewIssueCommentEvent<'a> {
+    reference: String,
+    title: &'a str,
+    html_url: &'a str,
+    excerpt: String,
+    truncated: bool,
+    repository: ViewRepository<'a>,
+    sender: ViewSender<'a>,
+}
+
+/// Turns a Markdown comment into a single line of plain text, keeping
+/// the text of links while leaving out formatting, HTML tags, code
+/// blocks and quoted replies.
+fn strip_markdown(markdown: &str) -> String {
+    static LINK: Lazy<Regex> = Lazy::new(|| Regex::new(r"!?\[([^\]]*)\]\([^)]*\)").unwrap());
+    static MARKUP: Lazy<Regex> = Lazy::new(|| Regex::new(r"<[^>]*>|\*\*?|__|~~|`").unwrap());
+    let mut lines = Vec::new();
+    let mut in_code_block = false;
+    for line in markdown.lines() {
+        let line = line.trim_start();
+        if line.starts_with("```") {
+            in_code_block = !in_code_block;
+        } else if !in_code_block && !line.starts_with('>') {
+            lines.push(line.trim_start_matches('#'));
+        }
+    }
+    let text = LINK.replace_all(&lines.join(" "), "$1").into_owned();
+    let text = MARKUP.replace_all(&text, "");
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Inline comment left on a pull request, usually as part of a review.
+/// Comments from the same review are merged, so that a review with many
+/// comments is announced once.
+#[derive(Debug, Deserialize)]
+pub struct PullRequestReviewCommentEvent<'a> {
+    #[serde(borrow)]
+    pub action: Cow<'a, str>,
+    #[serde(borrow)]
+    comment: ReviewComment<'a>,
+    #[serde(borrow)]
+    pull_request: PullRequest<'a>,
+    #[serde(borrow)]
+    pub repository: Repository<'a>,
+    #[serde(borrow)]
+    sender: Sender<'a>,
+    /// Number of later comments merged into this one.
+    #[serde(skip)]
+    merged: usize,
+}
+
+impl PullRequestReviewCommentEvent<'_> {
+    pub fn sender(&self) -> &str {
+        &self.sender.login
+    }
+
+    /// Checks whether the comment should be announced. Only new comments
+    /// are.
+    pub fn is_announced(&self) -> bool {
+        self.action == "created"
+    }
+
+    /// Identifies comments which are merged together, which are the ones
+    /// of the same review. Comments without a review are never merged.
+    pub fn batch_key(&self) -> String {
+        match self.comment.pull_request_review_id {
+            Some(id) => format!("{} review {}", self.repository.full_name, id),
+            None => self.comment.html_url.to_string(),
+        }
+    }
+
+    /// Counts a later comment as part of this one.
+    pub fn merge(&mut self, later: PullRequestReviewCommentEvent<'_>) {
+        self.merged += later.merged + 1;
+    }
+
+    /// Announces a single comment along with its start, or else how many
+    /// comments were left.
+    pub fn to_view<'a>(
+        &'a self,
+        username_aliases: &'a UsernameAliases,
+    ) -> ViewReviewCommentEvent<'a> {
+        let (excerpt, truncated) = comment_excerpt(&self.comment.body);
+        ViewReviewCommentEvent {
+            count: self.count(),
+            number: self.pull_request.number,
+            title: &self.pull_request.title,
+            html_url: self.html_url(),
+            excerpt,
+            truncated,
+            repository: self.repository.to_view(),
+            sender: self.sender.to_view(username_aliases),
+        }
+    }
+
+    /// Announces the comments on a single line, leaving out the text of
+    /// a single comment. This is used for `simple_rooms`.
+    pub fn format_simple<'a>(
+        &'a self,
+        username_aliases: &'a UsernameAliases,
+    ) -> ViewReviewCommentEvent<'a> {
+        ViewReviewCommentEvent {
+            excerpt: String::new(),
+            truncated: false,
+            ..self.to_view(username_aliases)
+        }
+    }
+
+    pub fn to_plain_text(&self, username_aliases: &UsernameAliases) -> String {
+        let user = escape_plain(username_aliases.get(&self.sender.login));
+        if self.merged == 0 {
+            let (excerpt, truncated) = comment_excerpt(&self.comment.body);
+            format!(
+                "{} {} commented on PR#{} ({}): {}{} {}",
+                self.repository.to_plain_text(),
+                user,
+                self.pull_request.number,
+                escape_plain(&self.pull_request.title),
+                escape_plain(&excerpt),
+                if truncated { "\u{2026}" } else { "" },
+                self.comment.html_url,
+            )
+        } else {
+            format!(
+                "{} {} left {} review comments on PR#{} ({}): {}",
+                self.repository.to_plain_text(),
+                user,
+                self.count(),
+                self.pull_request.number,
+                escape_plain(&self.pull_request.title),
+                self.html_url(),
+            )
+        }
+    }
+
+    /// Fills in a template, where `{excerpt}` is empty when several
+    /// comments were merged.
+    pub fn format_template(
+        &self,
+        template: &MessageTemplate,
+        username_aliases: &UsernameAliases,
+        format: Format,
+    ) -> String {
+        fill_template(template, format, |placeholder| match placeholder {
+            "user" => username_aliases.get(&self.sender.login).into(),
+            "repo" => self.repository.to_view().name.into(),
+            "number" => self.pull_request.number.to_string(),
+            "title" => self.pull_request.title.to_string(),
+            "url" => self.html_url(),
+            "count" => self.count().to_string(),
+            "excerpt" if self.merged == 0 => comment_excerpt(&self.comment.body).0,
+            _ => String::new(),
+        })
+    }
+
+    fn count(&self) -> usize {
+        self.merged + 1
+    }
+
+    /// Links to the comment, or to its review when several comments were
+    /// merged.
+    fn html_url(&self) -> String {
+        match self.comment.pull_request_review_id {
+            Some(id) if self.merged > 0 => {
+                format!("{}#pullrequestreview-{}", self.pull_request.html_url, id)
+            }
+            _ => self.comment.html_url.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ReviewComment<'a> {
+    #[serde(borrow)]
+    html_url: Cow<'a, str>,
+    #[serde(borrow)]
+    body: Cow<'a, str>,
+    pull_request_review_id: Option<u64>,
+}
+
+#[derive(Template)]
+#[template(path = "review_comment_event.html")]
+pub struct ViewReviewCommentEvent<'a> {
+    count: usize,
+    number: u32,
+    title: &'a str,
+    html_url: String,
+    excerpt: String,
+    truncated: bool,
+    repository: ViewRepository<'a>,
+    sender: ViewSender<'a>,
+}
+
+/// Longest release description excerpt, in characters.
+const RELEASE_EXCERPT_LENGTH: usize = 200;
+
+#[derive(Debug, Deserialize)]
+pub struct ReleaseEvent<'a> {
+    #[serde(borrow)]
+    pub action: Cow<'a, str>,
+    #[serde(borrow)]
+    release: Release<'a>,
+    #[serde(borrow)]
+    pub repository: Repository<'a>,
+    #[serde(borrow)]
+    sender: Sender<'a>,
+}
+
+impl ReleaseEvent<'_> {
+    pub fn sender(&self) -> &str {
+        &self.sender.login
+    }
+
+    /// Checks whether the release should be announced. Drafts are never
+    /// announced, while prereleases are only announced when `prereleases`
+    /// is set.
+    pub fn is_announced(&self, prereleases: bool) -> bool {
+        self.action == "published"
+            && !self.release.draft
+            && (prereleases || !self.release.prerelease)
+    }
+
+    /// Account which wrote the release, which is usually the one that
+    /// published it.
+    fn author(&self) -> &Sender<'_> {
+        self.release.author.as_ref().unwrap_or(&self.sender)
+    }
+
+    pub fn to_view<'a>(&'a self, username_aliases: &'a UsernameAliases) -> ViewReleaseEvent<'a> {
+        let body = self.release.body.as_deref().unwrap_or_default();
+        let mut excerpt = body.split_whitespace().collect::<Vec<_>>().join(" ");
+        let cut = excerpt
+            .char_indices()
+            .nth(RELEASE_EXCERPT_LENGTH)
+            .map(|(index, _)| index);
+        if let Some(index) = cut {
+            excerpt.truncate(index);
+        }
+        ViewReleaseEvent {
+            repository: self.repository.to_view(),
+            author: self.author().to_view(username_aliases),
+            tag_name: &self.release.tag_name,
+            name: self.name(),
+            html_url: &self.release.html_url,
+            excerpt,
+            truncated: cut.is_some(),
+        }
+    }
+
+    /// Announces the release on a single line, leaving out the
+    /// description. This is used for `simple_rooms`.
+    pub fn format_simple<'a>(
+        &'a self,
+        username_aliases: &'a UsernameAliases,
+    ) -> ViewReleaseEvent<'a> {
+        ViewReleaseEvent {
+            excerpt: String::new(),
+            truncated: false,
+            ..self.to_view(username_aliases)
+        }
+    }
+
+    /// Announces the release as a plain chat message, leaving out the
+    /// description.
+    pub fn to_plain_text(&self, username_aliases: &UsernameAliases) -> String {
+        let mut text = format!(
+            "{} New release by {}: {}",
+            self.repository.to_plain_text(),
+            escape_plain(username_aliases.get(&self.author().login)),
+            escape_plain(&self.release.tag_name),
+        );
+        if let Some(name) = self.name() {
+            text += " \u{2014} ";
+            text += &escape_plain(name);
+        }
+        format!("{} ({})", text, self.release.html_url)
+    }
+
+    /// Fills in a template, where `{name}` falls back to the tag when the
+    /// release has no name of its own.
+    pub fn format_template(
+        &self,
+        template: &MessageTemplate,
+        username_aliases: &UsernameAliases,
+        format: Format,
+    ) -> String {
+        fill_template(template, format, |placeholder| match placeholder {
+            "user" => username_aliases.get(&self.author().login).into(),
+            "repo" => self.repository.to_view().name.into(),
+            "tag" => self.release.tag_name.to_string(),
+            "name" => self.name().unwrap_or(&self.release.tag_name).into(),
+            "url" => self.release.html_url.to_string(),
+            _ => String::new(),
+        })
+    }
+
+    fn name(&self) -> Option<&str> {
+        self.release
+            .name
+            .as_deref()
+            .filter(|name| !name.is_empty() && *name != self.release.tag_name)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Release<'a> {
+    #[serde(borrow)]
+    tag_name: Cow<'a, str>,
+    #[serde(borrow)]
+    name: Option<Cow<'a, str>>,
+    #[serde(borrow)]
+    html_url: Cow<'a, str>,
+    #[serde(borrow)]
+    body: Option<Cow<'a, str>>,
+    #[serde(default)]
+    draft: bool,
+    #[serde(default)]
+    prerelease: bool,
+    #[serde(borrow, default)]
+    author: Option<Sender<'a>>,
+}
+
+#[derive(Template)]
+#[template(path = "release_event.html")]
+pub struct ViewReleaseEvent<'a> {
+    repository: ViewRepository<'a>,
+    author: ViewSender<'a>,
+    tag_name: &'a str,
+    name: Option<&'a str>,
+    html_url: &'a str,
+    excerpt: String,
+    truncated: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WorkflowRunEvent<'a> {
+    #[serde(borrow)]
+    pub action: Cow<'a, str>,
+    #[serde(borrow)]
+    workflow_run: WorkflowRun<'a>,
+    #[serde(borrow)]
+    pub repository: Repository<'a>,
+    #[serde(borrow)]
+    sender: Sender<'a>,
+}
+
+impl WorkflowRunEvent<'_> {
+    pub fn sender(&self) -> &str {
+        &self.sender.login
+    }
+
+    /// Checks whether the run can change whether the workflow is
+    /// failing, which is when a run for a push to the default branch
+    /// completed. Runs for pull requests are left out, as they fail
+    /// while work is in progress.
+    pub fn is_tracked(&self) -> bool {
+        self.action == "completed"
+            && self.workflow_run.event == "push"
+            && self.workflow_run.head_branch.as_deref() == Some(&*self.repository.default_branch)
+    }
+
+    /// Identifies the workflow and branch whose state is tracked.
+    pub fn key(&self) -> String {
+        format!(
+            "{} {} {}",
+            self.repository.full_name,
+            self.workflow_run.name,
+            self.branch(),
+        )
+    }
+
+    pub fn conclusion(&self) -> &str {
+        self.workflow_run.conclusion.as_deref().unwrap_or_default()
+    }
+
+    pub fn to_view(
+        &self,
+        transition: Transition,
+        max_title_length: usize,
+    ) -> ViewWorkflowRunEvent<'_> {
+        let (title, truncated) = commit_title(self.message(), max_title_length);
+        ViewWorkflowRunEvent {
+            action: describe_transition(transition),
+            workflow: &self.workflow_run.name,
+            branch: self.branch(),
+            sha: self.short_sha(),
+            title,
+            truncated,
+            html_url: &self.workflow_run.html_url,
+            repository: self.repository.to_view(),
+        }
+    }
+
+    pub fn to_plain_text(&self, transition: Transition, max_title_length: usize) -> String {
+        let (title, truncated) = commit_title(self.message(), max_title_length);
+        format!(
+            "{} {} {} on {} ({}): {} {}{}",
+            self.repository.to_plain_text(),
+            escape_plain(&self.workflow_run.name),
+            describe_transition(transition),
+            escape_plain(self.branch()),
+            self.workflow_run.html_url,
+            self.short_sha(),
+            escape_plain(title),
+            if truncated { "\u{2026}" } else { "" },
+        )
+    }
+
+    pub fn format_template(
+        &self,
+        template: &MessageTemplate,
+        transition: Transition,
+        format: Format,
+    ) -> String {
+        fill_template(template, format, |placeholder| match placeholder {
+            "repo" => self.repository.to_view().name.into(),
+            "workflow" => self.workflow_run.name.to_string(),
+            "action" => describe_transition(transition).into(),
+            "branch" => self.branch().into(),
+            "sha" => self.short_sha().into(),
+            "title" => commit_title(self.message(), usize::MAX).0.into(),
+            "url" => self.workflow_run.html_url.to_string(),
+            _ => String::new(),
+        })
+    }
+
+    fn branch(&self) -> &str {
+        self.workflow_run.head_branch.as_deref().unwrap_or_default()
+    }
+
+    fn short_sha(&self) -> &str {
+        self.repository.short_sha(&self.workflow_run.head_sha)
+    }
+
+    fn message(&self) -> &str {
+        self.workflow_run
+            .head_commit
+            .as_ref()
+            .map_or("", |commit| &commit.message)
+    }
+}
+
+fn describe_transition(transition: Transition) -> &'static str {
+    match transition {
+        Transition::Failed => "failed",
+        Transition::Recovered => "passed again",
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct WorkflowRun<'a> {
+    #[serde(borrow)]
+    name: Cow<'a, str>,
+    /// Missing for runs which didn't start from a branch.
+    #[serde(borrow)]
+    head_branch: Option<Cow<'a, str>>,
+    #[serde(borrow)]
+    head_sha: Cow<'a, str>,
+    /// What started the run, such as `push` or `pull_request`.
+    #[serde(borrow)]
+    event: Cow<'a, str>,
+    /// Missing until the run completes.
+    #[serde(borrow)]
+    conclusion: Option<Cow<'a, str>>,
+    #[serde(borrow)]
+    html_url: Cow<'a, str>,
+    #[serde(borrow)]
+    head_commit: Option<HeadCommit<'a>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HeadCommit<'a> {
+    #[serde(borrow)]
+    message: Cow<'a, str>,
+}
+
+#[derive(Template)]
+#[template(path = "workflow_run_event.html")]
+pub struct ViewWorkflowRunEvent<'a> {
+    action: &'a str,
+    workflow: &'a str,
+    branch: &'a str,
+    sha: &'a str,
+    title: &'a str,
+    truncated: bool,
+    html_url: &'a str,
+    repository: ViewRepository<'a>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatusEvent<'a> {
+    #[serde(borrow)]
+    sha: Cow<'a, str>,
+    /// One of `pending`, `success`, `failure` and `error`.
+    #[serde(borrow)]
+    state: Cow<'a, str>,
+    #[serde(borrow)]
+    context: Cow<'a, str>,
+    #[serde(borrow)]
+    target_url: Option<Cow<'a, str>>,
+    /// Branches whose latest commit is the one with the status.
+    #[serde(borrow)]
+    branches: Vec<StatusBranch<'a>>,
+    #[serde(borrow)]
+    pub repository: Repository<'a>,
+    #[serde(borrow)]
+    sender: Sender<'a>,
+}
+
+impl StatusEvent<'_> {
+    pub fn sender(&self) -> &str {
+        &self.sender.login
+    }
+
+    /// Returns the reported check, if it's for the default branch.
+    pub fn check(&self) -> Option<Check<'_>> {
+        let default_branch = &self.repository.default_branch;
+        if !self
+            .branches
+            .iter()
+            .any(|branch| branch.name == *default_branch)
+        {
+            return None;
+        }
+        let state = match &*self.state {
+            "pending" => CheckState::Pending,
+            "success" => CheckState::Success,
+            _ => CheckState::Failure,
+        };
+        let url = match &self.target_url {
+            Some(url) => Cow::Borrowed(&**url),
+            None => Cow::Owned(self.repository.commit_url(&self.sha)),
+        };
+        Some(Check {
+            context: &self.context,
+            sha: &self.sha,
+            state,
+            url,
+            repository: &self.repository,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct StatusBranch<'a> {
+    #[serde(borrow)]
+    name: Cow<'a, str>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CheckSuiteEvent<'a> {
+    #[serde(borrow)]
+    pub action: Cow<'a, str>,
+    #[serde(borrow)]
+    check_suite: CheckSuite<'a>,
+    #[serde(borrow)]
+    pub repository: Repository<'a>,
+    #[serde(borrow)]
+    sender: Sender<'a>,
+}
+
+impl CheckSuiteEvent<'_> {
+    pub fn sender(&self) -> &str {
+        &self.sender.login
+    }
+
+    /// Checks whether the suite was run by GitHub Actions, whose runs are
+    /// also delivered as `workflow_run` events.
+    pub fn is_from_actions(&self) -> bool {
+        self.check_suite.app.slug == "github-actions"
+    }
+
+    /// Returns the result of the suite, if it completed on the default
+    /// branch. Cancelled and stale suites don't count as a result.
+    pub fn check(&self) -> Option<Check<'_>> {
+        if self.action != "completed"
+            || self.check_suite.head_branch.as_deref() != Some(&*self.repository.default_branch)
+        {
+            return None;
+        }
+        let state = match self.check_suite.conclusion.as_deref()? {
+            "success" | "neutral" | "skipped" => CheckState::Success,
+            "failure" | "timed_out" | "action_required" | "startup_failure" => CheckState::Failure,
+            _ => return None,
+        };
+        let suite = &self.check_suite;
+        Some(Check {
+            context: &suite.app.name,
+            sha: &suite.head_sha,
+            state,
+            url: Cow::Owned(format!(
+                "{}/checks?check_suite_id={}",
+                self.repository.commit_url(&suite.head_sha),
+                suite.id,
+            )),
+            repository: &self.repository,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CheckSuite<'a> {
+    id: u64,
+    /// Missing for suites which didn't run on a branch.
+    #[serde(borrow)]
+    head_branch: Option<Cow<'a, str>>,
+    #[serde(borrow)]
+    head_sha: Cow<'a, str>,
+    /// Missing until the suite completes.
+    #[serde(borrow)]
+    conclusion: Option<Cow<'a, str>>,
+    #[serde(borrow)]
+    app: App<'a>,
+}
+
+#[derive(Debug, Deserialize)]
+struct App<'a> {
+    #[serde(borrow)]
+    slug: Cow<'a, str>,
+    #[serde(borrow)]
+    name: Cow<'a, str>,
+}
+
+/// Result of a CI check on a commit of the default branch, reported by
+/// either a commit status or a check suite.
+pub struct Check<'a> {
+    /// Name of the status, or of the app running the check suite.
+    pub context: &'a str,
+    pub sha: &'a str,
+    pub state: CheckState,
+    url: Cow<'a, str>,
+    repository: &'a Repository<'a>,
+}
+
+impl Check<'_> {
+    pub fn to_view(&self) -> ViewCheck<'_> {
+        ViewCheck {
+            context: self.context,
+            branch: &self.repository.default_branch,
+            sha: self.short_sha(),
+            url: &self.url,
+            repository: self.repository.to_view(),
+        }
+    }
+
+    pub fn to_plain_text(&self) -> String {
+        format!(
+            "{} {} failed on {} ({}): {}",
+            self.repository.to_plain_text(),
+            escape_plain(self.context),
+            escape_plain(&self.repository.default_branch),
+            self.url,
+            self.short_sha(),
+        )
+    }
+
+    pub fn format_template(&self, template: &MessageTemplate, format: Format) -> String {
+        fill_template(template, format, |placeholder| match placeholder {
+            "repo" => self.repository.to_view().name.into(),
+            "context" => self.context.into(),
+            "branch" => self.repository.default_branch.to_string(),
+            "sha" => self.short_sha().into(),
+            "url" => self.url.to_string(),
+            _ => String::new(),
+        })
+    }
+
+    fn short_sha(&self) -> &str {
+        self.repository.short_sha(self.sha)
+    }
+}
+
+#[derive(Template)]
+#[template(path = "check_event.html")]
+pub struct ViewCheck<'a> {
+    context: &'a str,
+    branch: &'a str,
+    sha: &'a str,
+    url: &'a str,
+    repository: ViewRepository<'a>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateEvent<'a> {
+    /// Name of the branch or tag, without the `refs/heads/` or
+    /// `refs/tags/` prefix.
+    #[serde(borrow, rename = "ref")]
+    git_ref: Cow<'a, str>,
+    /// Either `branch` or `tag`.
+    #[serde(borrow)]
+    ref_type: Cow<'a, str>,
+    #[serde(borrow)]
+    pub repository: Repository<'a>,
+    #[serde(borrow)]
+    sender: Sender<'a>,
+}
+
+impl CreateEvent<'_> {
+    pub fn sender(&self) -> &str {
+        &self.sender.login
+    }
+
+    pub fn name(&self) -> &str {
+        &self.git_ref
+    }
+
+    pub fn is_tag(&self) -> bool {
+        self.ref_type == "tag"
+    }
+
+    pub fn to_view<'a>(&'a self, username_aliases: &'a UsernameAliases) -> ViewCreateEvent<'a> {
+        ViewCreateEvent {
+            kind: &self.ref_type,
+            name: &self.git_ref,
+            url: self.ref_url(),
+            repository: self.repository.to_view(),
+            sender: self.sender.to_view(username_aliases),
+        }
+    }
+
+    pub fn to_plain_text(&self, username_aliases: &UsernameAliases) -> String {
+        format!(
+            "{} {} created {} {} ({})",
+            self.repository.to_plain_text(),
+            escape_plain(username_aliases.get(&self.sender.login)),
+            self.ref_type,
+            escape_plain(&self.git_ref),
+            self.ref_url(),
+        )
+    }
+
+    pub fn format_template(
+        &self,
+        template: &MessageTemplate,
+        username_aliases: &UsernameAliases,
+        format: Format,
+    ) -> String {
+        fill_template(template, format, |placeholder| match placeholder {
+            "user" => username_aliases.get(&self.sender.login).into(),
+            "repo" => self.repository.to_view().name.into(),
+            "kind" => self.ref_type.to_string(),
+            "name" => self.git_ref.to_string(),
+            "url" => self.ref_url(),
+            _ => String::new(),
+        })
+    }
+
+    fn ref_url(&self) -> String {
+        let path = if self.is_tag() {
+            "releases/tag"
+        } else {
+            "tree"
+        };
+        format!("{}/{}/{}", self.repository.html_url, path, self.git_ref)
+    }
+}
+
+#[derive(Template)]
+#[template(path = "create_event.html")]
+pub struct ViewCreateEvent<'a> {
+    kind: &'a str,
+    name: &'a str,
+    url: String,
+    repository: ViewRepository<'a>,
+    sender: ViewSender<'a>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteEvent<'a> {
+    /// Name of the branch or tag, like in [`CreateEvent`].
+    #[serde(borrow, rename = "ref")]
+    git_ref: Cow<'a, str>,
+    #[serde(borrow)]
+    ref_type: Cow<'a, str>,
+    #[serde(borrow)]
+    pub repository: Repository<'a>,
+    #[serde(borrow)]
+    sender: Sender<'a>,
+}
+
+impl DeleteEvent<'_> {
+    pub fn sender(&self) -> &str {
+        &self.sender.login
+    }
+
+    pub fn name(&self) -> &str {
+        &self.git_ref
+    }
+
+    pub fn is_tag(&self) -> bool {
+        self.ref_type == "tag"
+    }
+
+    /// Describes the deletion, starting with the warning for deletions
+    /// of protected branches.
+    pub fn to_view<'a>(
+        &'a self,
+        username_aliases: &'a UsernameAliases,
+        warning: Option<&'a str>,
+    ) -> ViewDeleteEvent<'a> {
+        ViewDeleteEvent {
+            warning,
+            kind: &self.ref_type,
+            name: &self.git_ref,
+            repository: self.repository.to_view(),
+            sender: self.sender.to_view(username_aliases),
+        }
+    }
+
+    pub fn to_plain_text(
+        &self,
+        username_aliases: &UsernameAliases,
+        warning: Option<&str>,
+    ) -> String {
+        let mut text = self.repository.to_plain_text();
+        if let Some(warning) = warning {
+            text += " ";
+            text += &escape_plain(warning);
+        }
+        text += &format!(
+            " {} deleted {} {}",
+            escape_plain(username_aliases.get(&self.sender.login)),
+            self.ref_type,
+            escape_plain(&self.git_ref),
+        );
+        text
+    }
+
+    pub fn format_template(
+        &self,
+        template: &MessageTemplate,
+        username_aliases: &UsernameAliases,
+        format: Format,
+    ) -> String {
+        fill_template(template, format, |placeholder| match placeholder {
+            "user" => username_aliases.get(&self.sender.login).into(),
+            "repo" => self.repository.to_view().name.into(),
+            "kind" => self.ref_type.to_string(),
+            "name" => self.git_ref.to_string(),
+            _ => String::new(),
+        })
+    }
+}
+
+#[derive(Template)]
+#[template(path = "delete_event.html")]
+pub struct ViewDeleteEvent<'a> {
+    warning: Option<&'a str>,
+    kind: &'a str,
+    name: &'a str,
+    repository: ViewRepository<'a>,
+    sender: ViewSender<'a>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ForkEvent<'a> {
+    /// The new fork.
+    #[serde(borrow)]
+    forkee: Forkee<'a>,
+    /// The forked repository, counting the new fork.
+    #[serde(borrow)]
+    pub repository: Repository<'a>,
+    /// Number of later forks merged into this one.
+    #[serde(skip)]
+    merged: usize,
+}
+
+impl ForkEvent<'_> {
+    /// Returns the owner of the fork, which may be an organization.
+    pub fn owner(&self) -> &str {
+        &self.forkee.owner.login
+    }
+
+    /// Identifies forks which are merged together, which are the ones of
+    /// the same repository.
+    pub fn batch_key(&self) -> String {
+        self.repository.full_name.to_string()
+    }
+
+    /// Counts a later fork as part of this one, keeping the latest number
+    /// of forks.
+    pub fn merge(&mut self, later: ForkEvent<'_>) {
+        self.merged += later.merged + 1;
+        self.repository.forks_count = later.repository.forks_count;
+    }
+
+    /// Announces the fork, or how many forks were made when several were
+    /// merged.
+    pub fn to_view<'a>(&'a self, username_aliases: &'a UsernameAliases) -> ViewForkEvent<'a> {
+        ViewForkEvent {
+            owner: username_aliases.get(self.owner()),
+            html_url: &self.forkee.html_url,
+            others: self.merged,
+            forks: self.repository.forks_count,
+            repository: self.repository.to_view(),
+        }
+    }
+
+    pub fn to_plain_text(&self, username_aliases: &UsernameAliases) -> String {
+        let mut text = format!(
+            "{} {}",
+            self.repository.to_plain_text(),
+            escape_plain(username_aliases.get(self.owner())),
+        );
+        if self.merged > 0 {
+            text += &format!(" and {} more", self.merged);
+        }
+        text += &format!(" forked the repository ({})", self.forkee.html_url);
+        if let Some(forks) = self.repository.forks_count {
+            text += &format!(", now {} forks", forks);
+        }
+        text
+    }
+
+    pub fn format_template(
+        &self,
+        template: &MessageTemplate,
+        username_aliases: &UsernameAliases,
+        format: Format,
+    ) -> String {
+        fill_template(template, format, |placeholder| match placeholder {
+            "user" => username_aliases.get(self.owner()).into(),
+            "repo" => self.repository.to_view().name.into(),
+            "count" => (self.merged + 1).to_string(),
+            "forks" => self
+                .repository
+                .forks_count
+                .map_or_else(String::new, |forks| forks.to_string()),
+            "url" => self.forkee.html_url.to_string(),
+            _ => String::new(),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StarEvent<'a> {
+    /// Either `created` or `deleted`.
+    #[serde(borrow)]
+    pub action: Cow<'a, str>,
+    /// The starred repository, counting the new star.
+    #[serde(borrow)]
+    pub repository: Repository<'a>,
+    #[serde(borrow)]
+    sender: Sender<'a>,
+}
+
+impl StarEvent<'_> {
+    pub fn stars(&self) -> Option<u32> {
+        self.repository.stargazers_count
+    }
+
+    fn stargazers_url(&self) -> String {
+        format!("{}/stargazers", self.repository.html_url)
+    }
+
+    /// Announces the repository reaching a number of stars, given by the
+    /// milestone as the count may have moved past it since.
+    pub fn to_view<'a>(
+        &'a self,
+        username_aliases: &'a UsernameAliases,
+        milestone: u32,
+    ) -> ViewStarEvent<'a> {
+        ViewStarEvent {
+            stars: with_separators(milestone),
+            url: self.stargazers_url(),
+            repository: self.repository.to_view(),
+            sender: self.sender.to_view(username_aliases),
+        }
+    }
+
+    pub fn to_plain_text(&self, username_aliases: &UsernameAliases, milestone: u32) -> String {
+        format!(
+            "{} reached {} stars with a star from {} ({})",
+            self.repository.to_plain_text(),
+            with_separators(milestone),
+            escape_plain(username_aliases.get(&self.sender.login)),
+            self.stargazers_url(),
+        )
+    }
+
+    pub fn format_template(
+        &self,
+        template: &MessageTemplate,
+        username_aliases: &UsernameAliases,
+        format: Format,
+        milestone: u32,
+    ) -> String {
+        fill_template(template, format, |placeholder| match placeholder {
+            "user" => username_aliases.get(&self.sender.login).into(),
+            "repo" => self.repository.to_view().name.into(),
+            "stars" => with_separators(milestone),
+            "url" => self.stargazers_url(),
+            _ => String::new(),
+        })
+    }
+}
+
+#[derive(Template)]
+#[template(path = "star_event.html")]
+pub struct ViewStarEvent<'a> {
+    stars: String,
+    url: String,
+    repository: ViewRepository<'a>,
+    sender: ViewSender<'a>,
+}
+
+/// Writes a number with commas between groups of thousands, like
+/// `5,000`.
+fn with_separators(number: u32) -> String {
+    let digits = number.to_string();
+    let mut text = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            text.push(',');
+        }
+        text.push(digit);
+    }
+    text
+}
+
+#[derive(Debug, Deserialize)]
+struct Forkee<'a> {
+    #[serde(borrow)]
+    html_url: Cow<'a, str>,
+    #[serde(borrow)]
+    owner: Owner<'a>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Owner<'a> {
+    #[serde(borrow)]
+    login: Cow<'a, str>,
+}
+
+#[derive(Template)]
+#[template(path = "fork_event.html")]
+pub struct ViewForkEvent<'a> {
+    owner: &'a str,
+    html_url: &'a str,
+    others: usize,
+    forks: Option<u32>,
+    repository: ViewRepository<'a>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MilestoneEvent<'a> {
+    /// One of `created`, `edited`, `closed`, `opened` and `deleted`.
+    #[serde(borrow)]
+    pub action: Cow<'a, str>,
+    #[serde(borrow)]
+    milestone: Milestone<'a>,
+    #[serde(borrow)]
+    pub repository: Repository<'a>,
+    #[serde(borrow)]
+    sender: Sender<'a>,
+}
+
+impl<'a> MilestoneEvent<'a> {
+    pub fn sender(&self) -> &str {
+        &self.sender.login
+    }
+
+    /// Checks whether the action is announced. Edits and reopening only
+    /// correct earlier actions.
+    pub fn is_announced(&self) -> bool {
+        matches!(&*self.action, "created" | "closed" | "deleted")
+    }
+
+    /// Identifies changes which are merged together, which are the ones
+    /// to the same milestone.
+    pub fn batch_key(&self) -> String {
+        format!("{} {}", self.repository.full_name, self.milestone.number)
+    }
+
+    /// Replaces the milestone with how a later change left it. Edits keep
+    /// the earlier action, while reopening a closed milestone cancels
+    /// announcing that it was closed.
+    pub fn merge(&mut self, later: MilestoneEvent<'a>) {
+        if later.action != "edited" {
+            self.action = later.action;
+            self.sender = later.sender;
+        }
+        self.milestone = later.milestone;
+    }
+
+    /// Describes the issue counts and due date, depending on the action.
+    fn details(&self) -> String {
+        let milestone = &self.milestone;
+        if self.action == "closed" {
+            let mut details = format!(
+                " \u{2014} {} issue{} completed",
+                milestone.closed_issues,
+                if milestone.closed_issues == 1 {
+                    ""
+                } else {
+                    "s"
+                },
+            );
+            if milestone.open_issues > 0 {
+                details += &format!(", {} still open", milestone.open_issues);
+            }
+            return details;
+        }
+        let mut details = String::new();
+        if let Some(due_on) = milestone.due_on {
+            details += &format!(", due {}", due_on.format("%Y-%m-%d"));
+        }
+        if milestone.open_issues > 0 || milestone.closed_issues > 0 {
+            details += &format!(
+                ", {} open and {} closed issue{}",
+                milestone.open_issues,
+                milestone.closed_issues,
+                if milestone.closed_issues == 1 {
+                    ""
+                } else {
+                    "s"
+                },
+            );
+        }
+        details
+    }
+
+    pub fn to_view<'b>(&'b self, username_aliases: &'b UsernameAliases) -> ViewMilestoneEvent<'b> {
+        ViewMilestoneEvent {
+            action: &self.action,
+            title: &self.milestone.title,
+            html_url: &self.milestone.html_url,
+            details: self.details(),
+            repository: self.repository.to_view(),
+            sender: self.sender.to_view(username_aliases),
+        }
+    }
+
+    pub fn to_plain_text(&self, username_aliases: &UsernameAliases) -> String {
+        let title = escape_plain(&self.milestone.title);
+        match &*self.action {
+            "closed" => format!(
+                "{} Milestone {} closed{} ({})",
+                self.repository.to_plain_text(),
+                title,
+                self.details(),
+                self.milestone.html_url,
+            ),
+            "deleted" => format!(
+                "{} {} deleted milestone {}{}",
+                self.repository.to_plain_text(),
+                escape_plain(username_aliases.get(&self.sender.login)),
+                title,
+                self.details(),
+            ),
+            action => format!(
+                "{} {} {} milestone {}{} ({})",
+                self.repository.to_plain_text(),
+                escape_plain(username_aliases.get(&self.sender.login)),
+                action,
+                title,
+                self.details(),
+                self.milestone.html_url,
+            ),
+        }
+    }
+
+    pub fn format_template(
+        &self,
+        template: &MessageTemplate,
+        username_aliases: &UsernameAliases,
+        format: Format,
+    ) -> String {
+        fill_template(template, format, |placeholder| match placeholder {
+            "user" => username_aliases.get(&self.sender.login).into(),
+            "repo" => self.repository.to_view().name.into(),
+            "action" => self.action.to_string(),
+            "title" => self.milestone.title.to_string(),
+            "open" => self.milestone.open_issues.to_string(),
+            "closed" => self.milestone.closed_issues.to_string(),
+            "due" => self
+                .milestone
+                .due_on
+                .map_or_else(String::new, |due_on| due_on.format("%Y-%m-%d").to_string()),
+            "url" => self.milestone.html_url.to_string(),
+            _ => String::new(),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Milestone<'a> {
+    #[serde(borrow)]
+    html_url: Cow<'a, str>,
+    number: u32,
+    #[serde(borrow)]
+    title: Cow<'a, str>,
+    open_issues: u32,
+    closed_issues: u32,
+    due_on: Option<DateTime<FixedOffset>>,
+}
+
+#[derive(Template)]
+#[template(path = "milestone_event.html")]
+pub struct ViewMilestoneEvent<'a> {
+    action: &'a str,
+    title: &'a str,
+    html_url: &'a str,
+    details: String,
+    repository: ViewRepository<'a>,
+    sender: ViewSender<'a>,
+}
+
+/// Number of wiki pages listed in an announcement before the rest are
+/// only counted.
+const MAX_WIKI_PAGES: usize = 3;
+
+#[derive(Debug, Deserialize)]
+pub struct GollumEvent<'a> {
+    /// Pages changed by the delivery, as several edits can be pushed to
+    /// the wiki at once.
+    #[serde(borrow)]
+    pages: Vec<WikiPage<'a>>,
+    #[serde(borrow)]
+    pub repository: Repository<'a>,
+    #[serde(borrow)]
+    sender: Sender<'a>,
+}
+
+impl GollumEvent<'_> {
+    pub fn sender(&self) -> &str {
+        &self.sender.login
+    }
+
+    fn wiki_url(&self) -> String {
+        format!("{}/wiki", self.repository.html_url)
+    }
+
+    /// Lists the changed pages, linking to the revisions made.
+    pub fn to_view<'a>(&'a self, username_aliases: &'a UsernameAliases) -> ViewGollumEvent<'a> {
+        ViewGollumEvent {
+            pages: self
+                .pages
+                .iter()
+                .take(MAX_WIKI_PAGES)
+                .map(WikiPage::to_view)
+                .collect(),
+            remaining_pages: self.pages.len().saturating_sub(MAX_WIKI_PAGES),
+            wiki_url: self.wiki_url(),
+            repository: self.repository.to_view(),
+            sender: self.sender.to_view(username_aliases),
+        }
+    }
+
+    /// Summarizes the changes in a line, for `simple_rooms`.
+    pub fn format_simple<'a>(
+        &'a self,
+        username_aliases: &'a UsernameAliases,
+    ) -> ViewSimpleGollumEvent<'a> {
+        ViewSimpleGollumEvent {
+            count: self.pages.len(),
+            wiki_url: self.wiki_url(),
+            repository: self.repository.to_view(),
+            sender: self.sender.to_view(username_aliases),
+        }
+    }
+
+    pub fn to_plain_text(&self, username_aliases: &UsernameAliases) -> String {
+        let pages: Vec<_> = self
+            .pages
+            .iter()
+            .take(MAX_WIKI_PAGES)
+            .map(|page| {
+                format!(
+                    "{} {} ({})",
+                    page.action,
+                    escape_plain(&page.title),
+                    page.revision_url(),
+                )
+            })
+            .collect();
+        let mut text = format!(
+            "{} {} updated the wiki: {}",
+            self.repository.to_plain_text(),
+            escape_plain(username_aliases.get(&self.sender.login)),
+            pages.join(", "),
+        );
+        let remaining = self.pages.len().saturating_sub(MAX_WIKI_PAGES);
+        if remaining > 0 {
+            text += &format!(
+                " and {} more page{}",
+                remaining,
+                if remaining == 1 { "" } else { "s" },
+            );
+        }
+        text
+    }
+
+    pub fn simple_to_plain_text(&self, username_aliases: &UsernameAliases) -> String {
+        format!(
+            "{} {} updated {} wiki page{} ({})",
+            self.repository.to_plain_text(),
+            escape_plain(username_aliases.get(&self.sender.login)),
+            self.pages.len(),
+            if self.pages.len() == 1 { "" } else { "s" },
+            self.wiki_url(),
+        )
+    }
+
+    pub fn format_template(
+        &self,
+        template: &MessageTemplate,
+        username_aliases: &UsernameAliases,
+        format: Format,
+    ) -> String {
+        fill_template(template, format, |placeholder| match placeholder {
+            "user" => username_aliases.get(&self.sender.login).into(),
+            "repo" => self.repository.to_view().name.into(),
+            "count" => self.pages.len().to_string(),
+            "title" => self
+                .pages
+                .first()
+                .map_or_else(String::new, |page| page.title.to_string()),
+            "url" => self.wiki_url(),
+            _ => String::new(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct WikiPage<'a> {
+    #[serde(borrow)]
+    title: Cow<'a, str>,
+    /// Either `created` or `edited`.
+    #[serde(borrow)]
+    action: Cow<'a, str>,
+    /// Revision of the page made by the change.
+    #[serde(borrow)]
+    sha: Cow<'a, str>,
+    #[serde(borrow)]
+    html_url: Cow<'a, str>,
+}
+
+impl WikiPage<'_> {
+    fn revision_url(&self) -> String {
+        format!("{}/{}", self.html_url, self.sha)
+    }
+
+    fn to_view(&self) -> ViewWikiPage<'_> {
+        ViewWikiPage {
+            title: &self.title,
+            action: &self.action,
+            url: self.revision_url(),
+        }
+    }
+}
+
+struct ViewWikiPage<'a> {
+    title: &'a str,
+    action: &'a str,
+    url: String,
+}
+
+#[derive(Template)]
+#[template(path = "gollum_event.html")]
+pub struct ViewGollumEvent<'a> {
+    pages: Vec<ViewWikiPage<'a>>,
+    remaining_pages: usize,
+    wiki_url: String,
+    repository: ViewRepository<'a>,
+    sender: ViewSender<'a>,
+}
+
+#[derive(Template)]
+#[template(path = "simple_gollum_event.html")]
+pub struct ViewSimpleGollumEvent<'a> {
+    count: usize,
+    wiki_url: String,
+    repository: ViewRepository<'a>,
+    sender: ViewSender<'a>,
+}
+
+#[cfg(test)]
 mod test {
     use super::{
-        Author, Commit, PullRequest, PullRequestEvent, PushEvent, PushEventContext, Pusher,
-        Repository, Sender,
+        escape_plain, escape_showdown, strip_markdown, with_separators, Author, CheckSuiteEvent,
+        Commit, CommitCommentEvent, CreateEvent, DeleteEvent, DeploymentStatusEvent, ForkEvent,
+        GollumEvent, Issue, IssueCommentEvent, IssueEvent, Label, MilestoneEvent, PullRequest,
+        PullRequestEvent, PullRequestReviewCommentEvent, PullRequestReviewEvent, PushEvent,
+        PushEventContext, Pusher, Release, ReleaseEvent, Repository, RepositoryEvent, Review,
+        ReviewComment, Sender, StarEvent, StatusEvent, WikiPage, WorkflowRunEvent,
     };
-    use crate::config::UsernameAliases;
+    use crate::config::{UsernameAliases, DEFAULT_SHA_LENGTH};
+    use crate::github_api::{CommitInfo, PullRequestSummary};
+    use crate::message_template::{placeholders, MessageTemplate};
+    use crate::quiet_hours::Format;
+    use crate::webhook::workflows::{CheckState, Transition};
+    use chrono_tz::Tz;
+    use serde::Deserialize;
+
+    fn sample_commit() -> Commit<'static> {
+        Commit {
+            id: "0da2590a700d054fc2ce39ddc9c95f360329d9be".into(),
+            message: "Hello, world!".into(),
+            author: Author {
+                name: "Konrad Borowski".into(),
+                username: Some("xfix".into()),
+            },
+            url: "http://example.com".into(),
+            timestamp: None,
+            added: vec![],
+            removed: vec![],
+            modified: vec![],
+        }
+    }
+
+    fn sample_push_event(commits: usize) -> PushEvent<'static> {
+        PushEvent {
+            git_ref: "refs/heads/master".into(),
+            before: "1111111111111111111111111111111111111111".into(),
+            after: "2222222222222222222222222222222222222222".into(),
+            commits: (0..commits).map(|_| sample_commit()).collect(),
+            pusher: Pusher {
+                name: "Zarel".into(),
+            },
+            repository: Repository {
+                name: "pokemon-showdown".into(),
+                full_name: "smogon/pokemon-showdown".into(),
+                html_url: "https://github.com/smogon/pokemon-showdown".into(),
+                default_branch: "master".into(),
+                forks_count: None,
+                stargazers_count: None,
+                display_name: None,
+                sha_length: DEFAULT_SHA_LENGTH,
+            },
+            forced: false,
+            deleted: false,
+            pull_request: None,
+            tag_message: None,
+            head_commit: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_push_event() {
+        let commit = concat!(
+            "[<a href='https:&#x2f;&#x2f;github.com&#x2f;smogon&#x2f;pokemon-showdown'>",
+            "<font color=FF00FF>server</font></a>] ",
+            "<a href='http:&#x2f;&#x2f;example.com'><font color=606060><kbd>0da2590</kbd></font></a>\n",
+            "Hello, world! ",
+            r#"<font color=909090 title="Konrad Borowski">(xfix)</font>"#,
+        );
+        assert_eq!(
+            sample_push_event(2)
+                .to_view(PushEventContext {
+                    github_api: None,
+                    username_aliases: &UsernameAliases::default(),
+                    timezone: Tz::UTC,
+                    max_commits: 5,
+                    max_commit_title_length: 100,
+                    sha_length: 7,
+                })
+                .await
+                .to_string(),
+            format!("{0}<br>{0}", commit)
+        );
+    }
+
+    #[test]
+    fn test_simple_push_event() {
+        let mut aliases = UsernameAliases::default();
+        aliases.insert("zarel".into(), "Guangcong".into());
+        assert_eq!(
+            sample_push_event(2).format_simple(&aliases).to_string(),
+            concat!(
+                "[<a href='https:&#x2f;&#x2f;github.com&#x2f;smogon&#x2f;pokemon-showdown'>",
+                "<font color=FF00FF>server</font></a>] ",
+                "<font color=909090>Guangcong</font> pushed ",
+                "<a href='https:&#x2f;&#x2f;github.com&#x2f;smogon&#x2f;pokemon-showdown",
+                "&#x2f;compare&#x2f;111111111111...222222222222'>2 commits</a> to master",
+            ),
+        );
+        assert!(sample_push_event(1)
+            .format_simple(&aliases)
+            .to_string()
+            .contains(">1 commit</a>"));
+    }
+
+    #[test]
+    fn test_push_event_from_commit() {
+        let commit: CommitInfo = serde_json::from_value(serde_json::json!({
+            "sha": "2222222222222222222222222222222222222222",
+            "html_url": "https://github.com/a/b/commit/2222222222222222222222222222222222222222",
+            "commit": {"message": "Update things", "author": {"name": "Guangcong Chen"}},
+            "author": {"login": "Zarel"},
+            "parents": [{"sha": "1111111111111111111111111111111111111111"}],
+            "files": [{"filename": "src/main.rs"}]
+        }))
+        .unwrap();
+        let push_event = PushEvent::from_commit("a/b", "main", &commit);
+        assert_eq!(push_event.branch(), "main");
+        assert_eq!(push_event.repository.default_branch, "main");
+        let files: Vec<_> = push_event.changed_files().collect();
+        assert_eq!(files, ["src/main.rs"]);
+        assert_eq!(
+            push_event.to_plain_text(&UsernameAliases::default()),
+            "[b] Zarel pushed 1 commit to main: \
+             https://github.com/a/b/compare/111111111111...222222222222",
+        );
+    }
+
+    #[tokio::test]
+    async fn test_push_event_with_pull_request() {
+        let mut push_event = sample_push_event(1);
+        push_event.git_ref = "refs/heads/feature".into();
+        push_event.pull_request = Some(PullRequestSummary {
+            number: 42,
+            title: "Add **widget**".into(),
+            html_url: "https://github.com/smogon/pokemon-showdown/pull/42".into(),
+        });
+        let aliases = UsernameAliases::default();
+        assert!(
+            push_event.format_simple(&aliases).to_string().ends_with(concat!(
+                " to feature (<a href='https:&#x2f;&#x2f;github.com&#x2f;smogon&#x2f;",
+                "pokemon-showdown&#x2f;pull&#x2f;42'>PR #42</a>: Add &#x2a;&#x2a;widget&#x2a;&#x2a;)",
+            )),
+            "{}",
+            push_event.format_simple(&aliases),
+        );
+        assert!(push_event
+            .to_plain_text(&aliases)
+            .contains(" to feature (PR #42: Add *\u{200B}*widget*\u{200B}*): "));
+        let ctx = PushEventContext {
+            github_api: None,
+            username_aliases: &aliases,
+            timezone: Tz::UTC,
+            max_commits: 3,
+            max_commit_title_length: 70,
+            sha_length: DEFAULT_SHA_LENGTH,
+        };
+        let view = push_event.to_view(ctx).await.to_string();
+        assert!(
+            view.ends_with(concat!(
+                "<br>[<a href='https:&#x2f;&#x2f;github.com&#x2f;smogon&#x2f;pokemon-showdown'>",
+                "<font color=FF00FF>server</font></a>] Pushed to <a href='https:&#x2f;&#x2f;",
+                "github.com&#x2f;smogon&#x2f;pokemon-showdown&#x2f;pull&#x2f;42'>PR #42</a>: ",
+                "Add &#x2a;&#x2a;widget&#x2a;&#x2a;",
+            )),
+            "{}",
+            view,
+        );
+    }
+
+    #[test]
+    fn test_plain_text_push_event() {
+        let mut aliases = UsernameAliases::default();
+        aliases.insert("zarel".into(), "Guangcong".into());
+        assert_eq!(
+            sample_push_event(2).to_plain_text(&aliases),
+            concat!(
+                "[server] Guangcong pushed 2 commits to master: ",
+                "https://github.com/smogon/pokemon-showdown/compare/111111111111...222222222222",
+            ),
+        );
+    }
+
+    #[test]
+    fn test_push_event_template() {
+        let template =
+            MessageTemplate::parse("{repo}: <b>{user}</b> +{count} {url}", placeholders("push"))
+                .unwrap();
+        let mut push_event = sample_push_event(3);
+        push_event.pusher.name = "<Zarel>".into();
+        assert_eq!(
+            push_event.format_template(&template, &UsernameAliases::default(), Format::PlainText),
+            concat!(
+                "server: <b><Zarel></b> +3 ",
+                "https://github.com/smogon/pokemon-showdown/compare/111111111111...222222222222",
+            ),
+        );
+        let template =
+            MessageTemplate::parse("<b>{user}</b> on {branch}", placeholders("push")).unwrap();
+        assert_eq!(
+            push_event.format_template(&template, &UsernameAliases::default(), Format::Html),
+            "<b>&lt;Zarel&gt;</b> on master",
+        );
+    }
+
+    #[test]
+    fn test_forced_push_event() {
+        let mut push_event = sample_push_event(0);
+        push_event.forced = true;
+        assert!(push_event.is_forced());
+        assert_eq!(
+            push_event
+                .format_forced(&UsernameAliases::default(), "\u{26a0}")
+                .to_string(),
+            concat!(
+                "[<a href='https:&#x2f;&#x2f;github.com&#x2f;smogon&#x2f;pokemon-showdown'>",
+                "<font color=FF00FF>server</font></a>] \u{26a0} ",
+                "<font color=909090>Zarel</font> force-pushed to ",
+                "<a href='https:&#x2f;&#x2f;github.com&#x2f;smogon&#x2f;pokemon-showdown",
+                "&#x2f;commit&#x2f;2222222222222222222222222222222222222222'>master</a>",
+            ),
+        );
+        assert_eq!(
+            push_event.forced_to_plain_text(&UsernameAliases::default(), "**Careful**"),
+            concat!(
+                "[server] *\u{200B}*Careful*\u{200B}* Zarel force-pushed to master: ",
+                "https://github.com/smogon/pokemon-showdown/commit/",
+                "2222222222222222222222222222222222222222",
+            ),
+        );
+    }
 
-    fn sample_commit() -> Commit<'static> {
-        Commit {
-            id: "0da2590a700d054fc2ce39ddc9c95f360329d9be".into(),
-            message: "Hello, world!".into(),
-            author: Author {
-                name: "Konrad Borowski".into(),
-                username: Some("xfix".into()),
+    fn deleted_push_event(git_ref: &str) -> PushEvent<'static> {
+        from_json(serde_json::json!({
+            "ref": git_ref,
+            "before": "1111111111111111111111111111111111111111",
+            "after": "0000000000000000000000000000000000000000",
+            "deleted": true,
+            "commits": [],
+            "pusher": {"name": "Zarel"},
+            "repository": {
+                "name": "pokemon-showdown",
+                "full_name": "smogon/pokemon-showdown",
+                "html_url": "https://github.com/smogon/pokemon-showdown",
+                "default_branch": "master"
+            }
+        }))
+    }
+
+    #[test]
+    fn test_deleted_branch() {
+        let aliases = UsernameAliases::default();
+        let push_event = deleted_push_event("refs/heads/feature");
+        assert!(push_event.is_deleted());
+        assert_eq!(
+            push_event.format_deleted(&aliases, None).to_string(),
+            concat!(
+                "[<a href='https:&#x2f;&#x2f;github.com&#x2f;smogon&#x2f;pokemon-showdown'>",
+                "<font color=FF00FF>server</font></a>] ",
+                "<font color=909090>Zarel</font> deleted branch feature",
+            ),
+        );
+        assert_eq!(
+            push_event.deleted_to_plain_text(&aliases, None),
+            "[server] Zarel deleted branch feature",
+        );
+        assert_eq!(
+            push_event
+                .format_deleted(&aliases, Some("Careful:"))
+                .to_string(),
+            concat!(
+                "[<a href='https:&#x2f;&#x2f;github.com&#x2f;smogon&#x2f;pokemon-showdown'>",
+                "<font color=FF00FF>server</font></a>] ",
+                "<b>Careful: <font color=909090>Zarel</font> deleted branch feature</b>",
+            ),
+        );
+        assert_eq!(
+            push_event.deleted_to_plain_text(&aliases, Some("Careful:")),
+            "[server] Careful: Zarel deleted branch feature",
+        );
+    }
+
+    #[test]
+    fn test_deleted_tag() {
+        let push_event = deleted_push_event("refs/tags/v1.0");
+        assert_eq!(
+            push_event.deleted_to_plain_text(&UsernameAliases::default(), None),
+            "[server] Zarel deleted tag v1.0",
+        );
+        assert!(push_event
+            .format_deleted(&UsernameAliases::default(), None)
+            .to_string()
+            .ends_with("</font> deleted tag v1.0"));
+    }
+
+    #[test]
+    fn test_tag_push() {
+        let aliases = UsernameAliases::default();
+        let mut push_event: PushEvent = fixture("push_tag");
+        assert_eq!(push_event.tag(), Some("v1.2.3"));
+        assert_eq!(
+            push_event.tag_to_plain_text(&aliases),
+            concat!(
+                "[server] Zarel tagged v1.2.3 ",
+                "(https://github.com/smogon/pokemon-showdown/releases/tag/v1.2.3)",
+            ),
+        );
+        push_event.tag_message = Some("Version 1.2.3\n\nFixes Spikes\n".into());
+        assert_eq!(
+            push_event.format_tag(&aliases).to_string(),
+            concat!(
+                "[<a href='https:&#x2f;&#x2f;github.com&#x2f;smogon&#x2f;pokemon-showdown'>",
+                "<font color=FF00FF>server</font></a>] <font color=909090>Zarel</font> tagged ",
+                "<a href='https:&#x2f;&#x2f;github.com&#x2f;smogon&#x2f;pokemon-showdown",
+                "&#x2f;releases&#x2f;tag&#x2f;v1.2.3'>v1.2.3</a>: Version 1.2.3",
+            ),
+        );
+        assert_eq!(sample_push_event(1).tag(), None);
+    }
+
+    #[test]
+    fn test_annotated_tag() {
+        let push_event: PushEvent = fixture("push_tag");
+        assert!(push_event.is_annotated_tag());
+        let lightweight = include_str!("../../testdata/push_tag.json").replace(
+            "8c5b3a0e9f1d2c4b6a7e8f9d0c1b2a3e4f5d6c7b",
+            "2f6f3ad3c1b0e5d7a9c8b7a6f5e4d3c2b1a09f8e",
+        );
+        let push_event: PushEvent = serde_json::from_str(&lightweight).unwrap();
+        assert!(!push_event.is_annotated_tag());
+        assert!(!sample_push_event(1).is_annotated_tag());
+    }
+
+    #[test]
+    fn test_missing_default_branch() {
+        let repository: Repository<'_> = serde_json::from_str(
+            r#"{"name": "a", "full_name": "a/a", "html_url": "https://github.com/a/a"}"#,
+        )
+        .unwrap();
+        assert_eq!(repository.default_branch, "");
+    }
+
+    #[test]
+    fn test_push_event_is_not_forced_by_default() {
+        let push_event: PushEvent = serde_json::from_str(
+            r#"{
+                "ref": "refs/heads/master",
+                "before": "1111111111111111111111111111111111111111",
+                "after": "2222222222222222222222222222222222222222",
+                "commits": [],
+                "pusher": {"name": "xfix"},
+                "repository": {
+                    "name": "pokemon-showdown",
+                    "full_name": "smogon/pokemon-showdown",
+                    "html_url": "https://github.com/smogon/pokemon-showdown",
+                    "default_branch": "master"
+                }
+            }"#,
+        )
+        .unwrap();
+        assert!(!push_event.is_forced());
+        assert!(!push_event.is_deleted());
+    }
+
+    #[tokio::test]
+    async fn test_large_push_event_is_collapsed() {
+        let view = sample_push_event(40)
+            .to_view(PushEventContext {
+                github_api: None,
+                username_aliases: &UsernameAliases::default(),
+                timezone: Tz::UTC,
+                max_commits: 5,
+                max_commit_title_length: 100,
+                sha_length: 7,
+            })
+            .await
+            .to_string();
+        assert_eq!(view.matches("<kbd>0da2590</kbd>").count(), 5);
+        assert!(
+            view.ends_with(concat!(
+                "<br>[<a href='https:&#x2f;&#x2f;github.com&#x2f;smogon&#x2f;pokemon-showdown'>",
+                "<font color=FF00FF>server</font></a>] ",
+                "<a href='https:&#x2f;&#x2f;github.com&#x2f;smogon&#x2f;pokemon-showdown",
+                "&#x2f;compare&#x2f;111111111111...222222222222'>&hellip;and 35 more</a>",
+            )),
+            "{}",
+            view,
+        );
+    }
+
+    #[tokio::test]
+    async fn test_max_commits_per_room() {
+        let push_event = sample_push_event(12);
+        let mut views = Vec::new();
+        for max_commits in [3, 10] {
+            views.push(
+                push_event
+                    .to_view(PushEventContext {
+                        github_api: None,
+                        username_aliases: &UsernameAliases::default(),
+                        timezone: Tz::UTC,
+                        max_commits,
+                        max_commit_title_length: 100,
+                        sha_length: 7,
+                    })
+                    .await
+                    .to_string(),
+            );
+        }
+        assert_eq!(views[0].matches("<kbd>0da2590</kbd>").count(), 3);
+        assert!(views[0].ends_with("&hellip;and 9 more</a>"), "{}", views[0]);
+        assert_eq!(views[1].matches("<kbd>0da2590</kbd>").count(), 10);
+        assert!(views[1].ends_with("&hellip;and 2 more</a>"), "{}", views[1]);
+    }
+
+    #[test]
+    fn test_escape_showdown() {
+        assert_eq!(escape_showdown("**bold**"), "&#x2a;&#x2a;bold&#x2a;&#x2a;");
+        assert_eq!(
+            escape_showdown("__italic__"),
+            "&#x5f;&#x5f;italic&#x5f;&#x5f;"
+        );
+        assert_eq!(
+            escape_showdown("~~struck~~"),
+            "&#x7e;&#x7e;struck&#x7e;&#x7e;"
+        );
+        assert_eq!(escape_showdown("^^up^^"), "&#x5e;&#x5e;up&#x5e;&#x5e;");
+        assert_eq!(escape_showdown(r"\\down\\"), "&#x5c;&#x5c;down&#x5c;&#x5c;");
+        assert_eq!(escape_showdown("``code``"), "&#x60;&#x60;code&#x60;&#x60;");
+        assert_eq!(escape_showdown("[[link]]"), "&#x5b;&#x5b;link]]");
+        assert_eq!(escape_showdown("<b>&</b>"), "&lt;b&gt;&amp;&lt;/b&gt;");
+        assert_eq!(
+            escape_showdown("single *stars* and _lines_"),
+            "single *stars* and _lines_"
+        );
+    }
+
+    #[test]
+    fn test_escape_showdown_commands() {
+        assert_eq!(escape_showdown("/ban someone"), "&#x2f;ban someone");
+        assert_eq!(escape_showdown("a\n/ban someone"), "a\n&#x2f;ban someone");
+        assert_eq!(escape_showdown("a/b"), "a/b");
+    }
+
+    #[test]
+    fn test_escape_plain() {
+        assert_eq!(escape_plain("**bold**"), "*\u{200B}*bold*\u{200B}*");
+        assert_eq!(escape_plain("****"), "*\u{200B}*\u{200B}*\u{200B}*");
+        assert_eq!(escape_plain("[[link]]"), "[\u{200B}[link]]");
+        assert_eq!(escape_plain("a\n/ban  someone\n"), "a /ban someone");
+        assert_eq!(escape_plain("single *stars*"), "single *stars*");
+    }
+
+    #[tokio::test]
+    async fn test_commit_with_markup() {
+        let mut commit = sample_commit();
+        commit.message = "/ban **everyone** in #1".into();
+        commit.author.name = "__Evil__".into();
+        commit.author.username = None;
+        let view = commit
+            .to_view(
+                "http://example.com",
+                &mut PushEventContext {
+                    github_api: None,
+                    username_aliases: &UsernameAliases::default(),
+                    timezone: Tz::UTC,
+                    max_commits: 5,
+                    max_commit_title_length: 100,
+                    sha_length: 7,
+                },
+            )
+            .await
+            .to_string();
+        assert!(
+            view.ends_with(concat!(
+                "\n&#x2f;ban &#x2a;&#x2a;everyone&#x2a;&#x2a; in ",
+                "<a href='http://example.com/issues/1'>#1</a> ",
+                "<font color=909090>(&#x5f;&#x5f;Evil&#x5f;&#x5f;)</font>",
+            )),
+            "{}",
+            view,
+        );
+    }
+
+    #[tokio::test]
+    async fn test_commit() {
+        assert_eq!(
+            sample_commit()
+                .to_view(
+                    "shouldn't be used",
+                    &mut PushEventContext {
+                        github_api: None,
+                        username_aliases: &UsernameAliases::default(),
+                        timezone: Tz::UTC,
+                        max_commits: 5,
+                        max_commit_title_length: 100,
+                        sha_length: 7,
+                    }
+                )
+                .await
+                .to_string(),
+            concat!(
+                "<a href='http:&#x2f;&#x2f;example.com'>",
+                "<font color=606060><kbd>0da2590</kbd></font></a>\n",
+                "Hello, world! ",
+                r#"<font color=909090 title="Konrad Borowski">(xfix)</font>"#,
+            ),
+        );
+    }
+
+    async fn commit_view(message: &str, max_commit_title_length: usize) -> String {
+        let mut commit = sample_commit();
+        commit.message = message.to_owned().into();
+        commit.author.username = None;
+        commit
+            .to_view(
+                "http://example.com",
+                &mut PushEventContext {
+                    github_api: None,
+                    username_aliases: &UsernameAliases::default(),
+                    timezone: Tz::UTC,
+                    max_commits: 5,
+                    max_commit_title_length,
+                    sha_length: 7,
+                },
+            )
+            .await
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn test_commit_body_is_left_out() {
+        for message in [
+            "Fix crash  \n\nThe body explaining\nthe fix.",
+            "Fix crash\r\n\r\nThe body explaining\r\nthe fix.",
+        ] {
+            let view = commit_view(message, 100).await;
+            assert!(
+                view.ends_with("</a>\nFix crash <font color=909090>(Konrad Borowski)</font>"),
+                "{}",
+                view,
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_long_commit_title_is_truncated() {
+        let view = commit_view(&"Update data ".repeat(10), 19).await;
+        assert!(
+            view.ends_with(
+                "</a>\nUpdate data Update&hellip; <font color=909090>(Konrad Borowski)</font>",
+            ),
+            "{}",
+            view,
+        );
+        let view = commit_view("Update data", 11).await;
+        assert!(view.contains("\nUpdate data <font"), "{}", view);
+    }
+
+    #[tokio::test]
+    async fn test_commit_timestamp_timezones() {
+        let mut commit = sample_commit();
+        commit.timestamp = Some("2021-07-01T12:30:00Z".parse().unwrap());
+        let mut views = Vec::new();
+        for timezone in [Tz::Europe__Warsaw, Tz::America__New_York] {
+            views.push(
+                commit
+                    .to_view(
+                        "shouldn't be used",
+                        &mut PushEventContext {
+                            github_api: None,
+                            username_aliases: &UsernameAliases::default(),
+                            timezone,
+                            max_commits: 5,
+                            max_commit_title_length: 100,
+                            sha_length: 7,
+                        },
+                    )
+                    .await
+                    .to_string(),
+            );
+        }
+        assert!(
+            views[0].starts_with(
+                "<a href='http:&#x2f;&#x2f;example.com' title='2021-07-01 14:30 CEST'>"
+            ),
+            "{}",
+            views[0],
+        );
+        assert!(
+            views[1].starts_with(
+                "<a href='http:&#x2f;&#x2f;example.com' title='2021-07-01 08:30 EDT'>"
+            ),
+            "{}",
+            views[1],
+        );
+    }
+
+    #[test]
+    fn test_changed_files() {
+        let push_event: PushEvent = serde_json::from_str(
+            r#"{
+                "ref": "refs/heads/master",
+                "before": "1111111111111111111111111111111111111111",
+                "after": "2222222222222222222222222222222222222222",
+                "commits": [
+                    {
+                        "id": "0da2590a700d054fc2ce39ddc9c95f360329d9be",
+                        "message": "Update things",
+                        "author": {"name": "Konrad Borowski", "username": "xfix"},
+                        "url": "http://example.com",
+                        "added": ["sim/new.ts"],
+                        "removed": [],
+                        "modified": ["web/index.html"]
+                    },
+                    {
+                        "id": "1da2590a700d054fc2ce39ddc9c95f360329d9be",
+                        "message": "Remove things",
+                        "author": {"name": "Konrad Borowski", "username": "xfix"},
+                        "url": "http://example.com",
+                        "removed": ["sim/old.ts"]
+                    }
+                ],
+                "pusher": {"name": "xfix"},
+                "repository": {
+                    "name": "pokemon-showdown",
+                    "full_name": "smogon/pokemon-showdown",
+                    "html_url": "https://github.com/smogon/pokemon-showdown",
+                    "default_branch": "master"
+                }
+            }"#,
+        )
+        .unwrap();
+        let files: Vec<_> = push_event.changed_files().collect();
+        assert_eq!(files, ["sim/new.ts", "web/index.html", "sim/old.ts"]);
+    }
+
+    fn sample_pull_request() -> PullRequestEvent<'static> {
+        PullRequestEvent {
+            action: "created".into(),
+            pull_request: PullRequest {
+                number: 1,
+                html_url: "http://example.com/pr/1".into(),
+                title: "Hello, world".into(),
+                merged: false,
+                merge_commit_sha: None,
+            },
+            repository: Repository {
+                name: "ExampleCom".into(),
+                full_name: "example/ExampleCom".into(),
+                html_url: "http://example.com/".into(),
+                default_branch: "master".into(),
+                forks_count: None,
+                stargazers_count: None,
+                display_name: None,
+                sha_length: DEFAULT_SHA_LENGTH,
+            },
+            sender: Sender {
+                login: "Me".into(),
+                html_url: "https://github.com/Me".into(),
+            },
+            label: None,
+        }
+    }
+
+    #[test]
+    fn test_pull_request() {
+        assert_eq!(
+            sample_pull_request()
+                .to_view(&UsernameAliases::default())
+                .to_string(),
+            concat!(
+                "[<a href='http:&#x2f;&#x2f;example.com&#x2f;'><font color=FF00FF>",
+                "ExampleCom</font></a>] <a href='https:&#x2f;&#x2f;github.com&#x2f;Me'><font ",
+                "color='909090'>Me</font></a> created ",
+                "<a href='http:&#x2f;&#x2f;example.com&#x2f;pr&#x2f;1'>PR#1</a>: Hello, world",
+            ),
+        );
+    }
+
+    #[test]
+    fn test_pull_request_template() {
+        let template = MessageTemplate::parse(
+            "{user} {action} #{number} in {repo}: {title} <{url}>",
+            placeholders("pull_request"),
+        )
+        .unwrap();
+        assert_eq!(
+            sample_pull_request().format_template(
+                &template,
+                &UsernameAliases::default(),
+                Format::PlainText,
+            ),
+            "Me created #1 in ExampleCom: Hello, world <http://example.com/pr/1>",
+        );
+    }
+
+    #[test]
+    fn test_plain_text_pull_request() {
+        let mut pull_request = sample_pull_request();
+        pull_request.pull_request.title = "Fix\n/ban everyone".into();
+        assert_eq!(
+            pull_request.to_plain_text(&UsernameAliases::default()),
+            "[ExampleCom] Me created PR#1 (http://example.com/pr/1): Fix /ban everyone",
+        );
+    }
+
+    #[test]
+    fn test_pull_request_with_an_alias() {
+        let mut aliases = UsernameAliases::default();
+        aliases.insert("mE".into(), "Not me".into());
+        assert_eq!(
+            sample_pull_request().to_view(&aliases).to_string(),
+            concat!(
+                "[<a href='http:&#x2f;&#x2f;example.com&#x2f;'><font color=FF00FF>",
+                "ExampleCom</font></a>] <a href='https:&#x2f;&#x2f;github.com&#x2f;Me'><font ",
+                "color='909090'>Not me</font></a> created ",
+                "<a href='http:&#x2f;&#x2f;example.com&#x2f;pr&#x2f;1'>PR#1</a>: Hello, world",
+            ),
+        );
+    }
+
+    #[test]
+    fn test_pull_request_with_project_aliases() {
+        let mut global = UsernameAliases::default();
+        global.insert("me".into(), "Everyone's me".into());
+        let mut project = UsernameAliases::default();
+        project.insert("ME".into(), "Project's me".into());
+        let project = project.layered_over(&global);
+        let pull_request = sample_pull_request();
+        assert!(pull_request
+            .to_view(&global)
+            .to_string()
+            .contains(">Everyone&#x27;s me</font>"));
+        assert!(pull_request
+            .to_view(&project)
+            .to_string()
+            .contains(">Project&#x27;s me</font>"));
+    }
+
+    fn sample_review(state: &str) -> PullRequestReviewEvent<'static> {
+        let pull_request = sample_pull_request();
+        PullRequestReviewEvent {
+            action: "submitted".into(),
+            review: Review {
+                state: state.to_owned().into(),
+                html_url: "http://example.com/pr/1#pullrequestreview-1".into(),
+                body: None,
+            },
+            pull_request: pull_request.pull_request,
+            repository: pull_request.repository,
+            sender: pull_request.sender,
+        }
+    }
+
+    #[test]
+    fn test_pull_request_review() {
+        let mut aliases = UsernameAliases::default();
+        aliases.insert("me".into(), "Not me".into());
+        assert_eq!(
+            sample_review("approved").to_view(&aliases).to_string(),
+            concat!(
+                "[<a href='http:&#x2f;&#x2f;example.com&#x2f;'><font color=FF00FF>",
+                "ExampleCom</font></a>] <a href='https:&#x2f;&#x2f;github.com&#x2f;Me'><font ",
+                "color='909090'>Not me</font></a> approved <a href='http:&#x2f;&#x2f;",
+                "example.com&#x2f;pr&#x2f;1#pullrequestreview-1'>PR#1</a>: Hello, world",
+            ),
+        );
+        assert_eq!(
+            sample_review("changes_requested").to_plain_text(&aliases),
+            concat!(
+                "[ExampleCom] Not me requested changes on PR#1 ",
+                "(http://example.com/pr/1#pullrequestreview-1): Hello, world",
+            ),
+        );
+    }
+
+    #[test]
+    fn test_pull_request_review_is_announced() {
+        assert!(sample_review("approved").is_announced(false));
+        assert!(sample_review("changes_requested").is_announced(false));
+        let mut commented = sample_review("commented");
+        assert!(!commented.is_announced(true));
+        commented.review.body = Some(" \r\n".into());
+        assert!(!commented.is_announced(true));
+        commented.review.body = Some("Looks fine, but see the comments".into());
+        assert!(!commented.is_announced(false));
+        assert!(commented.is_announced(true));
+        let mut dismissed = sample_review("approved");
+        dismissed.action = "dismissed".into();
+        assert!(!dismissed.is_announced(true));
+    }
+
+    fn sample_review_comment(body: &str) -> PullRequestReviewCommentEvent<'static> {
+        let pull_request = sample_pull_request();
+        PullRequestReviewCommentEvent {
+            action: "created".into(),
+            comment: ReviewComment {
+                html_url: "http://example.com/pr/1#discussion_r2".into(),
+                body: body.to_owned().into(),
+                pull_request_review_id: Some(1),
+            },
+            pull_request: pull_request.pull_request,
+            repository: pull_request.repository,
+            sender: pull_request.sender,
+            merged: 0,
+        }
+    }
+
+    #[test]
+    fn test_review_comment() {
+        let aliases = UsernameAliases::default();
+        let mut comment = sample_review_comment("Use `species` *here*");
+        assert_eq!(
+            comment.to_view(&aliases).to_string(),
+            concat!(
+                "[<a href='http:&#x2f;&#x2f;example.com&#x2f;'><font color=FF00FF>",
+                "ExampleCom</font></a>] <a href='https:&#x2f;&#x2f;github.com&#x2f;Me'><font ",
+                "color='909090'>Me</font></a> commented on <a href='http:&#x2f;&#x2f;",
+                "example.com&#x2f;pr&#x2f;1#discussion_r2'>PR#1</a> (Hello, world): ",
+                "Use species here",
+            ),
+        );
+        comment.merge(sample_review_comment("Typo"));
+        comment.merge(sample_review_comment("Typo"));
+        assert_eq!(
+            comment.to_view(&aliases).to_string(),
+            concat!(
+                "[<a href='http:&#x2f;&#x2f;example.com&#x2f;'><font color=FF00FF>",
+                "ExampleCom</font></a>] <a href='https:&#x2f;&#x2f;github.com&#x2f;Me'><font ",
+                "color='909090'>Me</font></a> left 3 review comments on <a href='http:&#x2f;",
+                "&#x2f;example.com&#x2f;pr&#x2f;1#pullrequestreview-1'>PR#1</a> (Hello, world)",
+            ),
+        );
+    }
+
+    #[test]
+    fn test_review_comment_is_announced() {
+        let mut comment = sample_review_comment("Typo");
+        assert!(comment.is_announced());
+        comment.action = "edited".into();
+        assert!(!comment.is_announced());
+    }
+
+    fn sample_release() -> ReleaseEvent<'static> {
+        ReleaseEvent {
+            action: "published".into(),
+            release: Release {
+                tag_name: "v1.0".into(),
+                name: Some("**First** release".into()),
+                html_url: "http://example.com/releases/v1.0".into(),
+                body: Some("Many\nchanges".into()),
+                draft: false,
+                prerelease: false,
+                author: None,
+            },
+            repository: Repository {
+                name: "ExampleCom".into(),
+                full_name: "example/ExampleCom".into(),
+                html_url: "http://example.com/".into(),
+                default_branch: "master".into(),
+                forks_count: None,
+                stargazers_count: None,
+                display_name: None,
+                sha_length: DEFAULT_SHA_LENGTH,
+            },
+            sender: Sender {
+                login: "Me".into(),
+                html_url: "https://github.com/Me".into(),
             },
-            url: "http://example.com".into(),
         }
     }
 
-    #[tokio::test]
-    async fn test_push_event() {
-        let commit = concat!(
-            "[<a href='https:&#x2f;&#x2f;github.com&#x2f;smogon&#x2f;pokemon-showdown'>",
-            "<font color=FF00FF>server</font></a>] ",
-            "<a href='http:&#x2f;&#x2f;example.com'><font color=606060><kbd>0da259</kbd></font></a>\n",
-            "<span title='Hello, world!'>Hello, world!</span> ",
-            r#"<font color=909090 title="Konrad Borowski">(xfix)</font>"#,
+    #[test]
+    fn test_release() {
+        assert_eq!(
+            sample_release()
+                .to_view(&UsernameAliases::default())
+                .to_string(),
+            concat!(
+                "[<a href='http:&#x2f;&#x2f;example.com&#x2f;'><font color=FF00FF>",
+                "ExampleCom</font></a>] New release by ",
+                "<a href='https:&#x2f;&#x2f;github.com&#x2f;Me'><font color='909090'>Me</font></a>: ",
+                "<a href='http:&#x2f;&#x2f;example.com&#x2f;releases&#x2f;v1.0'>v1.0</a>",
+                " &mdash; &#x2a;&#x2a;First&#x2a;&#x2a; release<br>Many changes",
+            ),
+        );
+    }
+
+    #[test]
+    fn test_simple_release() {
+        assert_eq!(
+            sample_release()
+                .format_simple(&UsernameAliases::default())
+                .to_string(),
+            concat!(
+                "[<a href='http:&#x2f;&#x2f;example.com&#x2f;'><font color=FF00FF>",
+                "ExampleCom</font></a>] New release by ",
+                "<a href='https:&#x2f;&#x2f;github.com&#x2f;Me'><font color='909090'>Me</font></a>: ",
+                "<a href='http:&#x2f;&#x2f;example.com&#x2f;releases&#x2f;v1.0'>v1.0</a>",
+                " &mdash; &#x2a;&#x2a;First&#x2a;&#x2a; release",
+            ),
         );
+    }
+
+    #[test]
+    fn test_plain_text_release() {
         assert_eq!(
-            PushEvent {
-                git_ref: "refs/head/master".into(),
-                commits: vec![sample_commit(), sample_commit()],
-                pusher: Pusher {
-                    name: "Zarel".into(),
-                },
-                repository: Repository {
-                    name: "pokemon-showdown".into(),
-                    html_url: "https://github.com/smogon/pokemon-showdown".into(),
-                    default_branch: "master".into(),
-                }
-            }
+            sample_release().to_plain_text(&UsernameAliases::default()),
+            concat!(
+                "[ExampleCom] New release by Me: v1.0 \u{2014} *\u{200B}*First*\u{200B}* ",
+                "release (http://example.com/releases/v1.0)",
+            ),
+        );
+    }
+
+    #[test]
+    fn test_release_author() {
+        let mut release = sample_release();
+        release.release.author = Some(Sender {
+            login: "Zarel".into(),
+            html_url: "https://github.com/Zarel".into(),
+        });
+        let mut aliases = UsernameAliases::default();
+        aliases.insert("zarel".into(), "Zarel Ye".into());
+        assert!(release
+            .to_plain_text(&aliases)
+            .starts_with("[ExampleCom] New release by Zarel Ye: v1.0"));
+    }
+
+    #[tokio::test]
+    async fn test_display_name() {
+        let mut push_event = sample_push_event(1);
+        push_event.repository.display_name = Some("PS");
+        let view = push_event
             .to_view(PushEventContext {
                 github_api: None,
                 username_aliases: &UsernameAliases::default(),
+                timezone: Tz::UTC,
+                max_commits: 5,
+                max_commit_title_length: 100,
+                sha_length: 7,
             })
             .await
-            .to_string(),
-            format!("{0}<br>{0}", commit)
+            .to_string();
+        assert!(view.contains("<font color=FF00FF>PS</font>"), "{}", view);
+        assert!(push_event
+            .to_plain_text(&UsernameAliases::default())
+            .starts_with("[PS] "));
+        let mut pull_request = sample_pull_request();
+        pull_request.repository.display_name = Some("Example");
+        let view = pull_request
+            .to_view(&UsernameAliases::default())
+            .to_string();
+        assert!(
+            view.starts_with(concat!(
+                "[<a href='http:&#x2f;&#x2f;example.com&#x2f;'>",
+                "<font color=FF00FF>Example</font></a>]",
+            )),
+            "{}",
+            view,
         );
+        let mut release = sample_release();
+        release.repository.display_name = Some("Example");
+        assert!(release
+            .to_view(&UsernameAliases::default())
+            .to_string()
+            .contains("<font color=FF00FF>Example</font>"));
+        assert!(!sample_release()
+            .to_view(&UsernameAliases::default())
+            .to_string()
+            .contains("<font color=FF00FF>Example</font>"));
     }
 
-    #[tokio::test]
-    async fn test_commit() {
+    #[test]
+    fn test_release_body_is_truncated() {
+        let mut release = sample_release();
+        release.release.name = None;
+        release.release.body = Some("ab ".repeat(100).into());
+        let view = release.to_view(&UsernameAliases::default()).to_string();
+        assert!(
+            view.ends_with(&format!("</a><br>{}&hellip;", "ab ".repeat(67).trim_end())),
+            "{}",
+            view,
+        );
+    }
+
+    #[test]
+    fn test_prereleases_are_ignored() {
+        let mut release = sample_release();
+        assert!(release.is_announced(false));
+        release.release.prerelease = true;
+        assert!(!release.is_announced(false));
+        assert!(release.is_announced(true));
+        release.release.draft = true;
+        assert!(!release.is_announced(true));
+        release.release.draft = false;
+        release.action = "created".into();
+        assert!(!release.is_announced(true));
+    }
+
+    fn sample_workflow_run(event: &str, branch: &str) -> WorkflowRunEvent<'static> {
+        from_json(serde_json::json!({
+            "action": "completed",
+            "workflow_run": {
+                "name": "Node.js CI",
+                "head_branch": branch,
+                "head_sha": "0da2590a700d054fc2ce39ddc9c95f360329d9be",
+                "event": event,
+                "conclusion": "failure",
+                "html_url": "https://github.com/smogon/pokemon-showdown/actions/runs/1",
+                "head_commit": {"message": "Fix **Spikes**\n\nIt crashed."}
+            },
+            "repository": {
+                "name": "pokemon-showdown",
+                "full_name": "smogon/pokemon-showdown",
+                "html_url": "https://github.com/smogon/pokemon-showdown",
+                "default_branch": "master"
+            },
+            "sender": {"login": "Zarel", "html_url": "https://github.com/Zarel"}
+        }))
+    }
+
+    #[test]
+    fn test_workflow_run() {
+        let run = sample_workflow_run("push", "master");
+        assert!(run.is_tracked());
+        assert_eq!(run.conclusion(), "failure");
+        assert_eq!(run.key(), "smogon/pokemon-showdown Node.js CI master");
         assert_eq!(
-            sample_commit()
-                .to_view(
-                    "shouldn't be used",
-                    &mut PushEventContext {
-                        github_api: None,
-                        username_aliases: &UsernameAliases::default(),
-                    }
-                )
-                .await
-                .to_string(),
+            run.to_view(Transition::Failed, 100).to_string(),
             concat!(
-                "<a href='http:&#x2f;&#x2f;example.com'>",
-                "<font color=606060><kbd>0da259</kbd></font></a>\n",
-                "<span title='Hello, world!'>Hello, world!</span> ",
-                r#"<font color=909090 title="Konrad Borowski">(xfix)</font>"#,
+                "[<a href='https:&#x2f;&#x2f;github.com&#x2f;smogon&#x2f;pokemon-showdown'>",
+                "<font color=FF00FF>server</font></a>] <a href='https:&#x2f;&#x2f;github.com",
+                "&#x2f;smogon&#x2f;pokemon-showdown&#x2f;actions&#x2f;runs&#x2f;1'>Node.js CI</a>",
+                " failed on master: <font color=606060><kbd>0da2590</kbd></font> ",
+                "Fix &#x2a;&#x2a;Spikes&#x2a;&#x2a;",
+            ),
+        );
+        assert_eq!(
+            run.to_plain_text(Transition::Recovered, 3),
+            concat!(
+                "[server] Node.js CI passed again on master ",
+                "(https://github.com/smogon/pokemon-showdown/actions/runs/1): 0da2590 Fix\u{2026}",
             ),
         );
     }
 
-    fn sample_pull_request() -> PullRequestEvent<'static> {
-        PullRequestEvent {
-            action: "created".into(),
-            pull_request: PullRequest {
-                number: 1,
-                html_url: "http://example.com/pr/1".into(),
-                title: "Hello, world".into(),
+    #[test]
+    fn test_untracked_workflow_runs() {
+        assert!(!sample_workflow_run("pull_request", "master").is_tracked());
+        assert!(!sample_workflow_run("push", "feature").is_tracked());
+        let mut run = sample_workflow_run("push", "master");
+        run.action = "requested".into();
+        assert!(!run.is_tracked());
+    }
+
+    fn sample_issue() -> IssueEvent<'static> {
+        IssueEvent {
+            action: "opened".into(),
+            issue: Issue {
+                number: 123,
+                html_url: "http://example.com/issues/123".into(),
+                title: "It's **broken**".into(),
+                state_reason: None,
             },
             repository: Repository {
                 name: "ExampleCom".into(),
+                full_name: "example/ExampleCom".into(),
                 html_url: "http://example.com/".into(),
                 default_branch: "master".into(),
+                forks_count: None,
+                stargazers_count: None,
+                display_name: None,
+                sha_length: DEFAULT_SHA_LENGTH,
+            },
+            sender: Sender {
+                login: "Me".into(),
+                html_url: "https://github.com/Me".into(),
             },
-            sender: Sender { login: "Me".into() },
         }
     }
 
     #[test]
-    fn test_pull_request() {
+    fn test_issue() {
+        let mut aliases = UsernameAliases::default();
+        aliases.insert("me".into(), "Not me".into());
         assert_eq!(
-            sample_pull_request()
-                .to_view(&UsernameAliases::default())
-                .to_string(),
+            sample_issue().to_view(&aliases).to_string(),
             concat!(
                 "[<a href='http:&#x2f;&#x2f;example.com&#x2f;'><font color=FF00FF>",
-                "ExampleCom</font></a>] <a href='https://github.com/Me'><font ",
-                "color='909090'>Me</font></a> created ",
-                "<a href='http:&#x2f;&#x2f;example.com&#x2f;pr&#x2f;1'>PR#1</a>: Hello, world",
+                "ExampleCom</font></a>] ",
+                "<a href='https:&#x2f;&#x2f;github.com&#x2f;Me'><font color='909090'>Not me</font></a> ",
+                "opened <a href='http:&#x2f;&#x2f;example.com&#x2f;issues&#x2f;123'>issue #123</a>: ",
+                "It&#x27;s &#x2a;&#x2a;broken&#x2a;&#x2a;",
             ),
         );
     }
 
     #[test]
-    fn test_pull_request_with_an_alias() {
-        let mut aliases = UsernameAliases::default();
-        aliases.insert("mE".into(), "Not me".into());
+    fn test_plain_text_issue() {
         assert_eq!(
-            sample_pull_request().to_view(&aliases).to_string(),
+            sample_issue().to_plain_text(&UsernameAliases::default()),
             concat!(
-                "[<a href='http:&#x2f;&#x2f;example.com&#x2f;'><font color=FF00FF>",
-                "ExampleCom</font></a>] <a href='https://github.com/Me'><font ",
-                "color='909090'>Not me</font></a> created ",
-                "<a href='http:&#x2f;&#x2f;example.com&#x2f;pr&#x2f;1'>PR#1</a>: Hello, world",
+                "[ExampleCom] Me opened issue #123 (http://example.com/issues/123): ",
+                "It's *\u{200B}*broken*\u{200B}*",
+            ),
+        );
+    }
+
+    /// Parses a payload captured from GitHub, kept in `testdata`.
+    fn fixture<T: Deserialize<'static>>(name: &str) -> T {
+        let path = format!("{}/testdata/{}.json", env!("CARGO_MANIFEST_DIR"), name);
+        let payload = std::fs::read_to_string(path).unwrap();
+        serde_json::from_str(Box::leak(payload.into_boxed_str())).unwrap()
+    }
+
+    /// Parses a payload built with `json!`, leaking it like `fixture`
+    /// does, as events borrow from their payload.
+    fn from_json<T: Deserialize<'static>>(value: serde_json::Value) -> T {
+        serde_json::from_str(Box::leak(value.to_string().into_boxed_str())).unwrap()
+    }
+
+    #[test]
+    fn test_issue_fixtures() {
+        let aliases = UsernameAliases::default();
+        let opened: IssueEvent = fixture("issues_opened");
+        assert!(opened.is_announced());
+        assert_eq!(
+            opened.to_plain_text(&aliases),
+            concat!(
+                "[server] Zarel opened issue #8453 ",
+                "(https://github.com/smogon/pokemon-showdown/issues/8453): ",
+                "Crash when switching into Spikes",
+            ),
+        );
+        let closed: IssueEvent = fixture("issues_closed");
+        assert!(closed.is_announced());
+        assert!(closed
+            .to_view(&aliases)
+            .to_string()
+            .contains(">issue #8452</a> as not planned: "));
+        let labeled: IssueEvent = fixture("issues_labeled");
+        assert!(!labeled.is_announced());
+    }
+
+    #[test]
+    fn test_pull_request_label_fixture() {
+        let aliases = UsernameAliases::default();
+        let labeled: PullRequestEvent = fixture("pull_request_labeled");
+        assert!(labeled.changes_label(&["needs review".into(), "blocked".into()]));
+        assert!(!labeled.changes_label(&["blocked".into()]));
+        assert_eq!(
+            labeled.label_to_plain_text(&aliases),
+            concat!(
+                "[server] Zarel added label Needs Review to PR#8460 ",
+                "(https://github.com/smogon/pokemon-showdown/pull/8460): ",
+                "Fix Spikes crash on switch",
+            ),
+        );
+        let view = labeled.label_to_view(&aliases).to_string();
+        assert!(
+            view.contains(concat!(
+                "added <span style='background: #fbca04; color: #000000; ",
+                "border-radius: 2em; padding: 0 7px'>Needs Review</span> to ",
+                "<a href='https:&#x2f;&#x2f;github.com&#x2f;smogon&#x2f;pokemon-showdown&#x2f;",
+                "pull&#x2f;8460'>PR#8460</a>: Fix Spikes crash on switch",
+            )),
+            "{}",
+            view,
+        );
+        assert!(!sample_pull_request().changes_label(&["needs review".into()]));
+    }
+
+    #[test]
+    fn test_pull_request_label_escaping() {
+        let mut pull_request = sample_pull_request();
+        pull_request.action = "unlabeled".into();
+        pull_request.label = Some(Label {
+            name: "<b>blocked</b> & co".into(),
+            color: "'><script>".into(),
+        });
+        let view = pull_request
+            .label_to_view(&UsernameAliases::default())
+            .to_string();
+        assert!(
+            view.contains(concat!(
+                "removed <span style='background: #ededed; color: #000000; ",
+                "border-radius: 2em; padding: 0 7px'>&lt;b&gt;blocked&lt;/b&gt; &amp; co</span> from",
+            )),
+            "{}",
+            view,
+        );
+        assert!(!view.contains("<script>"), "{}", view);
+        pull_request.label = Some(Label {
+            name: "breaking-change".into(),
+            color: "b60205".into(),
+        });
+        assert!(pull_request
+            .label_to_view(&UsernameAliases::default())
+            .to_string()
+            .contains("background: #b60205; color: #ffffff;"));
+    }
+
+    #[test]
+    fn test_pull_request_review_fixtures() {
+        let aliases = UsernameAliases::default();
+        let approved: PullRequestReviewEvent = fixture("pull_request_review_approved");
+        assert!(approved.is_announced(false));
+        assert_eq!(
+            approved.to_plain_text(&aliases),
+            concat!(
+                "[server] Zarel approved PR#8460 ",
+                "(https://github.com/smogon/pokemon-showdown/pull/8460",
+                "#pullrequestreview-902113351): ",
+                "Fix Spikes crash on switch",
+            ),
+        );
+        let changes_requested: PullRequestReviewEvent =
+            fixture("pull_request_review_changes_requested");
+        assert!(changes_requested.is_announced(false));
+        assert!(changes_requested
+            .to_view(&aliases)
+            .to_string()
+            .contains(">Marty-D</font></a> requested changes on <a "));
+        let dismissed: PullRequestReviewEvent = fixture("pull_request_review_dismissed");
+        assert!(!dismissed.is_announced(true));
+    }
+
+    #[test]
+    fn test_check_fixtures() {
+        let mut status: StatusEvent = fixture("status_failure");
+        let check = status.check().unwrap();
+        assert_eq!(check.state, CheckState::Failure);
+        assert_eq!(
+            check.to_plain_text(),
+            concat!(
+                "[server] continuous-integration/sim failed on master ",
+                "(https://ci.example.com/smogon/pokemon-showdown/builds/4711): 8c3b6f0",
+            ),
+        );
+        status.branches.clear();
+        assert!(status.check().is_none());
+        let suite: CheckSuiteEvent = fixture("check_suite_completed");
+        assert!(suite.is_from_actions());
+        let check = suite.check().unwrap();
+        assert_eq!(check.state, CheckState::Failure);
+        assert!(check.to_view().to_string().contains(concat!(
+            "<a href='https:&#x2f;&#x2f;github.com&#x2f;smogon&#x2f;pokemon-showdown&#x2f;commit&#x2f;",
+            "8c3b6f0d2e4a9f1b7c5d3e2a1f0b9c8d7e6f5a4b&#x2f;checks?check_suite_id=5513260193'>",
+            "GitHub Actions</a> failed on master",
+        )));
+    }
+
+    #[test]
+    fn test_check_templates() {
+        let status: StatusEvent = fixture("status_failure");
+        let template = MessageTemplate::parse(
+            "{context} broke {branch} in {repo} at {sha}",
+            placeholders("status"),
+        )
+        .unwrap();
+        assert_eq!(
+            status
+                .check()
+                .unwrap()
+                .format_template(&template, Format::PlainText),
+            "continuous-integration/sim broke master in server at 8c3b6f0",
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sha_length() {
+        let mut status: StatusEvent = fixture("status_failure");
+        status.repository.sha_length = 10;
+        assert!(status
+            .check()
+            .unwrap()
+            .to_plain_text()
+            .ends_with(": 8c3b6f0d2e"));
+        let view = sample_commit()
+            .to_view(
+                "http://example.com",
+                &mut PushEventContext {
+                    github_api: None,
+                    username_aliases: &UsernameAliases::default(),
+                    timezone: Tz::UTC,
+                    max_commits: 5,
+                    max_commit_title_length: 100,
+                    sha_length: 12,
+                },
+            )
+            .await
+            .to_string();
+        assert!(view.contains("<kbd>0da2590a700d</kbd>"), "{}", view);
+    }
+
+    #[test]
+    fn test_create_fixtures() {
+        let aliases = UsernameAliases::default();
+        let branch: CreateEvent = fixture("create_branch");
+        assert!(!branch.is_tag());
+        assert_eq!(branch.name(), "release-1.2");
+        assert_eq!(
+            branch.to_plain_text(&aliases),
+            concat!(
+                "[server] Zarel created branch release-1.2 ",
+                "(https://github.com/smogon/pokemon-showdown/tree/release-1.2)",
+            ),
+        );
+        let tag: CreateEvent = fixture("create_tag");
+        assert!(tag.is_tag());
+        assert!(tag.to_view(&aliases).to_string().contains(concat!(
+            "</font></a> created tag <a href='https:&#x2f;&#x2f;github.com&#x2f;smogon&#x2f;",
+            "pokemon-showdown&#x2f;releases&#x2f;tag&#x2f;v1.2.0'>v1.2.0</a>",
+        )));
+    }
+
+    #[test]
+    fn test_delete_fixtures() {
+        let aliases = UsernameAliases::default();
+        let branch: DeleteEvent = fixture("delete_branch");
+        assert!(!branch.is_tag());
+        assert_eq!(branch.name(), "old-feature");
+        assert_eq!(
+            branch.to_plain_text(&aliases, None),
+            "[server] Zarel deleted branch old-feature",
+        );
+        assert_eq!(
+            branch.to_plain_text(&aliases, Some("\u{26a0}")),
+            "[server] \u{26a0} Zarel deleted branch old-feature",
+        );
+        assert!(branch
+            .to_view(&aliases, Some("\u{26a0}"))
+            .to_string()
+            .contains(" <b>\u{26a0} <a href='https:&#x2f;&#x2f;github.com&#x2f;Zarel'>"));
+        let tag: DeleteEvent = fixture("delete_tag");
+        assert!(tag.is_tag());
+        assert!(tag
+            .to_view(&aliases, None)
+            .to_string()
+            .ends_with("</font></a> deleted tag v0.1.0-rc1"));
+    }
+
+    #[test]
+    fn test_fork_fixture() {
+        let aliases = UsernameAliases::default();
+        let mut fork: ForkEvent = fixture("fork");
+        assert_eq!(fork.owner(), "Marty-D");
+        assert_eq!(
+            fork.to_plain_text(&aliases),
+            concat!(
+                "[server] Marty-D forked the repository ",
+                "(https://github.com/Marty-D/pokemon-showdown), now 4321 forks",
+            ),
+        );
+        let mut later: ForkEvent = fixture("fork");
+        later.repository.forks_count = Some(4323);
+        fork.merge(later);
+        let mut later: ForkEvent = fixture("fork");
+        later.merged = 1;
+        later.repository.forks_count = Some(4325);
+        fork.merge(later);
+        let view = fork.to_view(&aliases).to_string();
+        assert!(
+            view.ends_with("</font></a> and 3 more forked the repository, now 4325 forks"),
+            "{}",
+            view,
+        );
+    }
+
+    #[test]
+    fn test_star_fixture() {
+        let aliases = UsernameAliases::default();
+        let star: StarEvent = fixture("star");
+        assert_eq!(star.action, "created");
+        assert_eq!(star.stars(), Some(5000));
+        assert_eq!(
+            star.to_plain_text(&aliases, 5000),
+            concat!(
+                "[server] reached 5,000 stars with a star from Marty-D ",
+                "(https://github.com/smogon/pokemon-showdown/stargazers)",
+            ),
+        );
+        let view = star.to_view(&aliases, 5000).to_string();
+        assert!(
+            view.contains("reached <b>5,000</b> <a href='https:&#x2f;&#x2f;github.com"),
+            "{}",
+            view,
+        );
+        assert!(
+            view.contains("&#x2f;stargazers'>stars</a> with a star from "),
+            "{}",
+            view,
+        );
+    }
+
+    #[test]
+    fn test_with_separators() {
+        assert_eq!(with_separators(0), "0");
+        assert_eq!(with_separators(999), "999");
+        assert_eq!(with_separators(1000), "1,000");
+        assert_eq!(with_separators(2500), "2,500");
+        assert_eq!(with_separators(1234567), "1,234,567");
+    }
+
+    #[test]
+    fn test_gollum_fixture() {
+        let aliases = UsernameAliases::default();
+        let mut gollum: GollumEvent = fixture("gollum");
+        assert_eq!(gollum.sender(), "Marty-D");
+        assert_eq!(
+            gollum.to_plain_text(&aliases),
+            concat!(
+                "[server] Marty-D updated the wiki: created Rules & Guidelines ",
+                "(https://github.com/smogon/pokemon-showdown/wiki/Rules-&-Guidelines/",
+                "2f5b9b0e7d8d33a6e1c0a4b1c8f6d6a8d2b0c6f1), edited Home ",
+                "(https://github.com/smogon/pokemon-showdown/wiki/Home/",
+                "8c4f2b1a6d3e5f7a9b0c1d2e3f4a5b6c7d8e9f0a)",
+            ),
+        );
+        let view = gollum.to_view(&aliases).to_string();
+        assert!(view.contains(">Rules &amp; Guidelines</a>"), "{}", view);
+        assert!(!view.contains("more page"), "{}", view);
+        let simple = gollum.format_simple(&aliases).to_string();
+        assert!(simple.ends_with(">2 wiki pages</a>"), "{}", simple);
+        let page: WikiPage = serde_json::from_str(
+            r#"{
+                "title": "Glossary",
+                "action": "edited",
+                "sha": "0d4a6c3f1e2b5a7d9c8e6f4a2b1c3d5e7f9a0b2c",
+                "html_url": "https://github.com/smogon/pokemon-showdown/wiki/Glossary"
+            }"#,
+        )
+        .unwrap();
+        for _ in 0..5 {
+            gollum.pages.push(page.clone());
+        }
+        let view = gollum.to_view(&aliases).to_string();
+        assert!(view.ends_with("and 4 more pages</a>"), "{}", view);
+        assert!(gollum
+            .to_plain_text(&aliases)
+            .ends_with(" and 4 more pages"));
+    }
+
+    #[test]
+    fn test_milestone_fixtures() {
+        let aliases = UsernameAliases::default();
+        let created: MilestoneEvent = fixture("milestone_created");
+        assert!(created.is_announced());
+        assert_eq!(
+            created.to_plain_text(&aliases),
+            concat!(
+                "[server] Zarel created milestone v1.3, due 2021-04-01 ",
+                "(https://github.com/smogon/pokemon-showdown/milestone/7)",
+            ),
+        );
+        let closed: MilestoneEvent = fixture("milestone_closed");
+        assert_eq!(
+            closed.to_plain_text(&aliases),
+            concat!(
+                "[server] Milestone v1.3 closed \u{2014} 42 issues completed ",
+                "(https://github.com/smogon/pokemon-showdown/milestone/7)",
+            ),
+        );
+        let view = closed.to_view(&aliases).to_string();
+        assert!(
+            view.ends_with(">v1.3</a> closed \u{2014} 42 issues completed"),
+            "{}",
+            view,
+        );
+        let deleted: MilestoneEvent = fixture("milestone_deleted");
+        assert_eq!(
+            deleted.to_plain_text(&aliases),
+            "[server] Zarel deleted milestone v1.3, 3 open and 5 closed issues",
+        );
+    }
+
+    #[test]
+    fn test_milestone_merge() {
+        let mut created: MilestoneEvent = fixture("milestone_created");
+        let mut edited: MilestoneEvent = fixture("milestone_created");
+        edited.action = "edited".into();
+        edited.milestone.title = "v1.3.0".into();
+        created.merge(edited);
+        assert_eq!(created.action, "created");
+        assert_eq!(created.milestone.title, "v1.3.0");
+        let mut closed: MilestoneEvent = fixture("milestone_closed");
+        let mut reopened: MilestoneEvent = fixture("milestone_closed");
+        reopened.action = "opened".into();
+        closed.merge(reopened);
+        assert!(!closed.is_announced());
+        closed.merge(fixture("milestone_closed"));
+        assert!(closed.is_announced());
+    }
+
+    #[test]
+    fn test_commit_comment_fixture() {
+        let aliases = UsernameAliases::default();
+        let comment: CommitCommentEvent = fixture("commit_comment");
+        assert!(comment.is_announced());
+        assert_eq!(comment.sender(), "Marty-D");
+        assert_eq!(
+            comment.to_plain_text(&aliases),
+            concat!(
+                "[server] Marty-D commented on 0da2590: This breaks Z-Moves & Max Moves badly\u{2026} ",
+                "https://github.com/smogon/pokemon-showdown/commit/",
+                "0da2590a700d054fc2ce39ddc9c95f360329d9be#commitcomment-51426532",
+            ),
+        );
+        let view = comment.to_view(&aliases).to_string();
+        assert!(
+            view.ends_with(
+                "<kbd>0da2590</kbd></a>: This breaks Z-Moves &amp; Max Moves badly&hellip;"
+            ),
+            "{}",
+            view,
+        );
+        let simple = comment.format_simple(&aliases).to_string();
+        assert!(simple.ends_with("<kbd>0da2590</kbd></a>"), "{}", simple);
+    }
+
+    #[test]
+    fn test_deployment_status_fixture() {
+        let aliases = UsernameAliases::default();
+        let mut deployment: DeploymentStatusEvent = fixture("deployment_status");
+        assert!(deployment.is_finished());
+        assert_eq!(deployment.deployer(), "Marty-D");
+        assert_eq!(deployment.environment(), "production");
+        assert_eq!(
+            deployment.to_plain_text(&aliases),
+            concat!(
+                "[server] Deployment to production ",
+                "(https://github.com/smogon/pokemon-showdown/actions/runs/4012345678) ",
+                "succeeded: master 0da2590 by Marty-D",
+            ),
+        );
+        let view = deployment.to_view(&aliases).to_string();
+        assert!(
+            view.contains(concat!(
+                "Deployment to <a href='https:&#x2f;&#x2f;github.com&#x2f;smogon&#x2f;",
+                "pokemon-showdown&#x2f;actions&#x2f;runs&#x2f;4012345678'>production</a> ",
+                "succeeded: master <font color=606060><kbd>0da2590</kbd></font> by ",
+            )),
+            "{}",
+            view,
+        );
+        deployment.deployment_status.state = "failure".into();
+        deployment.deployment_status.target_url = Some("".into());
+        assert!(deployment
+            .to_plain_text(&aliases)
+            .starts_with("[server] Deployment to production failed: "));
+        deployment.deployment_status.state = "in_progress".into();
+        assert!(!deployment.is_finished());
+    }
+
+    #[test]
+    fn test_repository_fixture() {
+        let aliases = UsernameAliases::default();
+        let mut event: RepositoryEvent = fixture("repository_renamed");
+        assert!(event.is_announced());
+        assert_eq!(
+            event.previous_name().as_deref(),
+            Some("smogon/Pokemon-Showdown"),
+        );
+        assert_eq!(
+            event.to_plain_text(&aliases),
+            concat!(
+                "[server] Marty-D renamed the repository from smogon/Pokemon-Showdown ",
+                "to smogon/pokemon-showdown (https://github.com/smogon/pokemon-showdown)",
+            ),
+        );
+        let view = event.to_view(&aliases).to_string();
+        assert!(
+            view.ends_with(concat!(
+                "renamed the repository from smogon/Pokemon-Showdown to ",
+                "<a href='https:&#x2f;&#x2f;github.com&#x2f;smogon&#x2f;pokemon-showdown'>",
+                "smogon/pokemon-showdown</a>",
+            )),
+            "{}",
+            view,
+        );
+        event.action = "transferred".into();
+        event.changes =
+            serde_json::from_str(r#"{"owner": {"from": {"user": {"login": "Zarel"}}}}"#).unwrap();
+        assert_eq!(
+            event.previous_name().as_deref(),
+            Some("Zarel/pokemon-showdown"),
+        );
+        event.action = "archived".into();
+        event.changes = None;
+        assert_eq!(event.previous_name(), None);
+        assert_eq!(
+            event.to_plain_text(&aliases),
+            "[server] Marty-D archived the repository (https://github.com/smogon/pokemon-showdown)",
+        );
+        event.action = "edited".into();
+        assert!(!event.is_announced());
+    }
+
+    #[test]
+    fn test_issue_close_reasons() {
+        let aliases = UsernameAliases::default();
+        let mut issue = sample_issue();
+        issue.action = "closed".into();
+        assert!(issue
+            .to_plain_text(&aliases)
+            .contains(" Me closed issue #123 "));
+        issue.issue.state_reason = Some("completed".into());
+        assert!(issue
+            .to_plain_text(&aliases)
+            .contains(" Me closed issue #123 as completed "));
+        issue.action = "reopened".into();
+        assert!(issue
+            .to_plain_text(&aliases)
+            .contains(" Me reopened issue #123 "));
+    }
+    fn sample_issue_comment(body: &str, pull_request: bool) -> IssueCommentEvent<'static> {
+        let mut issue = serde_json::json!({
+            "number": 1,
+            "title": "Crash",
+            "state": "open",
+            "html_url": "https://github.com/a/b/issues/1"
+        });
+        if pull_request {
+            issue["pull_request"] =
+                serde_json::json!({"url": "https://api.github.com/a/b/pulls/1"});
+        }
+        from_json(serde_json::json!({
+            "action": "created",
+            "issue": issue,
+            "comment": {
+                "html_url": "https://github.com/a/b/issues/1#issuecomment-2",
+                "body": body
+            },
+            "repository": {
+                "name": "b",
+                "full_name": "a/b",
+                "html_url": "https://github.com/a/b"
+            },
+            "sender": {"login": "Zarel", "html_url": "https://github.com/Zarel"}
+        }))
+    }
+
+    #[test]
+    fn test_issue_comment() {
+        let comment = sample_issue_comment(
+            "> Does it crash?\r\n\r\nYes, **with** <b>Spikes</b> & [a replay](https://example.com).",
+            false,
+        );
+        assert_eq!(
+            comment.to_view(&UsernameAliases::default()).to_string(),
+            concat!(
+                "[<a href='https:&#x2f;&#x2f;github.com&#x2f;a&#x2f;b'><font color=FF00FF>b</font></a>] ",
+                "<a href='https:&#x2f;&#x2f;github.com&#x2f;Zarel'><font color='909090'>Zarel</font></a> ",
+                "commented on <a href='https:&#x2f;&#x2f;github.com&#x2f;a&#x2f;b&#x2f;issues&#x2f;1",
+                "#issuecomment-2'>issue #1</a> (Crash): Yes, with Spikes &amp; a replay.",
+            ),
+        );
+        assert!(comment
+            .format_simple(&UsernameAliases::default())
+            .to_string()
+            .ends_with("#issuecomment-2'>issue #1</a> (Crash)"));
+    }
+
+    #[test]
+    fn test_long_pull_request_comment() {
+        let comment = sample_issue_comment(&"Looks good. ".repeat(20), true);
+        assert_eq!(
+            comment.to_plain_text(&UsernameAliases::default()),
+            format!(
+                "[b] Zarel commented on PR#1 (Crash): {}Look\u{2026} \
+                 https://github.com/a/b/issues/1#issuecomment-2",
+                "Looks good. ".repeat(8),
             ),
         );
     }
+
+    #[test]
+    fn test_issue_comment_is_announced() {
+        let mut comment = sample_issue_comment("Thanks", false);
+        assert!(comment.is_announced(false));
+        comment.issue.state = "closed".into();
+        assert!(!comment.is_announced(false));
+        assert!(comment.is_announced(true));
+        comment.action = "edited".into();
+        assert!(!comment.is_announced(true));
+    }
+
+    #[test]
+    fn test_strip_markdown() {
+        assert_eq!(
+            strip_markdown(concat!(
+                "# Title\n\nSee ![screenshot](a.png) and `code`:\n",
+                "```rust\nfn main() {}\n```\nDone ~~not~~",
+            )),
+            "Title See screenshot and code: Done not",
+        );
+        assert_eq!(strip_markdown("snake_case stays"), "snake_case stays");
+    }
 }
@@ -0,0 +1,297 @@
+//! GitLab webhook payloads, converted to their GitHub counterparts so
+//! that they can be routed and announced the same way.
+
+use super::{Author, Commit, PullRequest, PullRequestEvent, PushEvent, Pusher, Repository, Sender};
+use crate::config::DEFAULT_SHA_LENGTH;
+use chrono::{DateTime, FixedOffset};
+use serde::Deserialize;
+use std::borrow::Cow;
+
+#[derive(Deserialize)]
+pub struct InitialPayload<'a> {
+    #[serde(borrow)]
+    pub project: InitialProject<'a>,
+}
+
+#[derive(Deserialize)]
+pub struct InitialProject<'a> {
+    #[serde(borrow)]
+    pub path_with_namespace: Cow<'a, str>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Project<'a> {
+    #[serde(borrow)]
+    name: Cow<'a, str>,
+    #[serde(borrow)]
+    path_with_namespace: Cow<'a, str>,
+    #[serde(borrow)]
+    web_url: Cow<'a, str>,
+    #[serde(borrow)]
+    default_branch: Cow<'a, str>,
+}
+
+impl<'a> Project<'a> {
+    /// Guesses the URL of a user profile, as GitLab doesn't provide it.
+    fn user_url(&self, username: &str) -> String {
+        let root = self
+            .web_url
+            .strip_suffix(&*self.path_with_namespace)
+            .unwrap_or("https://gitlab.com/");
+        format!("{}{}", root, username)
+    }
+
+    fn into_repository(self) -> Repository<'a> {
+        Repository {
+            name: self.name,
+            full_name: self.path_with_namespace,
+            html_url: self.web_url,
+            default_branch: self.default_branch,
+            forks_count: None,
+            stargazers_count: None,
+            display_name: None,
+            sha_length: DEFAULT_SHA_LENGTH,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PushHook<'a> {
+    #[serde(borrow, rename = "ref")]
+    git_ref: Cow<'a, str>,
+    #[serde(borrow)]
+    before: Cow<'a, str>,
+    #[serde(borrow)]
+    after: Cow<'a, str>,
+    #[serde(borrow)]
+    user_username: Cow<'a, str>,
+    #[serde(borrow)]
+    project: Project<'a>,
+    #[serde(borrow)]
+    commits: Vec<PushHookCommit<'a>>,
+}
+
+impl<'a> From<PushHook<'a>> for PushEvent<'a> {
+    fn from(push: PushHook<'a>) -> Self {
+        PushEvent {
+            git_ref: push.git_ref,
+            before: push.before,
+            after: push.after,
+            commits: push.commits.into_iter().map(Commit::from).collect(),
+            pusher: Pusher {
+                name: push.user_username,
+            },
+            repository: push.project.into_repository(),
+            forced: false,
+            deleted: false,
+            pull_request: None,
+            tag_message: None,
+            head_commit: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PushHookCommit<'a> {
+    #[serde(borrow)]
+    id: Cow<'a, str>,
+    #[serde(borrow)]
+    message: Cow<'a, str>,
+    #[serde(borrow)]
+    url: Cow<'a, str>,
+    #[serde(default)]
+    timestamp: Option<DateTime<FixedOffset>>,
+    #[serde(borrow)]
+    author: CommitAuthor<'a>,
+    #[serde(borrow, default)]
+    added: Vec<Cow<'a, str>>,
+    #[serde(borrow, default)]
+    removed: Vec<Cow<'a, str>>,
+    #[serde(borrow, default)]
+    modified: Vec<Cow<'a, str>>,
+}
+
+impl<'a> From<PushHookCommit<'a>> for Commit<'a> {
+    fn from(commit: PushHookCommit<'a>) -> Self {
+        Commit {
+            id: commit.id,
+            message: commit.message,
+            // GitLab only provides author e-mails, not usernames.
+            author: Author {
+                name: commit.author.name,
+                username: None,
+            },
+            url: commit.url,
+            timestamp: commit.timestamp,
+            added: commit.added,
+            removed: commit.removed,
+            modified: commit.modified,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CommitAuthor<'a> {
+    #[serde(borrow)]
+    name: Cow<'a, str>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MergeRequestHook<'a> {
+    #[serde(borrow)]
+    user: User<'a>,
+    #[serde(borrow)]
+    project: Project<'a>,
+    #[serde(borrow)]
+    object_attributes: MergeRequest<'a>,
+}
+
+impl<'a> From<MergeRequestHook<'a>> for PullRequestEvent<'a> {
+    fn from(merge_request: MergeRequestHook<'a>) -> Self {
+        let attributes = merge_request.object_attributes;
+        let action: Cow<'a, str> = match attributes.action.as_deref().unwrap_or("update") {
+            "open" => "opened".into(),
+            "close" => "closed".into(),
+            "reopen" => "reopened".into(),
+            "merge" => "merged".into(),
+            "update" => "synchronize".into(),
+            action => action.to_owned().into(),
+        };
+        PullRequestEvent {
+            pull_request: PullRequest {
+                number: attributes.iid,
+                html_url: attributes.url,
+                title: attributes.title,
+                merged: action == "merged",
+                // Checks are only followed on GitHub.
+                merge_commit_sha: None,
+            },
+            action,
+            sender: Sender {
+                html_url: merge_request
+                    .project
+                    .user_url(&merge_request.user.username)
+                    .into(),
+                login: merge_request.user.username,
+            },
+            repository: merge_request.project.into_repository(),
+            label: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct User<'a> {
+    #[serde(borrow)]
+    username: Cow<'a, str>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MergeRequest<'a> {
+    iid: u32,
+    #[serde(borrow)]
+    title: Cow<'a, str>,
+    #[serde(borrow)]
+    url: Cow<'a, str>,
+    #[serde(borrow)]
+    action: Option<Cow<'a, str>>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MergeRequestHook, PushHook};
+    use crate::config::UsernameAliases;
+    use crate::webhook::schema::{PullRequestEvent, PushEvent, PushEventContext};
+    use chrono_tz::Tz;
+
+    const PROJECT: &str = r#"{
+        "name": "Diaspora",
+        "path_with_namespace": "mike/diaspora",
+        "web_url": "http://example.com/mike/diaspora",
+        "default_branch": "master"
+    }"#;
+
+    #[tokio::test]
+    async fn test_push_hook() {
+        let json = format!(
+            r#"{{
+                "object_kind": "push",
+                "ref": "refs/heads/master",
+                "before": "1111111111111111111111111111111111111111",
+                "after": "2222222222222222222222222222222222222222",
+                "user_username": "jsmith",
+                "project": {},
+                "commits": [{{
+                    "id": "b6568db1bc1dcd7f8b4d5a946b0b91f9dacd7327",
+                    "message": "Update Catalan translation\n\nMore details",
+                    "timestamp": "2011-12-12T14:27:31+02:00",
+                    "url": "http://example.com/mike/diaspora/commit/b6568db1",
+                    "author": {{"name": "Jordi Mallach", "email": "jordi@softcatala.org"}},
+                    "added": ["CHANGELOG"],
+                    "modified": ["app/controller/application.rb"],
+                    "removed": []
+                }}],
+                "total_commits_count": 1
+            }}"#,
+            PROJECT,
+        );
+        let push_hook: PushHook = serde_json::from_str(&json).unwrap();
+        let push_event = PushEvent::from(push_hook);
+        assert_eq!(push_event.branch(), "master");
+        assert_eq!(push_event.repository.full_name, "mike/diaspora");
+        let files: Vec<_> = push_event.changed_files().collect();
+        assert_eq!(files, ["CHANGELOG", "app/controller/application.rb"]);
+        let view = push_event
+            .to_view(PushEventContext {
+                github_api: None,
+                username_aliases: &UsernameAliases::default(),
+                timezone: Tz::UTC,
+                max_commits: 5,
+                max_commit_title_length: 100,
+                sha_length: 7,
+            })
+            .await
+            .to_string();
+        assert!(
+            view.ends_with(concat!(
+                ">\nUpdate Catalan translation ",
+                "<font color=909090>(Jordi Mallach)</font>",
+            )),
+            "{}",
+            view,
+        );
+    }
+
+    #[test]
+    fn test_merge_request_hook() {
+        let json = format!(
+            r#"{{
+                "object_kind": "merge_request",
+                "user": {{"name": "Administrator", "username": "root"}},
+                "project": {},
+                "object_attributes": {{
+                    "iid": 1,
+                    "title": "MS-Viewport",
+                    "url": "http://example.com/mike/diaspora/merge_requests/1",
+                    "action": "open"
+                }}
+            }}"#,
+            PROJECT,
+        );
+        let merge_request: MergeRequestHook = serde_json::from_str(&json).unwrap();
+        let pull_request = PullRequestEvent::from(merge_request);
+        assert_eq!(pull_request.action, "opened");
+        assert_eq!(
+            pull_request
+                .to_view(&UsernameAliases::default())
+                .to_string(),
+            concat!(
+                "[<a href='http:&#x2f;&#x2f;example.com&#x2f;mike&#x2f;diaspora'>",
+                "<font color=FF00FF>Diaspora</font></a>] ",
+                "<a href='http:&#x2f;&#x2f;example.com&#x2f;root'><font color='909090'>root</font></a> ",
+                "opened <a href='http:&#x2f;&#x2f;example.com&#x2f;mike&#x2f;diaspora&#x2f;merge_requests&#x2f;1'>",
+                "PR#1</a>: MS-Viewport",
+            ),
+        );
+    }
+}
@@ -0,0 +1,85 @@
+use crate::config::StarMilestones;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Last star milestone announced for each repository, so that losing
+/// and regaining stars around a milestone doesn't announce it again.
+#[derive(Debug, Default)]
+pub struct AnnouncedMilestones {
+    last: Mutex<HashMap<String, u32>>,
+}
+
+impl AnnouncedMilestones {
+    /// Records the number of stars of a repository after it was starred,
+    /// returning the milestone to announce if a new one was reached.
+    /// Repositories seen for the first time only announce the milestone
+    /// reached by this very star.
+    pub fn starred(
+        &self,
+        repository: &str,
+        stars: u32,
+        milestones: &StarMilestones,
+    ) -> Option<u32> {
+        let reached = milestones.reached(stars)?;
+        let mut last = self.last.lock().unwrap();
+        let previous = *last
+            .entry(repository.into())
+            .or_insert_with(|| milestones.reached(stars.saturating_sub(1)).unwrap_or(0));
+        if reached > previous {
+            last.insert(repository.into(), reached);
+            Some(reached)
+        } else {
+            None
+        }
+    }
+
+    /// Lists the last milestone announced for each repository.
+    pub fn all(&self) -> HashMap<String, u32> {
+        self.last.lock().unwrap().clone()
+    }
+
+    /// Remembers milestones listed by [`AnnouncedMilestones::all`].
+    pub fn restore(&self, milestones: HashMap<String, u32>) {
+        self.last.lock().unwrap().extend(milestones);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AnnouncedMilestones;
+    use crate::config::StarMilestones;
+
+    #[test]
+    fn test_milestones() {
+        let announced = AnnouncedMilestones::default();
+        let every = StarMilestones::Every(100);
+        assert_eq!(announced.starred("a/b", 99, &every), None);
+        assert_eq!(announced.starred("a/b", 100, &every), Some(100));
+        // Unstarring and starring again doesn't repeat the milestone.
+        assert_eq!(announced.starred("a/b", 100, &every), None);
+        // Missed deliveries don't prevent the next milestone.
+        assert_eq!(announced.starred("a/b", 203, &every), Some(200));
+        assert_eq!(announced.starred("a/b", 204, &every), None);
+    }
+
+    #[test]
+    fn test_first_seen_repository() {
+        let announced = AnnouncedMilestones::default();
+        let at = StarMilestones::At(vec![1000, 2500]);
+        assert_eq!(announced.starred("a/b", 1200, &at), None);
+        assert_eq!(announced.starred("a/b", 2499, &at), None);
+        assert_eq!(announced.starred("a/b", 2500, &at), Some(2500));
+        assert_eq!(announced.starred("c/d", 1000, &at), Some(1000));
+        assert_eq!(announced.starred("e/f", 1, &at), None);
+    }
+
+    #[test]
+    fn test_restore() {
+        let announced = AnnouncedMilestones::default();
+        let every = StarMilestones::Every(100);
+        announced.starred("a/b", 100, &every);
+        let restored = AnnouncedMilestones::default();
+        restored.restore(announced.all());
+        assert_eq!(restored.starred("a/b", 100, &every), None);
+    }
+}
@@ -0,0 +1,256 @@
+use lru::LruCache;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+/// Number of merge commits whose checks are followed at once.
+const MERGE_COMMITS: usize = 100;
+
+/// Change in the state of a workflow worth announcing.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Transition {
+    Failed,
+    /// The workflow succeeded after failing.
+    Recovered,
+}
+
+/// Last known result of each workflow, by repository, workflow and
+/// branch, so that the first success after a failure can be announced.
+/// Kept in memory only, so a restart forgets earlier failures.
+#[derive(Debug, Default)]
+pub struct WorkflowConclusions {
+    failing: Mutex<HashMap<String, bool>>,
+}
+
+impl WorkflowConclusions {
+    /// Records how a workflow run concluded, returning what should be
+    /// announced. Conclusions other than `failure` and `success`, such as
+    /// `cancelled`, don't change the state.
+    pub fn record(&self, key: &str, conclusion: &str) -> Option<Transition> {
+        let mut failing = self.failing.lock().unwrap();
+        match conclusion {
+            "failure" => {
+                failing.insert(key.into(), true);
+                Some(Transition::Failed)
+            }
+            "success" => match failing.insert(key.into(), false) {
+                Some(true) => Some(Transition::Recovered),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// State reported by a CI check, either a commit status or a check
+/// suite.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CheckState {
+    Pending,
+    Success,
+    Failure,
+}
+
+/// Checks reported on the commits of merged pull requests, so that the
+/// merge can be announced along with whether they all passed instead of
+/// announcing each one. GitHub doesn't say which checks are still to
+/// come, so they are considered to have all passed once none reported
+/// for a while.
+///
+/// The announcement of each merge is held here until then.
+pub struct MergeChecks<T> {
+    commits: Mutex<LruCache<String, MergeCommit<T>>>,
+}
+
+struct MergeCommit<T> {
+    announcement: T,
+    pending: HashSet<String>,
+    updates: u64,
+}
+
+/// What a check reported on a merge commit means for its announcement.
+#[derive(Debug, Eq, PartialEq)]
+pub enum MergeCheck<T> {
+    /// More checks may follow, see [`MergeChecks::passed`].
+    Waiting(u64),
+    /// The check failed, so the merge is announced right away.
+    Failed(T),
+}
+
+impl<T> Default for MergeChecks<T> {
+    fn default() -> Self {
+        Self {
+            commits: Mutex::new(LruCache::new(MERGE_COMMITS)),
+        }
+    }
+}
+
+impl<T> MergeChecks<T> {
+    /// Starts following the checks of the commit merging a pull request,
+    /// holding its announcement. When too many merges are followed, the
+    /// announcement of the oldest one is returned to be sent right away.
+    pub fn track(&self, sha: &str, announcement: T) -> Option<T> {
+        let mut commits = self.commits.lock().unwrap();
+        let evicted = if commits.len() == commits.cap() {
+            commits.pop_lru().map(|(_, commit)| commit.announcement)
+        } else {
+            None
+        };
+        commits.put(
+            sha.into(),
+            MergeCommit {
+                announcement,
+                pending: HashSet::new(),
+                updates: 0,
+            },
+        );
+        evicted
+    }
+
+    /// Records a check reported on a commit. A failure stops following
+    /// the commit, returning its announcement.
+    pub fn record(&self, sha: &str, context: &str, state: CheckState) -> Option<MergeCheck<T>> {
+        let mut commits = self.commits.lock().unwrap();
+        let commit = commits.get_mut(&sha.to_owned())?;
+        match state {
+            CheckState::Pending => {
+                commit.pending.insert(context.into());
+            }
+            CheckState::Success => {
+                commit.pending.remove(context);
+            }
+            CheckState::Failure => {
+                let commit = commits.pop(&sha.to_owned())?;
+                return Some(MergeCheck::Failed(commit.announcement));
+            }
+        }
+        commit.updates += 1;
+        Some(MergeCheck::Waiting(commit.updates))
+    }
+
+    /// Checks whether every check of a commit passed, which is when none
+    /// reported since the given update and none is pending. The
+    /// announcement is returned once they passed, so that it's only sent
+    /// once.
+    pub fn passed(&self, sha: &str, update: u64) -> Option<T> {
+        let mut commits = self.commits.lock().unwrap();
+        match commits.peek(&sha.to_owned()) {
+            Some(commit) if commit.updates == update && commit.pending.is_empty() => commits
+                .pop(&sha.to_owned())
+                .map(|commit| commit.announcement),
+            _ => None,
+        }
+    }
+
+    /// Stops following a commit, returning its announcement. With
+    /// `unchecked`, this only happens when no check reported yet.
+    pub fn stop(&self, sha: &str, unchecked: bool) -> Option<T> {
+        let mut commits = self.commits.lock().unwrap();
+        match commits.peek(&sha.to_owned()) {
+            Some(commit) if !unchecked || commit.updates == 0 => commits
+                .pop(&sha.to_owned())
+                .map(|commit| commit.announcement),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        CheckState, MergeCheck, MergeChecks, Transition, WorkflowConclusions, MERGE_COMMITS,
+    };
+
+    #[test]
+    fn test_failure_then_success() {
+        let conclusions = WorkflowConclusions::default();
+        assert_eq!(conclusions.record("a/b CI master", "success"), None);
+        assert_eq!(
+            conclusions.record("a/b CI master", "failure"),
+            Some(Transition::Failed),
+        );
+        assert_eq!(conclusions.record("a/b CI master", "cancelled"), None);
+        assert_eq!(
+            conclusions.record("a/b CI master", "failure"),
+            Some(Transition::Failed),
+        );
+        assert_eq!(
+            conclusions.record("a/b CI master", "success"),
+            Some(Transition::Recovered),
+        );
+        assert_eq!(conclusions.record("a/b CI master", "success"), None);
+    }
+
+    #[test]
+    fn test_workflows_are_tracked_separately() {
+        let conclusions = WorkflowConclusions::default();
+        conclusions.record("a/b CI master", "failure");
+        assert_eq!(conclusions.record("a/b Lint master", "success"), None);
+        assert_eq!(conclusions.record("a/c CI master", "success"), None);
+        assert_eq!(
+            conclusions.record("a/b CI master", "success"),
+            Some(Transition::Recovered),
+        );
+    }
+
+    #[test]
+    fn test_merge_checks() {
+        let checks = MergeChecks::default();
+        assert_eq!(checks.record("abc", "ci", CheckState::Success), None);
+        assert_eq!(checks.track("abc", "merged #12"), None);
+        assert_eq!(
+            checks.record("abc", "ci", CheckState::Pending),
+            Some(MergeCheck::Waiting(1)),
+        );
+        assert_eq!(
+            checks.record("abc", "lint", CheckState::Success),
+            Some(MergeCheck::Waiting(2)),
+        );
+        // A check is still pending.
+        assert_eq!(checks.passed("abc", 2), None);
+        assert_eq!(
+            checks.record("abc", "ci", CheckState::Success),
+            Some(MergeCheck::Waiting(3)),
+        );
+        // Another check reported since.
+        assert_eq!(checks.passed("abc", 2), None);
+        assert_eq!(checks.passed("abc", 3), Some("merged #12"));
+        assert_eq!(checks.passed("abc", 3), None);
+    }
+
+    #[test]
+    fn test_merge_check_failure() {
+        let checks = MergeChecks::default();
+        checks.track("abc", "merged #12");
+        assert_eq!(
+            checks.record("abc", "ci", CheckState::Success),
+            Some(MergeCheck::Waiting(1)),
+        );
+        assert_eq!(
+            checks.record("abc", "lint", CheckState::Failure),
+            Some(MergeCheck::Failed("merged #12")),
+        );
+        assert_eq!(checks.passed("abc", 1), None);
+        assert_eq!(checks.record("abc", "ci", CheckState::Success), None);
+    }
+
+    #[test]
+    fn test_merges_without_checks() {
+        let checks = MergeChecks::default();
+        checks.track("abc", "merged #12");
+        checks.track("def", "merged #13");
+        checks.record("def", "ci", CheckState::Pending);
+        assert_eq!(checks.stop("abc", true), Some("merged #12"));
+        assert_eq!(checks.stop("def", true), None);
+        assert_eq!(checks.stop("def", false), Some("merged #13"));
+        assert_eq!(checks.stop("def", false), None);
+    }
+
+    #[test]
+    fn test_oldest_merge_is_evicted() {
+        let checks = MergeChecks::default();
+        for merge in 0..MERGE_COMMITS {
+            assert_eq!(checks.track(&merge.to_string(), merge), None);
+        }
+        assert_eq!(checks.track("new", MERGE_COMMITS), Some(0));
+    }
+}